@@ -0,0 +1,415 @@
+//! Assembler for the syntax printed by [`Instruction`]'s `Display` impl.
+//!
+//! One statement per line, `;` starts a comment and `name:` defines a label
+//! which may be used anywhere an address is expected. `db 1, 2, 0x3` emits
+//! raw bytes. Numbers are decimal, `0x`/`#` hex or `0b` binary. Instructions
+//! are always placed on an even address.
+
+use core::fmt;
+use std::collections::{BTreeMap, BTreeSet};
+use std::vec::Vec;
+
+use crate::instruction::Instruction;
+
+/// Source line (1-based) of the statement at each address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    lines: BTreeMap<u16, usize>,
+    code: BTreeSet<u16>,
+}
+
+impl SourceMap {
+    /// The line which emitted the byte at `addr`.
+    pub fn line(&self, addr: u16) -> Option<usize> {
+        self.lines.get(&addr).copied()
+    }
+
+    /// The first address emitted by `line`.
+    pub fn addr(&self, line: usize) -> Option<u16> {
+        self.lines
+            .iter()
+            .find_map(|(&addr, &at)| (at == line).then_some(addr))
+    }
+
+    /// `(addr, line)` pairs in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, usize)> + '_ {
+        self.lines.iter().map(|(&addr, &line)| (addr, line))
+    }
+
+    /// `(addr, line)` for the first byte of every instruction, leaving out
+    /// `db` data.
+    pub fn instructions(&self) -> impl Iterator<Item = (u16, usize)> + '_ {
+        self.code.iter().map(|&addr| (addr, self.lines[&addr]))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+    pub map: SourceMap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Mnemonic,
+    Operands,
+    Number,
+    Label,
+    DuplicateLabel,
+    TooLong,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error {
+    pub line: usize,
+    pub kind: ErrorKind,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self.kind {
+            ErrorKind::Mnemonic => "unknown mnemonic",
+            ErrorKind::Operands => "invalid operands",
+            ErrorKind::Number => "number out of range",
+            ErrorKind::Label => "undefined label",
+            ErrorKind::DuplicateLabel => "duplicate label",
+            ErrorKind::TooLong => "program does not fit in ram",
+        };
+
+        write!(f, "line {}: {msg}", self.line)
+    }
+}
+
+impl std::error::Error for Error {}
+
+enum Statement<'a> {
+    Inst(&'a str, Vec<&'a str>),
+    Data(Vec<&'a str>),
+}
+
+/// Split a line into its labels and statement.
+fn parse_line(line: &str) -> (Vec<&str>, Option<Statement<'_>>) {
+    let mut rest = line.split(';').next().unwrap_or_default().trim();
+    let mut labels = Vec::new();
+
+    while let Some((label, tail)) = rest.split_once(':') {
+        labels.push(label.trim());
+        rest = tail.trim();
+    }
+
+    if rest.is_empty() {
+        return (labels, None);
+    }
+
+    let (mnemonic, operands) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    let operands = operands
+        .split(',')
+        .map(str::trim)
+        .filter(|op| !op.is_empty())
+        .collect();
+
+    if mnemonic.eq_ignore_ascii_case("db") {
+        (labels, Some(Statement::Data(operands)))
+    } else {
+        (labels, Some(Statement::Inst(mnemonic, operands)))
+    }
+}
+
+/// Assemble `source` to be loaded at `0x200`.
+pub fn assemble(source: &str) -> Result<Assembly, Error> {
+    assemble_at(0x200, source)
+}
+
+pub fn assemble_at(origin: u16, source: &str) -> Result<Assembly, Error> {
+    let mut labels = BTreeMap::new();
+    let mut pending = Vec::new();
+    let mut addr = origin as usize;
+
+    for (idx, line) in source.lines().enumerate() {
+        let err = |kind| Error {
+            line: idx + 1,
+            kind,
+        };
+        let (names, stmt) = parse_line(line);
+
+        if let Some(Statement::Inst(..)) = stmt {
+            addr += addr % 2;
+        }
+
+        // A label on a line of its own belongs to the next statement, and
+        // so to any padding that statement needs.
+        pending.extend(names.into_iter().map(|name| (idx, name)));
+        if stmt.is_some() {
+            bind(&mut labels, &mut pending, addr)?;
+        }
+
+        addr += match stmt {
+            Some(Statement::Inst(..)) => 2,
+            Some(Statement::Data(bytes)) => bytes.len(),
+            None => 0,
+        };
+
+        if addr > 0x1000 {
+            return Err(err(ErrorKind::TooLong));
+        }
+    }
+    bind(&mut labels, &mut pending, addr)?;
+
+    let mut bytes = Vec::new();
+    let mut map = SourceMap::default();
+
+    for (idx, line) in source.lines().enumerate() {
+        let err = |kind| Error {
+            line: idx + 1,
+            kind,
+        };
+
+        let emitted: Vec<u8> = match parse_line(line).1 {
+            Some(Statement::Inst(mnemonic, operands)) => {
+                if bytes.len() % 2 == 1 {
+                    bytes.push(0);
+                }
+
+                map.code.insert(origin + bytes.len() as u16);
+
+                parse(mnemonic, &operands, |label| labels.get(label).copied())
+                    .map_err(err)?
+                    .encode()
+                    .to_be_bytes()
+                    .to_vec()
+            }
+            Some(Statement::Data(data)) => data
+                .iter()
+                .map(|byte| match number(byte) {
+                    Some(byte) => u8::try_from(byte).map_err(|_| err(ErrorKind::Number)),
+                    None => Err(err(ErrorKind::Operands)),
+                })
+                .collect::<Result<_, _>>()?,
+            None => continue,
+        };
+
+        for offset in 0..emitted.len() {
+            map.lines
+                .insert(origin + (bytes.len() + offset) as u16, idx + 1);
+        }
+
+        bytes.extend(emitted);
+    }
+
+    Ok(Assembly { origin, bytes, map })
+}
+
+/// Bind the labels waiting for a statement to `addr`.
+fn bind<'a>(
+    labels: &mut BTreeMap<&'a str, u16>,
+    pending: &mut Vec<(usize, &'a str)>,
+    addr: usize,
+) -> Result<(), Error> {
+    for (idx, name) in pending.drain(..) {
+        if labels.insert(name, addr as u16).is_some() {
+            return Err(Error {
+                line: idx + 1,
+                kind: ErrorKind::DuplicateLabel,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Parse a number in decimal, `0x`/`#` hex or `0b` binary.
+pub(crate) fn number(text: &str) -> Option<u16> {
+    let lower = text.to_ascii_lowercase();
+
+    if let Some(hex) = lower.strip_prefix("0x").or(lower.strip_prefix('#')) {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        u16::from_str_radix(bin, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+fn register(text: &str) -> Option<u8> {
+    match text.as_bytes() {
+        [b'v' | b'V', digit] => (*digit as char).to_digit(16).map(|reg| reg as u8),
+        _ => None,
+    }
+}
+
+/// Parse a single instruction, resolving labels with `label`.
+pub fn parse<F>(mnemonic: &str, operands: &[&str], label: F) -> Result<Instruction, ErrorKind>
+where
+    F: Fn(&str) -> Option<u16>,
+{
+    use Instruction::*;
+
+    enum Op {
+        V(u8),
+        N(u16),
+        I,
+        IndI,
+        Dt,
+        St,
+        K,
+        F,
+        B,
+    }
+
+    let mut ops = Vec::with_capacity(operands.len());
+    for &text in operands {
+        ops.push(match text.to_ascii_uppercase().as_str() {
+            "I" => Op::I,
+            "[I]" => Op::IndI,
+            "DT" => Op::Dt,
+            "ST" => Op::St,
+            "K" => Op::K,
+            "F" => Op::F,
+            "B" => Op::B,
+            _ => match register(text) {
+                Some(reg) => Op::V(reg),
+                None => match number(text) {
+                    Some(n) => Op::N(n),
+                    None if text.starts_with(|c: char| c.is_ascii_digit() || c == '#') => {
+                        return Err(ErrorKind::Number)
+                    }
+                    None => Op::N(label(text).ok_or(ErrorKind::Label)?),
+                },
+            },
+        });
+    }
+
+    let addr = |n: u16| (n <= 0xFFF).then_some(n).ok_or(ErrorKind::Number);
+    let byte = |n: u16| u8::try_from(n).map_err(|_| ErrorKind::Number);
+
+    let inst = match (mnemonic.to_ascii_uppercase().as_str(), ops.as_slice()) {
+        ("CLS", []) => Cls,
+        ("RET", []) => Ret,
+        ("LOW", []) => Low,
+        ("HIGH", []) => High,
+        ("JP", [Op::N(n)]) => Jp(addr(*n)?),
+        ("JP", [Op::V(0), Op::N(n)]) => Jp0(addr(*n)?),
+        ("CALL", [Op::N(n)]) => Call(addr(*n)?),
+        ("SE", [Op::V(x), Op::N(n)]) => Se(*x, byte(*n)?),
+        ("SE", [Op::V(x), Op::V(y)]) => Sev(*x, *y),
+        ("SNE", [Op::V(x), Op::N(n)]) => Sne(*x, byte(*n)?),
+        ("SNE", [Op::V(x), Op::V(y)]) => Snev(*x, *y),
+        ("LD", [Op::V(x), Op::N(n)]) => Ld(*x, byte(*n)?),
+        ("LD", [Op::V(x), Op::V(y)]) => Ldv(*x, *y),
+        ("LD", [Op::I, Op::N(n)]) => Ldi(addr(*n)?),
+        ("LD", [Op::V(x), Op::Dt]) => Lddtv(*x),
+        ("LD", [Op::V(x), Op::K]) => Ldkey(*x),
+        ("LD", [Op::Dt, Op::V(x)]) => Lddt(*x),
+        ("LD", [Op::St, Op::V(x)]) => Ldst(*x),
+        ("LD", [Op::F, Op::V(x)]) => Sprite(*x),
+        ("LD", [Op::B, Op::V(x)]) => Bcd(*x),
+        ("LD", [Op::IndI, Op::V(x)]) => Sviv(*x),
+        ("LD", [Op::V(x), Op::IndI]) => Ldiv(*x),
+        ("ADD", [Op::V(x), Op::N(n)]) => Add(*x, byte(*n)?),
+        ("ADD", [Op::V(x), Op::V(y)]) => Addv(*x, *y),
+        ("ADD", [Op::I, Op::V(x)]) => Addi(*x),
+        ("OR", [Op::V(x), Op::V(y)]) => Or(*x, *y),
+        ("AND", [Op::V(x), Op::V(y)]) => And(*x, *y),
+        ("XOR", [Op::V(x), Op::V(y)]) => Xor(*x, *y),
+        ("SUB", [Op::V(x), Op::V(y)]) => Sub(*x, *y),
+        ("SUBN", [Op::V(x), Op::V(y)]) => Subn(*x, *y),
+        ("SHR", [Op::V(x)] | [Op::V(x), Op::V(_)]) => Shr(*x),
+        ("SHL", [Op::V(x)] | [Op::V(x), Op::V(_)]) => Shl(*x),
+        ("RND", [Op::V(x), Op::N(n)]) => Rnd(*x, byte(*n)?),
+        ("DRW", [Op::V(x), Op::V(y), Op::N(n)]) if *n < 16 => Drw(*x, *y, *n as u8),
+        ("SKP", [Op::V(x)]) => Skp(*x),
+        ("SKNP", [Op::V(x)]) => Sknp(*x),
+        (
+            "CLS" | "RET" | "LOW" | "HIGH" | "JP" | "CALL" | "SE" | "SNE" | "LD" | "ADD" | "OR"
+            | "AND" | "XOR" | "SUB" | "SUBN" | "SHR" | "SHL" | "RND" | "DRW" | "SKP" | "SKNP",
+            _,
+        ) => return Err(ErrorKind::Operands),
+        _ => return Err(ErrorKind::Mnemonic),
+    };
+
+    Ok(inst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+
+    #[test]
+    fn disassembly_round_trip() {
+        for opcode in 0..=u16::MAX {
+            if let Some(inst) = Instruction::decode(opcode) {
+                let text = inst.to_string();
+                let (mnemonic, operands) = text.split_once(' ').unwrap_or((&text, ""));
+                let operands: Vec<_> = operands
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|op| !op.is_empty())
+                    .collect();
+
+                assert_eq!(parse(mnemonic, &operands, |_| None), Ok(inst), "{text}");
+            }
+        }
+    }
+
+    #[test]
+    fn assemble() {
+        let source = "\
+            ; count to ten
+            start:  ld v0, 0
+            loop:   add v0, 1
+                    se v0, 10
+                    jp loop
+            data:   db 1, 2, #3
+                    ld i, data
+                    call start";
+
+        let asm = super::assemble(source).unwrap();
+
+        assert_eq!(
+            asm.bytes,
+            [0x60, 0x00, 0x70, 0x01, 0x30, 0x0A, 0x12, 0x02, 1, 2, 3, 0, 0xA2, 0x08, 0x22, 0x00]
+        );
+
+        assert_eq!(asm.map.line(0x200), Some(2));
+        assert_eq!(asm.map.line(0x209), Some(6));
+        assert_eq!(asm.map.line(0x20B), None);
+        assert_eq!(asm.map.addr(7), Some(0x20C));
+        assert_eq!(asm.map.addr(1), None);
+
+        let code: Vec<_> = asm.map.instructions().collect();
+        assert_eq!(
+            code,
+            [
+                (0x200, 2),
+                (0x202, 3),
+                (0x204, 4),
+                (0x206, 5),
+                (0x20C, 7),
+                (0x20E, 8)
+            ]
+        );
+    }
+
+    #[test]
+    fn label_after_data() {
+        let asm = super::assemble("jp start\ndb 1\nstart:\ncls\nend:").unwrap();
+
+        assert_eq!(asm.bytes, [0x12, 0x04, 1, 0, 0x00, 0xE0]);
+        assert_eq!(asm.map.addr(4), Some(0x204));
+    }
+
+    #[test]
+    fn errors() {
+        let err = |source| super::assemble(source).unwrap_err();
+
+        assert_eq!(err("cls\nfoo v0").line, 2);
+        assert_eq!(err("foo v0").kind, ErrorKind::Mnemonic);
+        assert_eq!(err("ld v0").kind, ErrorKind::Operands);
+        assert_eq!(err("ld v0, 256").kind, ErrorKind::Number);
+        assert_eq!(err("jp nowhere").kind, ErrorKind::Label);
+        assert_eq!(err("a: cls\na: cls").kind, ErrorKind::DuplicateLabel);
+        assert_eq!(err("a:\ncls\na:").line, 3);
+        assert_eq!(err("db 1, x").kind, ErrorKind::Operands);
+    }
+}
@@ -0,0 +1,10 @@
+//! Debug Adapter Protocol server for CHIP-8 programs, speaking over stdio.
+
+use std::io;
+
+use chip8::debug::dap::Session;
+
+fn main() -> io::Result<()> {
+    let stdin = io::stdin();
+    Session::new(stdin.lock(), io::stdout().lock()).run()
+}
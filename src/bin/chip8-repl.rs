@@ -0,0 +1,23 @@
+//! Interactive monitor for CHIP-8 ROMs.
+
+use std::{env, fs, io, process};
+
+use chip8::debug::repl::Repl;
+
+fn main() -> io::Result<()> {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("usage: chip8-repl <rom>");
+        process::exit(2);
+    };
+
+    let rom = fs::read(&path)?;
+    let mut repl = match Repl::new(&rom) {
+        Ok(repl) => repl,
+        Err(err) => {
+            eprintln!("{path}: {err:?}");
+            process::exit(1);
+        }
+    };
+
+    repl.run(io::stdin().lock(), io::stdout().lock())
+}
@@ -0,0 +1,470 @@
+use core::fmt;
+use core::str::FromStr;
+
+use crate::vm::mem::Mem;
+
+/// Longest expression accepted, counted in numbers, names and operators.
+pub const MAX_TERMS: usize = 32;
+
+/// Deepest nesting of brackets and unary operators accepted.
+pub const MAX_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Var {
+    V(u8),
+    I,
+    Pc,
+    Dt,
+    St,
+    Sp,
+    Hits,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Sub,
+}
+
+impl BinOp {
+    /// Operators by precedence, loosest first. Longer tokens come before
+    /// their prefixes.
+    const TOKENS: [(&'static str, BinOp, u8); 13] = [
+        ("||", BinOp::Or, 0),
+        ("&&", BinOp::And, 1),
+        ("==", BinOp::Eq, 2),
+        ("!=", BinOp::Ne, 2),
+        ("<=", BinOp::Le, 2),
+        (">=", BinOp::Ge, 2),
+        ("<", BinOp::Lt, 2),
+        (">", BinOp::Gt, 2),
+        ("|", BinOp::BitOr, 3),
+        ("^", BinOp::BitXor, 4),
+        ("&", BinOp::BitAnd, 5),
+        ("+", BinOp::Add, 6),
+        ("-", BinOp::Sub, 6),
+    ];
+
+    const LEVELS: u8 = 7;
+
+    fn apply(self, lhs: u32, rhs: u32) -> u32 {
+        match self {
+            BinOp::Or => (lhs != 0 || rhs != 0) as u32,
+            BinOp::And => (lhs != 0 && rhs != 0) as u32,
+            BinOp::Eq => (lhs == rhs) as u32,
+            BinOp::Ne => (lhs != rhs) as u32,
+            BinOp::Lt => (lhs < rhs) as u32,
+            BinOp::Le => (lhs <= rhs) as u32,
+            BinOp::Gt => (lhs > rhs) as u32,
+            BinOp::Ge => (lhs >= rhs) as u32,
+            BinOp::BitOr => lhs | rhs,
+            BinOp::BitXor => lhs ^ rhs,
+            BinOp::BitAnd => lhs & rhs,
+            BinOp::Add => lhs.wrapping_add(rhs),
+            BinOp::Sub => lhs.wrapping_sub(rhs),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Num(u32),
+    Var(Var),
+    /// Replace the address on top of the stack with the byte stored there.
+    Load,
+    Not,
+    Neg,
+    Bin(BinOp),
+}
+
+/// Why an expression could not be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    Unexpected,
+    End,
+    Number,
+    Name,
+    Close(char),
+    TooLong,
+    TooDeep,
+}
+
+/// Parse error at byte offset `pos` of the expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    pub pos: usize,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ParseErrorKind::Unexpected => write!(f, "unexpected character at {}", self.pos),
+            ParseErrorKind::End => write!(f, "unexpected end of expression at {}", self.pos),
+            ParseErrorKind::Number => write!(f, "invalid number at {}", self.pos),
+            ParseErrorKind::Name => write!(f, "unknown name at {}", self.pos),
+            ParseErrorKind::Close(close) => write!(f, "expected '{close}' at {}", self.pos),
+            ParseErrorKind::TooLong => write!(f, "more than {MAX_TERMS} terms at {}", self.pos),
+            ParseErrorKind::TooDeep => {
+                write!(f, "nested more than {MAX_DEPTH} deep at {}", self.pos)
+            }
+        }
+    }
+}
+
+/// A boolean expression over machine state, used to make breakpoints and
+/// watchpoints conditional.
+///
+/// Operands are numbers (decimal, `0x` hex or `0b` binary), the registers
+/// `v0`-`vf`, `i`, `pc`, `dt`, `st` and `sp` (stack depth), `hits` (times
+/// the breakpoint has been reached, including this one) and `[addr]` for the
+/// byte at `addr`. Operators, loosest first, are `||`, `&&`, comparisons,
+/// `|`, `^`, `&`, `+` and `-`, with unary `!` and `-` and parentheses.
+///
+/// ```text
+/// v3 == 0x10 && i > 0x300 && [0x3F0] != 0 && hits > 199
+/// ```
+///
+/// The expression is stored in postfix order in a fixed size array, so
+/// evaluating it never allocates.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    ops: [Op; MAX_TERMS],
+    len: usize,
+}
+
+impl Condition {
+    pub fn parse(src: &str) -> Result<Self, ParseError> {
+        let mut parser = Parser {
+            src: src.as_bytes(),
+            pos: 0,
+            depth: 0,
+            cond: Condition {
+                ops: [Op::Num(0); MAX_TERMS],
+                len: 0,
+            },
+        };
+
+        parser.binary(0)?;
+        match parser.peek() {
+            None => Ok(parser.cond),
+            Some(_) => Err(parser.error(ParseErrorKind::Unexpected)),
+        }
+    }
+
+    /// Evaluate against `mem`, anything other than zero is true.
+    pub fn eval(&self, mem: &Mem, hits: u32) -> u32 {
+        let mut stack = [0u32; MAX_TERMS];
+        let mut sp = 0;
+
+        for op in &self.ops[..self.len] {
+            let value = match *op {
+                Op::Num(num) => num,
+                Op::Var(var) => match var {
+                    Var::V(reg) => mem.reg.as_array()[reg as usize] as u32,
+                    Var::I => mem.i as u32,
+                    Var::Pc => mem.pc as u32,
+                    Var::Dt => mem.dt as u32,
+                    Var::St => mem.st as u32,
+                    Var::Sp => mem.stack.depth() as u32,
+                    Var::Hits => hits,
+                },
+                Op::Load => {
+                    sp -= 1;
+                    u16::try_from(stack[sp])
+                        .ok()
+                        .and_then(|addr| mem.ram.read_byte(addr).ok())
+                        .unwrap_or(0) as u32
+                }
+                Op::Not => {
+                    sp -= 1;
+                    (stack[sp] == 0) as u32
+                }
+                Op::Neg => {
+                    sp -= 1;
+                    stack[sp].wrapping_neg()
+                }
+                Op::Bin(op) => {
+                    sp -= 2;
+                    op.apply(stack[sp], stack[sp + 1])
+                }
+            };
+
+            stack[sp] = value;
+            sp += 1;
+        }
+
+        stack[0]
+    }
+
+    pub fn test(&self, mem: &Mem, hits: u32) -> bool {
+        self.eval(mem, hits) != 0
+    }
+}
+
+impl FromStr for Condition {
+    type Err = ParseError;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        Self::parse(src)
+    }
+}
+
+impl fmt::Debug for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(&self.ops[..self.len]).finish()
+    }
+}
+
+/// Recursive descent parser emitting postfix operations.
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+    depth: usize,
+    cond: Condition,
+}
+
+impl Parser<'_> {
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            pos: self.pos,
+            kind,
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        while self.src.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+        self.src.get(self.pos).copied()
+    }
+
+    fn emit(&mut self, op: Op) -> Result<(), ParseError> {
+        let slot = self.cond.ops.get_mut(self.cond.len).ok_or(ParseError {
+            pos: self.pos,
+            kind: ParseErrorKind::TooLong,
+        })?;
+
+        *slot = op;
+        self.cond.len += 1;
+        Ok(())
+    }
+
+    /// Parse with one more level of nesting, so input can't overflow the
+    /// call stack.
+    fn nested<F>(&mut self, parse: F) -> Result<(), ParseError>
+    where
+        F: FnOnce(&mut Self) -> Result<(), ParseError>,
+    {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error(ParseErrorKind::TooDeep));
+        }
+
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn binary(&mut self, level: u8) -> Result<(), ParseError> {
+        if level == BinOp::LEVELS {
+            return self.unary();
+        }
+
+        self.binary(level + 1)?;
+        loop {
+            self.peek();
+            let rest = &self.src[self.pos..];
+            let Some(&(token, op, _)) = BinOp::TOKENS
+                .iter()
+                .find(|(token, _, at)| *at == level && rest.starts_with(token.as_bytes()))
+            else {
+                return Ok(());
+            };
+
+            // `|` and `&` must not match the first half of `||` and `&&`
+            if token.len() == 1 && rest.get(1) == Some(&rest[0]) {
+                return Ok(());
+            }
+
+            self.pos += token.len();
+            self.binary(level + 1)?;
+            self.emit(Op::Bin(op))?;
+        }
+    }
+
+    fn unary(&mut self) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(b'!') => {
+                self.pos += 1;
+                self.nested(Self::unary)?;
+                self.emit(Op::Not)
+            }
+            Some(b'-') => {
+                self.pos += 1;
+                self.nested(Self::unary)?;
+                self.emit(Op::Neg)
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn close(&mut self, close: char) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(byte) if byte as char == close => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(self.error(ParseErrorKind::Close(close))),
+        }
+    }
+
+    fn primary(&mut self) -> Result<(), ParseError> {
+        match self.peek() {
+            None => Err(self.error(ParseErrorKind::End)),
+            Some(b'(') => {
+                self.pos += 1;
+                self.nested(|parser| parser.binary(0))?;
+                self.close(')')
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                self.nested(|parser| parser.binary(0))?;
+                self.close(']')?;
+                self.emit(Op::Load)
+            }
+            Some(byte) if byte.is_ascii_alphanumeric() => {
+                let start = self.pos;
+                let len = self.src[start..]
+                    .iter()
+                    .take_while(|byte| byte.is_ascii_alphanumeric() || **byte == b'_')
+                    .count();
+
+                let word = &self.src[start..start + len];
+                let op = if byte.is_ascii_digit() {
+                    Op::Num(number(word).ok_or(self.error(ParseErrorKind::Number))?)
+                } else {
+                    Op::Var(name(word).ok_or(self.error(ParseErrorKind::Name))?)
+                };
+
+                self.pos += len;
+                self.emit(op)
+            }
+            Some(_) => Err(self.error(ParseErrorKind::Unexpected)),
+        }
+    }
+}
+
+fn number(word: &[u8]) -> Option<u32> {
+    let word = core::str::from_utf8(word).ok()?;
+    let (digits, radix) = match word.get(..2) {
+        Some("0x" | "0X") => (&word[2..], 16),
+        Some("0b" | "0B") => (&word[2..], 2),
+        _ => (word, 10),
+    };
+
+    u32::from_str_radix(digits, radix).ok()
+}
+
+fn name(word: &[u8]) -> Option<Var> {
+    let mut lower = [0; 4];
+    let lower = lower.get_mut(..word.len())?;
+    lower.copy_from_slice(word);
+    lower.make_ascii_lowercase();
+
+    Some(match &*lower {
+        b"i" => Var::I,
+        b"pc" => Var::Pc,
+        b"dt" => Var::Dt,
+        b"st" => Var::St,
+        b"sp" => Var::Sp,
+        b"hits" => Var::Hits,
+        [b'v', digit] => Var::V((*digit as char).to_digit(16)? as u8),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    fn eval(src: &str, mem: &Mem, hits: u32) -> u32 {
+        Condition::parse(src).unwrap().eval(mem, hits)
+    }
+
+    #[test]
+    fn evaluate() {
+        let mut mem = Mem::default();
+        mem.reg.set(3, 0x10).unwrap();
+        mem.reg.set(0xA, 7).unwrap();
+        mem.i = 0x310;
+        mem.ram.write_byte(0x3F0, 5).unwrap();
+
+        let cond = "v3 == 0x10 && i > 0x300 && [0x3F0] != 0 && hits > 199";
+        assert_eq!(eval(cond, &mem, 199), 0);
+        assert_eq!(eval(cond, &mem, 200), 1);
+
+        assert_eq!(eval("1 + 2 - 4", &mem, 0), u32::MAX);
+        assert_eq!(eval("-1 + 2", &mem, 0), 1);
+        assert_eq!(eval("1 + 2 == 3", &mem, 0), 1);
+        assert_eq!(eval("va & 3 | 8 ^ 1", &mem, 0), 0xB);
+        assert_eq!(eval("(va & 3 | 8) ^ 1", &mem, 0), 0xA);
+        assert_eq!(eval("!(VA >= 7) || 0", &mem, 0), 0);
+        assert_eq!(eval("[i - 0x10 + 0xF0] + 0", &mem, 0), 5);
+        assert_eq!(eval("[0x10000]", &mem, 0), 0);
+        assert_eq!(eval("pc + sp + dt + st", &mem, 0), 0);
+        assert_eq!(eval("0b101 + 0XF", &mem, 0), 20);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |src| Condition::parse(src).unwrap_err();
+
+        assert_eq!(error("").kind, ParseErrorKind::End);
+        assert_eq!(error("v3 ==").kind, ParseErrorKind::End);
+        assert_eq!(
+            error("v3 = 1"),
+            ParseError {
+                pos: 3,
+                kind: ParseErrorKind::Unexpected
+            }
+        );
+        assert_eq!(error("vg").kind, ParseErrorKind::Name);
+        assert_eq!(error("0xZ").kind, ParseErrorKind::Number);
+        assert_eq!(error("(1 + 2").kind, ParseErrorKind::Close(')'));
+        assert_eq!(error("[1 + 2)").kind, ParseErrorKind::Close(']'));
+        assert_eq!(error("1 2").kind, ParseErrorKind::Unexpected);
+        assert_eq!(
+            error("1+1+1+1+1+1+1+1+1+1+1+1+1+1+1+1+1").kind,
+            ParseErrorKind::TooLong
+        );
+
+        assert!(Condition::parse("((((((((((((((((1))))))))))))))))").is_ok());
+        assert_eq!(
+            error("(((((((((((((((((1)))))))))))))))))"),
+            ParseError {
+                pos: 17,
+                kind: ParseErrorKind::TooDeep
+            }
+        );
+        for src in ["(".repeat(100_000), "!-".repeat(100_000)] {
+            assert_eq!(
+                Condition::parse(&src).unwrap_err().kind,
+                ParseErrorKind::TooDeep
+            );
+        }
+    }
+}
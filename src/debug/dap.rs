@@ -0,0 +1,509 @@
+//! Debug Adapter Protocol server.
+//!
+//! Launches a ROM, or an [`asm`] source file when the program
+//! ends in `.asm`, in a headless [`Chip8`]. Source breakpoints need a source
+//! file; ROMs can use instruction breakpoints instead.
+//!
+//! Breakpoint conditions use the [`Condition`] expression syntax.
+//!
+//! Execution is synchronous, so `continue` runs until a stop or until the
+//! `maxSteps` launch argument (default 1,000,000) is used up.
+
+use std::format;
+use std::io::{self, BufRead, Write};
+use std::string::{String, ToString};
+use std::vec::Vec;
+
+use serde_json::{json, Value};
+
+use super::{Breakpoint, Condition, Debugger, Stop};
+use crate::asm::{self, SourceMap};
+use crate::hal::headless::{HeldKeys, NoDelay, NullBuzzer, NullScreen, XorShift};
+use crate::instruction::Instruction;
+use crate::vm::{self, mem::Load, Chip8};
+
+type Machine = Debugger<NullScreen, HeldKeys, NullBuzzer, XorShift, NoDelay>;
+type MachineError = vm::ChipError<NullScreen, HeldKeys, NullBuzzer, XorShift, NoDelay>;
+
+const REGISTERS_REF: u64 = 1;
+const TIMERS_REF: u64 = 2;
+
+/// A single debug session over a pair of streams, usually stdin and stdout.
+pub struct Session<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+    seq: u64,
+    machine: Option<Machine>,
+    source: Option<(String, SourceMap)>,
+    /// Breakpoint ids owned by `setBreakpoints` and `setInstructionBreakpoints`
+    lines: Vec<usize>,
+    instructions: Vec<usize>,
+    stop_on_entry: bool,
+}
+
+impl<R: BufRead, W: Write> Session<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            input,
+            output,
+            seq: 0,
+            machine: None,
+            source: None,
+            lines: Vec::new(),
+            instructions: Vec::new(),
+            stop_on_entry: false,
+        }
+    }
+
+    pub fn into_output(self) -> W {
+        self.output
+    }
+
+    /// Handle requests until `disconnect` or the end of the input.
+    pub fn run(&mut self) -> io::Result<()> {
+        while let Some(request) = self.read()? {
+            let command = request["command"].as_str().unwrap_or_default().to_string();
+            let args = &request["arguments"];
+
+            let result = match command.as_str() {
+                "disconnect" => {
+                    self.respond(&request, Ok(Value::Null))?;
+                    return Ok(());
+                }
+                "initialize" => Ok(json!({
+                    "supportsConditionalBreakpoints": true,
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsInstructionBreakpoints": true,
+                    "supportsReadMemoryRequest": true,
+                })),
+                "launch" => self.launch(args),
+                "setBreakpoints" => self.set_breakpoints(args),
+                "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+                "configurationDone" | "continue" | "next" | "stepIn" | "stepOut" => self
+                    .machine()
+                    .map(|_| json!({ "allThreadsContinued": true })),
+                "pause" => Ok(Value::Null),
+                "threads" => Ok(json!({ "threads": [{ "id": 1, "name": "chip8" }] })),
+                "stackTrace" => self.stack_trace(),
+                "scopes" => Ok(json!({ "scopes": [
+                    { "name": "Registers", "variablesReference": REGISTERS_REF, "expensive": false },
+                    { "name": "Timers", "variablesReference": TIMERS_REF, "expensive": false },
+                ]})),
+                "variables" => self.variables(args),
+                "readMemory" => self.read_memory(args),
+                _ => Err(format!("unsupported request: {command}")),
+            };
+
+            let ok = result.is_ok();
+            self.respond(&request, result)?;
+
+            match command.as_str() {
+                "launch" if ok => self.event("initialized", Value::Null)?,
+                "configurationDone" if ok && self.stop_on_entry => self.stopped(Ok(None))?,
+                "configurationDone" | "continue" if ok => self.resume(Machine::cont)?,
+                "next" if ok => self.resume(Machine::step_over)?,
+                "stepIn" if ok => self.resume(Machine::step_into)?,
+                "stepOut" if ok => self.resume(Machine::step_out)?,
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn machine(&mut self) -> Result<&mut Machine, String> {
+        self.machine
+            .as_mut()
+            .ok_or_else(|| String::from("no program launched"))
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["program"].as_str().ok_or("missing program")?;
+        let data = std::fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+
+        let rom = if path.ends_with(".asm") {
+            let source = String::from_utf8(data).map_err(|err| format!("{path}: {err}"))?;
+            let asm = asm::assemble(&source).map_err(|err| format!("{path}: {err}"))?;
+            self.source = Some((path.to_string(), asm.map));
+            asm.bytes
+        } else {
+            data
+        };
+
+        let mut chip = Chip8::new(
+            NullScreen,
+            HeldKeys::default(),
+            NullBuzzer::default(),
+            XorShift::default(),
+            NoDelay,
+        );
+
+        chip.state_mut()
+            .ram
+            .load(0x200, &rom[..])
+            .map_err(|err| format!("{path}: {err:?}"))?;
+        chip.init().map_err(|err| format!("{err:?}"))?;
+
+        let mut machine = Debugger::new(chip);
+        machine.set_limit(Some(args["maxSteps"].as_u64().unwrap_or(1_000_000) as usize));
+
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        self.machine = Some(machine);
+        Ok(Value::Null)
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["source"]["path"].as_str().unwrap_or_default();
+        let map = match &self.source {
+            Some((source, map)) if source == path => Some(map),
+            _ => None,
+        };
+
+        // Breakpoints on lines without code, or with only data, move to the next
+        // line which has an instruction
+        let requests: Vec<_> = args["breakpoints"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|bp| Some((bp, bp["line"].as_u64()?)))
+            .map(|(bp, line)| {
+                let target = map.and_then(|map| {
+                    map.instructions()
+                        .filter(|&(_, at)| at as u64 >= line)
+                        .min_by_key(|&(addr, at)| (at, addr))
+                });
+                let breakpoint = breakpoint(bp, target.map_or(0, |(addr, _)| addr));
+                (line, target, breakpoint)
+            })
+            .collect();
+
+        let machine = self.machine.as_mut().ok_or("no program launched")?;
+        for id in core::mem::take(&mut self.lines) {
+            machine.remove_breakpoint(id);
+        }
+
+        let mut results = Vec::new();
+        for (line, target, breakpoint) in requests {
+            let result = match (target, breakpoint) {
+                (None, _) => json!({ "verified": false, "line": line }),
+                (Some(_), Err(message)) => {
+                    json!({ "verified": false, "line": line, "message": message })
+                }
+                (Some((addr, at)), Ok(bp)) => match machine.add_breakpoint(bp) {
+                    Some(id) => {
+                        self.lines.push(id);
+                        json!({
+                            "id": id,
+                            "verified": true,
+                            "line": at,
+                            "instructionReference": format!("0x{addr:03X}"),
+                        })
+                    }
+                    None => json!({ "verified": false, "line": line }),
+                },
+            };
+            results.push(result);
+        }
+
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let requests: Vec<Result<Breakpoint, String>> = args["breakpoints"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|bp| {
+                let base = bp["instructionReference"]
+                    .as_str()
+                    .and_then(parse_addr)
+                    .ok_or("invalid instructionReference")?;
+                let offset = bp["offset"].as_i64().unwrap_or(0) as u16;
+                breakpoint(bp, base.wrapping_add(offset))
+            })
+            .collect();
+
+        let machine = self.machine.as_mut().ok_or("no program launched")?;
+        for id in core::mem::take(&mut self.instructions) {
+            machine.remove_breakpoint(id);
+        }
+
+        let mut results = Vec::new();
+        for request in requests {
+            let result = match request {
+                Err(message) => json!({ "verified": false, "message": message }),
+                Ok(bp) => match machine.add_breakpoint(bp) {
+                    Some(id) => {
+                        self.instructions.push(id);
+                        json!({
+                            "id": id,
+                            "verified": true,
+                            "instructionReference": format!("0x{:03X}", bp.addr),
+                        })
+                    }
+                    None => json!({ "verified": false }),
+                },
+            };
+            results.push(result);
+        }
+
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn frame(&self, id: usize, addr: u16) -> Value {
+        let ram = &self.machine.as_ref().unwrap().state().ram;
+        let name = match ram.read_bytes(addr, 2) {
+            Ok(&[msb, lsb]) => match Instruction::decode(u16::from_be_bytes([msb, lsb])) {
+                Some(inst) => format!("0x{addr:03X}: {inst}"),
+                None => format!("0x{addr:03X}: {msb:02X}{lsb:02X}"),
+            },
+            _ => format!("0x{addr:03X}"),
+        };
+
+        let mut frame = json!({
+            "id": id,
+            "name": name,
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": format!("0x{addr:03X}"),
+        });
+
+        if let Some((path, map)) = &self.source {
+            if let Some(line) = map.line(addr) {
+                frame["line"] = json!(line);
+                frame["column"] = json!(1);
+                frame["source"] = json!({ "path": path });
+            }
+        }
+
+        frame
+    }
+
+    fn stack_trace(&mut self) -> Result<Value, String> {
+        let mem = *self.machine()?.state();
+
+        // Each stack frame holds a return address, show the call site instead
+        let calls = mem.stack.iter().rev().map(|ret| ret.wrapping_sub(2));
+        let frames: Vec<Value> = core::iter::once(mem.pc)
+            .chain(calls)
+            .enumerate()
+            .map(|(id, addr)| self.frame(id, addr))
+            .collect();
+
+        Ok(json!({ "totalFrames": frames.len(), "stackFrames": frames }))
+    }
+
+    fn variables(&mut self, args: &Value) -> Result<Value, String> {
+        let mem = *self.machine()?.state();
+        let var = |name: &str, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+
+        let vars: Vec<Value> = match args["variablesReference"].as_u64() {
+            Some(REGISTERS_REF) => {
+                let mut vars: Vec<Value> = mem
+                    .reg
+                    .as_array()
+                    .iter()
+                    .enumerate()
+                    .map(|(reg, val)| var(&format!("V{reg:X}"), format!("0x{val:02X}")))
+                    .collect();
+
+                let mut i = var("I", format!("0x{:03X}", mem.i));
+                i["memoryReference"] = json!(format!("0x{:03X}", mem.i));
+
+                vars.push(i);
+                vars.push(var("PC", format!("0x{:03X}", mem.pc)));
+                vars.push(var("SP", mem.stack.depth().to_string()));
+                vars
+            }
+            Some(TIMERS_REF) => [("DT", mem.dt), ("ST", mem.st)]
+                .into_iter()
+                .map(|(name, val)| var(name, val.to_string()))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Ok(json!({ "variables": vars }))
+    }
+
+    fn read_memory(&mut self, args: &Value) -> Result<Value, String> {
+        let base = args["memoryReference"]
+            .as_str()
+            .and_then(parse_addr)
+            .ok_or("invalid memoryReference")?;
+
+        let offset = args["offset"].as_i64().unwrap_or(0);
+        let start = (base as i64).saturating_add(offset).clamp(0, 0x1000) as u16;
+        let count = args["count"].as_u64().unwrap_or(0).min(0x1000) as usize;
+        let end = (start as usize + count).min(0x1000) as u16;
+
+        let ram = &self.machine()?.state().ram;
+        let bytes: Vec<u8> = (start..end)
+            .filter_map(|addr| ram.read_byte(addr).ok())
+            .collect();
+
+        Ok(json!({
+            "address": format!("0x{start:03X}"),
+            "data": base64(&bytes),
+            "unreadableBytes": count - bytes.len(),
+        }))
+    }
+
+    fn resume(&mut self, run: fn(&mut Machine) -> Result<Stop, MachineError>) -> io::Result<()> {
+        let result = match self.machine.as_mut() {
+            Some(machine) => run(machine).map(Some).map_err(vm::Error::from),
+            None => return Ok(()),
+        };
+
+        self.stopped(result)
+    }
+
+    /// Send a `stopped` event, `Ok(None)` means stopped on entry.
+    fn stopped(&mut self, result: vm::Result<Option<Stop>>) -> io::Result<()> {
+        let mut body = json!({ "threadId": 1, "allThreadsStopped": true });
+
+        let (reason, text) = match result {
+            Ok(None) => ("entry", None),
+            Ok(Some(stop @ Stop::Breakpoint { id, .. })) => {
+                body["hitBreakpointIds"] = json!([id]);
+                ("breakpoint", Some(stop.to_string()))
+            }
+            Ok(Some(stop @ Stop::Opcode { .. })) => ("breakpoint", Some(stop.to_string())),
+            Ok(Some(stop @ (Stop::Watchpoint { .. } | Stop::Register { .. }))) => {
+                ("data breakpoint", Some(stop.to_string()))
+            }
+            Ok(Some(Stop::Limit)) => ("pause", Some(Stop::Limit.to_string())),
+            Ok(Some(_)) => ("step", None),
+            Err(err) => ("exception", Some(format!("{err:?}"))),
+        };
+
+        body["reason"] = json!(reason);
+        if let Some(text) = text {
+            body["description"] = json!(text.clone());
+            body["text"] = json!(text);
+        }
+
+        self.event("stopped", body)
+    }
+
+    fn read(&mut self) -> io::Result<Option<Value>> {
+        let mut len = None;
+
+        loop {
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("Content-Length") {
+                    len = value.trim().parse::<usize>().ok();
+                }
+            }
+        }
+
+        let len = len
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+        let mut body = std::vec![0; len];
+        self.input.read_exact(&mut body)?;
+
+        serde_json::from_slice(&body)
+            .map(Some)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+
+        let body = message.to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+        self.output.flush()
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+
+        match result {
+            Ok(Value::Null) => (),
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+
+        self.send(message)
+    }
+}
+
+/// Build a breakpoint at `addr` with the request's `condition`, if any.
+fn breakpoint(request: &Value, addr: u16) -> Result<Breakpoint, String> {
+    let mut bp = Breakpoint::new(addr);
+    if let Some(condition) = request["condition"]
+        .as_str()
+        .filter(|cond| !cond.is_empty())
+    {
+        let condition = Condition::parse(condition).map_err(|err| format!("{err}"))?;
+        bp = bp.when(condition);
+    }
+
+    Ok(bp)
+}
+
+fn parse_addr(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let word = chunk.iter().enumerate().fold(0u32, |word, (idx, &byte)| {
+            word | (byte as u32) << (16 - idx * 8)
+        });
+
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                out.push(ALPHABET[(word >> (18 - idx * 6)) as usize & 0x3F] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::base64;
+
+    #[test]
+    fn encode_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(&[0xFF, 0xEE, 0xDD, 0xCC]), "/+7dzA==");
+    }
+}
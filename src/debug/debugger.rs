@@ -0,0 +1,856 @@
+use core::fmt;
+
+use super::Condition;
+use crate::hal::{Buzzer, Delay, KeyState, Rng, Screen};
+use crate::instruction::Instruction;
+use crate::vm::mem::{self, Mem};
+use crate::vm::observer::{Access, AccessKind, Cpu, Exec, Observer};
+use crate::vm::{Chip8, ChipError};
+
+type Result<T, S, K, B, R, D> = core::result::Result<T, ChipError<S, K, B, R, D>>;
+
+pub const MAX_BREAKPOINTS: usize = 16;
+pub const MAX_OPCODES: usize = 8;
+pub const MAX_WATCHPOINTS: usize = 8;
+pub const MAX_REGISTERS: usize = 8;
+
+/// Stop before executing the instruction at `addr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    /// Ignore the breakpoint until it has been reached this many times.
+    pub after: u32,
+    pub hits: u32,
+    /// Only stop when this also holds.
+    pub condition: Option<Condition>,
+}
+
+impl Breakpoint {
+    pub fn new(addr: u16) -> Self {
+        Self {
+            addr,
+            after: 1,
+            hits: 0,
+            condition: None,
+        }
+    }
+
+    /// Only stop once the breakpoint has been reached `hits` times.
+    pub fn after(self, hits: u32) -> Self {
+        Self {
+            after: hits.max(1),
+            ..self
+        }
+    }
+
+    /// Only stop when `condition` holds, evaluated before the instruction.
+    pub fn when(self, condition: Condition) -> Self {
+        Self {
+            condition: Some(condition),
+            ..self
+        }
+    }
+}
+
+/// Stop before executing any opcode where `opcode & mask == value`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodeMatch {
+    pub mask: u16,
+    pub value: u16,
+}
+
+impl OpcodeMatch {
+    /// Match one exact opcode.
+    pub fn exact(opcode: u16) -> Self {
+        Self {
+            mask: 0xFFFF,
+            value: opcode,
+        }
+    }
+
+    /// Match every instruction of the same kind as `inst`, regardless of its
+    /// operands, e.g. `OpcodeMatch::class(Instruction::Drw(0, 0, 0))`.
+    pub fn class(inst: Instruction) -> Self {
+        let mask = inst.mask();
+        Self {
+            mask,
+            value: inst.encode() & mask,
+        }
+    }
+
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+/// Stop after an instruction reads or writes `len` bytes from `addr`.
+/// Instruction fetches are not watched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: u16,
+    pub len: u16,
+    pub read: bool,
+    pub write: bool,
+    pub hits: u32,
+    /// Only stop when this also holds, evaluated after the instruction.
+    pub condition: Option<Condition>,
+}
+
+impl Watchpoint {
+    fn new(addr: u16, len: u16, read: bool, write: bool) -> Self {
+        Self {
+            addr,
+            len,
+            read,
+            write,
+            hits: 0,
+            condition: None,
+        }
+    }
+
+    pub fn read(addr: u16, len: u16) -> Self {
+        Self::new(addr, len, true, false)
+    }
+
+    pub fn write(addr: u16, len: u16) -> Self {
+        Self::new(addr, len, false, true)
+    }
+
+    pub fn access(addr: u16, len: u16) -> Self {
+        Self::new(addr, len, true, true)
+    }
+
+    pub fn when(self, condition: Condition) -> Self {
+        Self {
+            condition: Some(condition),
+            ..self
+        }
+    }
+
+    pub fn matches(&self, access: &Access) -> bool {
+        let kind = match access.kind {
+            AccessKind::Fetch => false,
+            kind if kind.is_write() => self.write,
+            _ => self.read,
+        };
+
+        kind && access.addr >= self.addr && access.addr - self.addr < self.len
+    }
+}
+
+/// A register which can be watched for changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+impl Register {
+    pub fn read(&self, cpu: &Cpu) -> u16 {
+        match *self {
+            Register::V(reg) => cpu.v[reg as usize & 0xF] as u16,
+            Register::I => cpu.i,
+            Register::Pc => cpu.pc,
+            Register::Sp => cpu.sp as u16,
+            Register::Dt => cpu.dt as u16,
+            Register::St => cpu.st as u16,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(reg) => write!(f, "V{:X}", reg & 0xF),
+            Register::I => f.write_str("I"),
+            Register::Pc => f.write_str("PC"),
+            Register::Sp => f.write_str("SP"),
+            Register::Dt => f.write_str("DT"),
+            Register::St => f.write_str("ST"),
+        }
+    }
+}
+
+/// Why execution stopped. Ids are those returned when the breakpoint or
+/// watchpoint was added.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// A single instruction was executed.
+    Step,
+    Breakpoint {
+        id: usize,
+        addr: u16,
+        hits: u32,
+    },
+    Opcode {
+        id: usize,
+        pc: u16,
+        opcode: u16,
+    },
+    Watchpoint {
+        id: usize,
+        access: Access,
+    },
+    Register {
+        id: usize,
+        reg: Register,
+        old: u16,
+        new: u16,
+    },
+    /// The address given to [`Debugger::run_to`] was reached.
+    Reached(u16),
+    /// [`Debugger::step_out`] returned from the current subroutine.
+    Returned(u16),
+    /// The step limit was reached.
+    Limit,
+    /// Reverse execution reached the start of the recording.
+    Start,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Stop::Step => f.write_str("step"),
+            Stop::Breakpoint { id, addr, hits } => {
+                write!(f, "breakpoint {id} at 0x{addr:03X} (hit {hits})")
+            }
+            Stop::Opcode { id, pc, opcode } => {
+                write!(f, "opcode breakpoint {id}: {opcode:04X} at 0x{pc:03X}")
+            }
+            Stop::Watchpoint { id, access } => {
+                let dir = if access.kind.is_write() {
+                    "write"
+                } else {
+                    "read"
+                };
+                write!(
+                    f,
+                    "watchpoint {id}: {dir} 0x{:02X} at 0x{:03X} by 0x{:03X}",
+                    access.value, access.addr, access.pc
+                )
+            }
+            Stop::Register { id, reg, old, new } => {
+                write!(f, "watchpoint {id}: {reg} changed 0x{old:X} -> 0x{new:X}")
+            }
+            Stop::Reached(addr) => write!(f, "reached 0x{addr:03X}"),
+            Stop::Returned(addr) => write!(f, "returned to 0x{addr:03X}"),
+            Stop::Limit => f.write_str("step limit reached"),
+            Stop::Start => f.write_str("reached start of recording"),
+        }
+    }
+}
+
+/// Observer installed by the [`Debugger`] to catch memory watchpoints. Events
+/// are forwarded to the wrapped observer.
+#[derive(Debug, Clone)]
+pub struct Monitor<O> {
+    inner: O,
+    watchpoints: [Option<Watchpoint>; MAX_WATCHPOINTS],
+    /// First matching access to each watchpoint during the current step
+    hits: [Option<Access>; MAX_WATCHPOINTS],
+    /// The current step only polled for a key in `LD Vx, K`
+    waited: bool,
+}
+
+impl<O: Observer> Monitor<O> {
+    fn new(inner: O) -> Self {
+        Self {
+            inner,
+            watchpoints: [None; MAX_WATCHPOINTS],
+            hits: [None; MAX_WATCHPOINTS],
+            waited: false,
+        }
+    }
+
+    pub fn inner(&self) -> &O {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut O {
+        &mut self.inner
+    }
+}
+
+impl<O: Observer> Observer for Monitor<O> {
+    fn before(&mut self, exec: &Exec) {
+        self.inner.before(exec)
+    }
+
+    fn after(&mut self, exec: &Exec, after: &Cpu) {
+        self.inner.after(exec, after)
+    }
+
+    fn key_wait(&mut self, pc: u16, us: u32) {
+        self.waited = true;
+        self.inner.key_wait(pc, us)
+    }
+
+    fn access(&mut self, access: &Access) {
+        self.inner.access(access);
+
+        for (watch, hit) in self.watchpoints.iter().zip(&mut self.hits) {
+            if hit.is_none() && watch.is_some_and(|watch| watch.matches(access)) {
+                *hit = Some(*access);
+            }
+        }
+    }
+}
+
+/// Breakpoints, watchpoints and stepping on top of [`Chip8::step`].
+///
+/// Timers are not advanced by the debugger, a host which wants them to run
+/// should set a step limit and update them between calls.
+pub struct Debugger<S, K, B, R, D, O = ()>
+where
+    S: Screen,
+    K: KeyState,
+    B: Buzzer,
+    R: Rng,
+    D: Delay,
+    O: Observer,
+{
+    chip: Chip8<S, K, B, R, D, Monitor<O>>,
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    opcodes: [Option<OpcodeMatch>; MAX_OPCODES],
+    registers: [Option<Register>; MAX_REGISTERS],
+    limit: Option<usize>,
+}
+
+/// Store `$item` in the first free slot of `$slots`, returning its id.
+macro_rules! insert {
+    ($slots: expr, $item: expr) => {{
+        let (id, slot) = $slots
+            .iter_mut()
+            .enumerate()
+            .find(|(_, slot)| slot.is_none())?;
+        *slot = Some($item);
+        Some(id)
+    }};
+}
+
+impl<S, K, B, R, D, O> Debugger<S, K, B, R, D, O>
+where
+    S: Screen,
+    K: KeyState,
+    B: Buzzer,
+    R: Rng,
+    D: Delay,
+    O: Observer,
+{
+    pub fn new(chip: Chip8<S, K, B, R, D, O>) -> Self {
+        Self {
+            chip: chip.map_observer(Monitor::new),
+            breakpoints: [None; MAX_BREAKPOINTS],
+            opcodes: [None; MAX_OPCODES],
+            registers: [None; MAX_REGISTERS],
+            limit: None,
+        }
+    }
+
+    pub fn chip(&self) -> &Chip8<S, K, B, R, D, Monitor<O>> {
+        &self.chip
+    }
+
+    pub fn chip_mut(&mut self) -> &mut Chip8<S, K, B, R, D, Monitor<O>> {
+        &mut self.chip
+    }
+
+    pub fn state(&self) -> &Mem {
+        self.chip.state()
+    }
+
+    pub fn state_mut(&mut self) -> &mut Mem {
+        self.chip.state_mut()
+    }
+
+    pub fn into_inner(self) -> Chip8<S, K, B, R, D, O> {
+        self.chip.map_observer(|monitor| monitor.inner)
+    }
+
+    /// Stop with [`Stop::Limit`] after executing this many instructions in
+    /// a single call. Polls by `LD Vx, K` while it waits for a key are not
+    /// counted, but waiting for as many polls in a row also stops.
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> Option<usize> {
+        insert!(self.breakpoints, breakpoint)
+    }
+
+    pub fn remove_breakpoint(&mut self, id: usize) -> Option<Breakpoint> {
+        self.breakpoints.get_mut(id)?.take()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (usize, &Breakpoint)> {
+        self.breakpoints
+            .iter()
+            .enumerate()
+            .filter_map(|(id, bp)| Some((id, bp.as_ref()?)))
+    }
+
+    pub fn add_opcode(&mut self, opcode: OpcodeMatch) -> Option<usize> {
+        insert!(self.opcodes, opcode)
+    }
+
+    pub fn remove_opcode(&mut self, id: usize) -> Option<OpcodeMatch> {
+        self.opcodes.get_mut(id)?.take()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> Option<usize> {
+        insert!(self.chip.observer_mut().watchpoints, watchpoint)
+    }
+
+    pub fn remove_watchpoint(&mut self, id: usize) -> Option<Watchpoint> {
+        self.chip.observer_mut().watchpoints.get_mut(id)?.take()
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (usize, &Watchpoint)> {
+        self.chip
+            .observer()
+            .watchpoints
+            .iter()
+            .enumerate()
+            .filter_map(|(id, watch)| Some((id, watch.as_ref()?)))
+    }
+
+    pub fn add_register(&mut self, reg: Register) -> Option<usize> {
+        insert!(self.registers, reg)
+    }
+
+    pub fn remove_register(&mut self, id: usize) -> Option<Register> {
+        self.registers.get_mut(id)?.take()
+    }
+
+    /// Execute a single instruction, entering subroutines.
+    pub fn step_into(&mut self) -> Result<Stop, S, K, B, R, D> {
+        self.resume(|_| Some(Stop::Step))
+    }
+
+    /// Execute a single instruction, running any subroutine it calls to
+    /// completion.
+    pub fn step_over(&mut self) -> Result<Stop, S, K, B, R, D> {
+        let mem = self.chip.state();
+        let (pc, depth) = (mem.pc, mem.stack.depth());
+
+        match self.opcode_at(pc) {
+            Some(opcode) if opcode & 0xF000 == 0x2000 => self.resume(|mem| {
+                (mem.pc == pc + 2 && mem.stack.depth() == depth).then_some(Stop::Step)
+            }),
+            _ => self.step_into(),
+        }
+    }
+
+    /// Run until the current subroutine returns. Fails with
+    /// [`StackEmpty`](mem::Error::StackEmpty) outside of a subroutine.
+    pub fn step_out(&mut self) -> Result<Stop, S, K, B, R, D> {
+        let depth = self.chip.state().stack.depth();
+        if depth == 0 {
+            return Err(mem::Error::StackEmpty.into());
+        }
+        self.resume(|mem| (mem.stack.depth() < depth).then_some(Stop::Returned(mem.pc)))
+    }
+
+    /// Run until `addr` is about to be executed.
+    pub fn run_to(&mut self, addr: u16) -> Result<Stop, S, K, B, R, D> {
+        self.resume(|mem| (mem.pc == addr).then_some(Stop::Reached(addr)))
+    }
+
+    /// Run until a breakpoint, watchpoint or the step limit is hit.
+    pub fn cont(&mut self) -> Result<Stop, S, K, B, R, D> {
+        self.resume(|_| None)
+    }
+
+    fn opcode_at(&self, pc: u16) -> Option<u16> {
+        match self.chip.state().ram.read_bytes(pc, 2) {
+            Ok(&[msb, lsb]) => Some(u16::from_be_bytes([msb, lsb])),
+            _ => None,
+        }
+    }
+
+    /// Check breakpoints on the instruction about to execute.
+    fn check_before(&mut self) -> Option<Stop> {
+        let mem = self.chip.state();
+        let pc = mem.pc;
+
+        for (id, bp) in self.breakpoints.iter_mut().enumerate() {
+            if let Some(bp) = bp.as_mut().filter(|bp| bp.addr == pc) {
+                bp.hits += 1;
+                let holds = bp.condition.is_none_or(|cond| cond.test(mem, bp.hits));
+                if bp.hits >= bp.after && holds {
+                    return Some(Stop::Breakpoint {
+                        id,
+                        addr: pc,
+                        hits: bp.hits,
+                    });
+                }
+            }
+        }
+
+        let opcode = self.opcode_at(pc)?;
+        self.opcodes
+            .iter()
+            .position(|op| op.is_some_and(|op| op.matches(opcode)))
+            .map(|id| Stop::Opcode { id, pc, opcode })
+    }
+
+    /// Execute one instruction, then check watchpoints.
+    fn step_checked(&mut self) -> Result<Option<Stop>, S, K, B, R, D> {
+        let before = Cpu::from(self.chip.state());
+        self.chip.observer_mut().waited = false;
+
+        // Take the hits even if the step failed, so accesses made before the
+        // failure aren't reported by the next step.
+        let result = self.chip.step();
+        let hits = core::mem::take(&mut self.chip.observer_mut().hits);
+        result?;
+
+        for (id, access) in hits.into_iter().enumerate() {
+            let Some(access) = access else { continue };
+            let Some(watch) = self.chip.observer_mut().watchpoints[id].as_mut() else {
+                continue;
+            };
+
+            watch.hits += 1;
+            let (condition, hits) = (watch.condition, watch.hits);
+            if condition.is_none_or(|cond| cond.test(self.chip.state(), hits)) {
+                return Ok(Some(Stop::Watchpoint { id, access }));
+            }
+        }
+
+        let after = Cpu::from(self.chip.state());
+        Ok(self.registers.iter().enumerate().find_map(|(id, reg)| {
+            let reg = (*reg)?;
+            let (old, new) = (reg.read(&before), reg.read(&after));
+            (old != new).then_some(Stop::Register { id, reg, old, new })
+        }))
+    }
+
+    /// Step until `done` returns a reason to stop, or a breakpoint or
+    /// watchpoint is hit. Breakpoints are not checked on the first
+    /// instruction so that execution can continue from one. `LD Vx, K` keeps
+    /// polling until a key completes it, and breakpoints are only checked
+    /// before the first poll.
+    fn resume<F>(&mut self, done: F) -> Result<Stop, S, K, B, R, D>
+    where
+        F: FnMut(&Mem) -> Option<Stop>,
+    {
+        self.resume_with(done, |_| ())
+    }
+
+    /// [`resume`](Self::resume), calling `hook` after every instruction
+    /// which completes.
+    pub(super) fn resume_with<F, H>(
+        &mut self,
+        mut done: F,
+        mut hook: H,
+    ) -> Result<Stop, S, K, B, R, D>
+    where
+        F: FnMut(&Mem) -> Option<Stop>,
+        H: FnMut(&Chip8<S, K, B, R, D, Monitor<O>>),
+    {
+        let mut steps = 0;
+        let mut polls = 0;
+
+        loop {
+            if steps > 0 && polls == 0 {
+                if let Some(stop) = self.check_before() {
+                    return Ok(stop);
+                }
+            }
+
+            if self
+                .limit
+                .is_some_and(|limit| steps >= limit || polls >= limit)
+            {
+                return Ok(Stop::Limit);
+            }
+
+            let stop = self.step_checked()?;
+            if self.chip.observer().waited {
+                polls += 1;
+                continue;
+            }
+            polls = 0;
+            steps += 1;
+            hook(&self.chip);
+
+            if let Some(stop) = stop.or_else(|| done(self.chip.state())) {
+                return Ok(stop);
+            }
+        }
+    }
+
+    /// Execute one instruction without checking breakpoints or watchpoints.
+    #[cfg(feature = "std")]
+    pub(super) fn replay_step(&mut self) -> Result<(), S, K, B, R, D> {
+        let result = self.chip.step();
+        self.chip.observer_mut().hits = [None; MAX_WATCHPOINTS];
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::hal::chip;
+    use crate::instruction::*;
+    use crate::vm::mem::Load;
+    use std::string::ToString;
+
+    macro_rules! debugger {
+        ($($fn: ident $($arg: expr),*;)+) => {{
+            let mut chip = chip!();
+            chip.state_mut()
+                .ram
+                .load(0x200, &crate::chip8_asm! { $($fn $($arg),*;)+ })
+                .unwrap();
+            chip.init().unwrap();
+
+            let mut debugger = Debugger::new(chip);
+            debugger.set_limit(Some(100));
+            debugger
+        }};
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut dbg = debugger! {
+            ld 0, 0;
+            add 0, 1;
+            jp 0x202;
+        };
+
+        let id = dbg.add_breakpoint(Breakpoint::new(0x202).after(3)).unwrap();
+
+        assert_eq!(
+            dbg.cont().unwrap(),
+            Stop::Breakpoint {
+                id,
+                addr: 0x202,
+                hits: 3
+            }
+        );
+        assert_eq!(dbg.state().reg.get(0).unwrap(), 2);
+
+        assert_eq!(
+            dbg.cont().unwrap(),
+            Stop::Breakpoint {
+                id,
+                addr: 0x202,
+                hits: 4
+            }
+        );
+
+        dbg.remove_breakpoint(id).unwrap();
+        assert_eq!(dbg.cont().unwrap(), Stop::Limit);
+    }
+
+    #[test]
+    fn conditions() {
+        let mut dbg = debugger! {
+            ld 0, 0;
+            add 0, 1;
+            ldi 0x300;
+            sviv 0;
+            jp 0x202;
+        };
+        dbg.set_limit(Some(1000));
+
+        let cond = Condition::parse("v0 >= 3 && [0x300] == v0 && hits > 4").unwrap();
+        let id = dbg
+            .add_breakpoint(Breakpoint::new(0x202).when(cond))
+            .unwrap();
+
+        assert_eq!(
+            dbg.cont().unwrap(),
+            Stop::Breakpoint {
+                id,
+                addr: 0x202,
+                hits: 5
+            }
+        );
+        assert_eq!(dbg.state().reg.get(0).unwrap(), 4);
+        dbg.remove_breakpoint(id);
+
+        let cond = Condition::parse("[0x300] == 10 || hits == 8").unwrap();
+        dbg.add_watchpoint(Watchpoint::write(0x300, 1).when(cond));
+
+        assert!(matches!(
+            dbg.cont().unwrap(),
+            Stop::Watchpoint { id: 0, access } if access.value == 10
+        ));
+        assert_eq!(dbg.watchpoints().next().unwrap().1.hits, 6);
+
+        assert!(matches!(
+            dbg.cont().unwrap(),
+            Stop::Watchpoint { id: 0, access } if access.value == 12
+        ));
+    }
+
+    #[test]
+    fn failed_step() {
+        let mut dbg = debugger! {
+            ldi 0xFFE;
+            sviv 2;
+            ld 0, 0;
+        };
+
+        dbg.add_watchpoint(Watchpoint::write(0xFFE, 1));
+        dbg.step_into().unwrap();
+        assert!(dbg.step_into().is_err());
+
+        dbg.state_mut().pc = 0x204;
+        assert_eq!(dbg.step_into().unwrap(), Stop::Step);
+    }
+
+    #[test]
+    fn key_wait() {
+        let mut dbg = debugger! {
+            ld 0, 1;
+            ldkey 2;
+            ldkey 3;
+        };
+
+        // Key 1 is pressed on the third poll and released on the fourth
+        dbg.chip_mut()
+            .keypad_mut()
+            .set_sequence(std::vec![None, Some(1), None, None]);
+        dbg.add_breakpoint(Breakpoint::new(0x202));
+        dbg.set_limit(Some(4));
+
+        assert!(matches!(dbg.cont().unwrap(), Stop::Breakpoint { .. }));
+        assert_eq!(dbg.step_into().unwrap(), Stop::Step);
+        assert_eq!(dbg.state().reg.get(2).unwrap(), 1);
+
+        // No key is pressed again, so the wait ends at the limit
+        assert_eq!(dbg.cont().unwrap(), Stop::Limit);
+        assert_eq!(dbg.state().pc, 0x204);
+    }
+
+    #[test]
+    fn opcodes() {
+        let mut dbg = debugger! {
+            ld 0, 1;
+            cls;
+            drw 0, 0, 1;
+        };
+
+        dbg.add_opcode(OpcodeMatch::class(Instruction::Drw(0, 0, 0)));
+
+        let stop = dbg.cont().unwrap();
+        assert_eq!(
+            stop,
+            Stop::Opcode {
+                id: 0,
+                pc: 0x204,
+                opcode: 0xD001
+            }
+        );
+        assert_eq!(stop.to_string(), "opcode breakpoint 0: D001 at 0x204");
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut dbg = debugger! {
+            ld 0, 123;
+            ldi 0x300;
+            bcd 0;
+            ldiv 2;
+            ld 5, 1;
+        };
+
+        dbg.add_watchpoint(Watchpoint::write(0x301, 1));
+        dbg.add_watchpoint(Watchpoint::read(0x300, 3));
+        dbg.add_register(Register::V(5));
+
+        let stop = dbg.cont().unwrap();
+        assert_eq!(
+            stop,
+            Stop::Watchpoint {
+                id: 0,
+                access: Access {
+                    pc: 0x204,
+                    addr: 0x301,
+                    value: 2,
+                    kind: AccessKind::Bcd,
+                },
+            }
+        );
+        assert_eq!(
+            stop.to_string(),
+            "watchpoint 0: write 0x02 at 0x301 by 0x204"
+        );
+
+        assert!(matches!(
+            dbg.cont().unwrap(),
+            Stop::Watchpoint { id: 1, access } if access.pc == 0x206
+        ));
+
+        assert_eq!(
+            dbg.cont().unwrap(),
+            Stop::Register {
+                id: 0,
+                reg: Register::V(5),
+                old: 0,
+                new: 1
+            }
+        );
+    }
+
+    #[test]
+    fn stepping() {
+        let mut dbg = debugger! {
+            call 0x206;
+            ld 1, 1;
+            jp 0x204;
+            ld 2, 2;
+            call 0x20C;
+            ret;
+            ld 3, 3;
+            ret;
+        };
+
+        assert_eq!(dbg.step_over().unwrap(), Stop::Step);
+        assert_eq!(dbg.state().pc, 0x202);
+        assert_eq!(dbg.state().reg.get(3).unwrap(), 3);
+
+        dbg.state_mut().pc = 0x200;
+        assert_eq!(dbg.step_into().unwrap(), Stop::Step);
+        assert_eq!(dbg.state().pc, 0x206);
+        assert_eq!(dbg.state().stack.frames(), &[0x202]);
+
+        dbg.step_into().unwrap();
+        dbg.step_into().unwrap();
+        assert_eq!(dbg.state().pc, 0x20C);
+        assert_eq!(dbg.step_out().unwrap(), Stop::Returned(0x20A));
+        assert_eq!(dbg.step_out().unwrap(), Stop::Returned(0x202));
+
+        dbg.add_breakpoint(Breakpoint::new(0x204));
+        assert_eq!(dbg.run_to(0x204).unwrap(), Stop::Reached(0x204));
+        assert!(matches!(dbg.cont().unwrap(), Stop::Breakpoint { .. }));
+
+        dbg.state_mut().stack.clear();
+        dbg.state_mut().pc = 0x202;
+        assert_eq!(
+            dbg.step_out().unwrap_err(),
+            crate::vm::Error::Memory(mem::Error::StackEmpty)
+        );
+        assert_eq!(dbg.state().pc, 0x202);
+
+        dbg.state_mut().pc = 0x20A;
+        assert_eq!(
+            dbg.step_into().unwrap_err(),
+            crate::vm::Error::Memory(mem::Error::StackEmpty)
+        );
+    }
+}
@@ -0,0 +1,632 @@
+//! GDB remote serial protocol stub.
+//!
+//! The register file is V0-VF (8 bit), I and PC (16 bit), then DT, ST and SP
+//! (8 bit), in that order and little endian. SP is the stack depth.
+
+use std::format;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::string::String;
+use std::vec::Vec;
+
+use super::{Breakpoint, Debugger, Stop, Watchpoint};
+use crate::hal::{Buzzer, Delay, KeyState, Rng, Screen};
+use crate::vm::mem::Load;
+use crate::vm::observer::Observer;
+use crate::vm::{self, Error};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" regnum="0"/>
+    <reg name="v1" bitsize="8"/>
+    <reg name="v2" bitsize="8"/>
+    <reg name="v3" bitsize="8"/>
+    <reg name="v4" bitsize="8"/>
+    <reg name="v5" bitsize="8"/>
+    <reg name="v6" bitsize="8"/>
+    <reg name="v7" bitsize="8"/>
+    <reg name="v8" bitsize="8"/>
+    <reg name="v9" bitsize="8"/>
+    <reg name="va" bitsize="8"/>
+    <reg name="vb" bitsize="8"/>
+    <reg name="vc" bitsize="8"/>
+    <reg name="vd" bitsize="8"/>
+    <reg name="ve" bitsize="8"/>
+    <reg name="vf" bitsize="8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="dt" bitsize="8"/>
+    <reg name="st" bitsize="8"/>
+    <reg name="sp" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+/// Instructions to run between checks for an interrupt while continuing.
+const SLICE: usize = 10_000;
+
+/// Number of registers, and the width of each in bytes.
+const REGISTERS: [usize; 21] = [
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 1, 1, 1,
+];
+
+/// Serves a single GDB connection over any byte stream.
+pub struct Server<T: Read + Write> {
+    reader: BufReader<T>,
+    ack: bool,
+    last: String,
+}
+
+/// A stream that can tell whether input is waiting without blocking on it.
+pub trait Pending {
+    fn pending(&mut self) -> io::Result<bool>;
+}
+
+impl Pending for TcpStream {
+    fn pending(&mut self) -> io::Result<bool> {
+        self.set_nonblocking(true)?;
+        let result = match self.peek(&mut [0]) {
+            Ok(len) => Ok(len > 0),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        };
+        self.set_nonblocking(false)?;
+        result
+    }
+}
+
+/// Accept one connection on `addr` and serve it until the client detaches.
+pub fn listen<A, S, K, B, R, D, O>(
+    addr: A,
+    debugger: &mut Debugger<S, K, B, R, D, O>,
+) -> io::Result<()>
+where
+    A: ToSocketAddrs,
+    S: Screen,
+    K: KeyState,
+    B: Buzzer,
+    R: Rng,
+    D: Delay,
+    O: Observer,
+{
+    let (stream, _) = TcpListener::bind(addr)?.accept()?;
+    stream.set_nodelay(true)?;
+    Server::new(stream).serve(debugger)
+}
+
+impl<T: Read + Write> Server<T> {
+    pub fn new(stream: T) -> Self {
+        Self {
+            reader: BufReader::new(stream),
+            ack: true,
+            last: String::from("S05"),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.reader.into_inner()
+    }
+
+    /// Like [`serve`](Self::serve), for streams such as pipes which can't be
+    /// checked for input without blocking. A continue can't be interrupted
+    /// and only stops at a breakpoint, a watchpoint or the step limit.
+    pub fn serve_blocking<S, K, B, R, D, O>(
+        &mut self,
+        debugger: &mut Debugger<S, K, B, R, D, O>,
+    ) -> io::Result<()>
+    where
+        S: Screen,
+        K: KeyState,
+        B: Buzzer,
+        R: Rng,
+        D: Delay,
+        O: Observer,
+    {
+        self.run(debugger, |_| Ok(false))
+    }
+
+    fn run<S, K, B, R, D, O>(
+        &mut self,
+        debugger: &mut Debugger<S, K, B, R, D, O>,
+        interrupted: fn(&mut Self) -> io::Result<bool>,
+    ) -> io::Result<()>
+    where
+        S: Screen,
+        K: KeyState,
+        B: Buzzer,
+        R: Rng,
+        D: Delay,
+        O: Observer,
+    {
+        while let Some(packet) = self.read_packet()? {
+            let reply = match packet.as_slice() {
+                b"k" => return Ok(()),
+                b"D" => {
+                    self.write_packet(b"OK")?;
+                    return Ok(());
+                }
+                b"QStartNoAckMode" => {
+                    self.write_packet(b"OK")?;
+                    self.ack = false;
+                    continue;
+                }
+                [b'c', ..] => {
+                    self.last = self.cont(debugger, interrupted)?;
+                    self.last.clone()
+                }
+                packet => handle(packet, debugger, &mut self.last),
+            };
+
+            self.write_packet(reply.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Continue in slices of [`SLICE`] instructions, checking for an
+    /// interrupt from the client in between, and return the stop reply. The
+    /// debugger's own step limit still applies to the whole run.
+    fn cont<S, K, B, R, D, O>(
+        &mut self,
+        debugger: &mut Debugger<S, K, B, R, D, O>,
+        interrupted: fn(&mut Self) -> io::Result<bool>,
+    ) -> io::Result<String>
+    where
+        S: Screen,
+        K: KeyState,
+        B: Buzzer,
+        R: Rng,
+        D: Delay,
+        O: Observer,
+    {
+        let limit = debugger.limit();
+        let mut remaining = limit;
+
+        let result = loop {
+            let slice = remaining.map_or(SLICE, |remaining| remaining.min(SLICE));
+            debugger.set_limit(Some(slice));
+
+            match debugger.cont() {
+                Ok(Stop::Limit) if remaining != Some(slice) => (),
+                result => break Ok(stop_reply(debugger, result)),
+            }
+
+            remaining = remaining.map(|remaining| remaining - slice);
+            match interrupted(self) {
+                Ok(false) => (),
+                Ok(true) => break Ok(String::from("S02")),
+                Err(err) => break Err(err),
+            }
+        };
+
+        debugger.set_limit(limit);
+        result
+    }
+
+    /// Read the next packet, acknowledging it if required. Returns `None` at
+    /// the end of the stream. An interrupt is returned as `[0x03]`.
+    fn read_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let mut byte = [0];
+            if self.reader.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+
+            match byte[0] {
+                0x03 => return Ok(Some(Vec::from([0x03]))),
+                b'$' => (),
+                _ => continue,
+            }
+
+            let mut data = Vec::new();
+            if self.reader.read_until(b'#', &mut data)? == 0 || data.pop() != Some(b'#') {
+                return Ok(None);
+            }
+
+            let mut sum = [0; 2];
+            self.reader.read_exact(&mut sum)?;
+
+            let valid = parse_hex(&sum) == Some(checksum(&data) as u32);
+            if self.ack {
+                self.reader
+                    .get_mut()
+                    .write_all(if valid { b"+" } else { b"-" })?;
+            }
+
+            if valid {
+                return Ok(Some(data));
+            }
+        }
+    }
+
+    fn write_packet(&mut self, data: &[u8]) -> io::Result<()> {
+        let stream = self.reader.get_mut();
+        stream.write_all(b"$")?;
+        stream.write_all(data)?;
+        stream.write_all(format!("#{:02x}", checksum(data)).as_bytes())?;
+        stream.flush()
+    }
+}
+
+impl<T: Read + Write + Pending> Server<T> {
+    /// Handle packets until the client detaches, kills the target or closes
+    /// the connection. A continue can be interrupted by the client.
+    pub fn serve<S, K, B, R, D, O>(
+        &mut self,
+        debugger: &mut Debugger<S, K, B, R, D, O>,
+    ) -> io::Result<()>
+    where
+        S: Screen,
+        K: KeyState,
+        B: Buzzer,
+        R: Rng,
+        D: Delay,
+        O: Observer,
+    {
+        self.run(debugger, Self::interrupted)
+    }
+
+    /// Whether the client sent an interrupt, without blocking. Stray bytes
+    /// such as acknowledgements are dropped, a packet is left in place.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        if self.reader.buffer().is_empty() && !self.reader.get_mut().pending()? {
+            return Ok(false);
+        }
+
+        let buf = self.reader.fill_buf()?;
+        let (len, found) = match buf.iter().position(|&byte| byte == 0x03 || byte == b'$') {
+            Some(pos) if buf[pos] == 0x03 => (pos + 1, true),
+            Some(pos) => (pos, false),
+            None => (buf.len(), false),
+        };
+
+        self.reader.consume(len);
+        Ok(found)
+    }
+}
+
+fn handle<S, K, B, R, D, O>(
+    packet: &[u8],
+    debugger: &mut Debugger<S, K, B, R, D, O>,
+    last: &mut String,
+) -> String
+where
+    S: Screen,
+    K: KeyState,
+    B: Buzzer,
+    R: Rng,
+    D: Delay,
+    O: Observer,
+{
+    let cmd = packet.first().copied().unwrap_or_default();
+    let args = core::str::from_utf8(packet.get(1..).unwrap_or_default()).unwrap_or_default();
+
+    let reply = match cmd {
+        b'?' => Some(last.clone()),
+        b'g' => Some(read_registers(debugger)),
+        b'G' => write_registers(debugger, args),
+        b'p' => parse_hex(args.as_bytes()).and_then(|reg| read_register(debugger, reg as usize)),
+        b'P' => args.split_once('=').and_then(|(reg, val)| {
+            write_register(debugger, parse_hex(reg.as_bytes())? as usize, val)
+        }),
+        b'm' => read_memory(debugger, args),
+        b'M' => write_memory(debugger, args),
+        b's' | 0x03 => {
+            let result = match cmd {
+                b's' => debugger.step_into(),
+                _ => Ok(Stop::Step),
+            };
+
+            *last = stop_reply(debugger, result);
+            Some(last.clone())
+        }
+        b'Z' | b'z' => set_breakpoint(debugger, cmd == b'Z', args),
+        b'H' => Some(String::from("OK")),
+        b'q' => Some(query(args)),
+        _ => Some(String::new()),
+    };
+
+    reply.unwrap_or_else(|| String::from("E01"))
+}
+
+fn query(args: &str) -> String {
+    if args.starts_with("Supported") {
+        return String::from("PacketSize=1000;qXfer:features:read+;QStartNoAckMode+");
+    }
+
+    if let Some(annex) = args.strip_prefix("Xfer:features:read:target.xml:") {
+        let range = annex.split_once(',').and_then(|(offset, len)| {
+            Some((
+                parse_hex(offset.as_bytes())? as usize,
+                parse_hex(len.as_bytes())? as usize,
+            ))
+        });
+
+        return match range {
+            Some((offset, _)) if offset >= TARGET_XML.len() => String::from("l"),
+            Some((offset, len)) if offset + len >= TARGET_XML.len() => {
+                format!("l{}", &TARGET_XML[offset..])
+            }
+            Some((offset, len)) => format!("m{}", &TARGET_XML[offset..offset + len]),
+            None => String::from("E01"),
+        };
+    }
+
+    match args {
+        "Attached" => String::from("1"),
+        "C" => String::from("QC1"),
+        "fThreadInfo" => String::from("m1"),
+        "sThreadInfo" => String::from("l"),
+        _ => String::new(),
+    }
+}
+
+fn stop_reply<S, K, B, R, D, O>(
+    debugger: &Debugger<S, K, B, R, D, O>,
+    result: Result<Stop, vm::ChipError<S, K, B, R, D>>,
+) -> String
+where
+    S: Screen,
+    K: KeyState,
+    B: Buzzer,
+    R: Rng,
+    D: Delay,
+    O: Observer,
+{
+    match result {
+        Ok(Stop::Watchpoint { id, access }) => {
+            let kind = match debugger.watchpoints().find(|(watch, _)| *watch == id) {
+                Some((_, watch)) if watch.read && watch.write => "awatch",
+                _ if access.kind.is_write() => "watch",
+                _ => "rwatch",
+            };
+
+            format!("T05{kind}:{:x};", access.addr)
+        }
+        Ok(_) => String::from("S05"),
+        Err(Error::Instruction(_) | Error::NotAligned(_)) => String::from("S04"),
+        Err(Error::Memory(_)) => String::from("S0b"),
+        Err(_) => String::from("S06"),
+    }
+}
+
+fn register_values<S, K, B, R, D, O>(debugger: &Debugger<S, K, B, R, D, O>) -> [u16; 21]
+where
+    S: Screen,
+    K: KeyState,
+    B: Buzzer,
+    R: Rng,
+    D: Delay,
+    O: Observer,
+{
+    let mem = debugger.state();
+    let mut regs = [0; 21];
+
+    for (reg, &v) in regs.iter_mut().zip(mem.reg.as_array()) {
+        *reg = v as u16;
+    }
+
+    regs[16..].copy_from_slice(&[
+        mem.i,
+        mem.pc,
+        mem.dt as u16,
+        mem.st as u16,
+        mem.stack.depth() as u16,
+    ]);
+    regs
+}
+
+fn read_register<S, K, B, R, D, O>(
+    debugger: &Debugger<S, K, B, R, D, O>,
+    reg: usize,
+) -> Option<String>
+where
+    S: Screen,
+    K: KeyState,
+    B: Buzzer,
+    R: Rng,
+    D: Delay,
+    O: Observer,
+{
+    let value = register_values(debugger).get(reg).copied()?;
+    Some(encode_hex(&value.to_le_bytes()[..REGISTERS[reg]]))
+}
+
+fn read_registers<S, K, B, R, D, O>(debugger: &Debugger<S, K, B, R, D, O>) -> String
+where
+    S: Screen,
+    K: KeyState,
+    B: Buzzer,
+    R: Rng,
+    D: Delay,
+    O: Observer,
+{
+    (0..REGISTERS.len())
+        .filter_map(|reg| read_register(debugger, reg))
+        .collect()
+}
+
+fn write_register<S, K, B, R, D, O>(
+    debugger: &mut Debugger<S, K, B, R, D, O>,
+    reg: usize,
+    hex: &str,
+) -> Option<String>
+where
+    S: Screen,
+    K: KeyState,
+    B: Buzzer,
+    R: Rng,
+    D: Delay,
+    O: Observer,
+{
+    let bytes = decode_hex(hex)?;
+    if bytes.len() != *REGISTERS.get(reg)? {
+        return None;
+    }
+
+    let value = u16::from_le_bytes([bytes[0], bytes.get(1).copied().unwrap_or(0)]);
+    let mem = debugger.state_mut();
+
+    match reg {
+        0..=15 => mem.reg.as_array_mut()[reg] = value as u8,
+        16 => mem.i = value,
+        17 => mem.pc = value,
+        18 => mem.dt = value as u8,
+        19 => mem.st = value as u8,
+        _ if (value as usize) <= mem.stack.depth() => mem.stack.truncate(value as usize),
+        _ => return None,
+    }
+
+    Some(String::from("OK"))
+}
+
+fn write_registers<S, K, B, R, D, O>(
+    debugger: &mut Debugger<S, K, B, R, D, O>,
+    hex: &str,
+) -> Option<String>
+where
+    S: Screen,
+    K: KeyState,
+    B: Buzzer,
+    R: Rng,
+    D: Delay,
+    O: Observer,
+{
+    let mut offset = 0;
+    for (reg, width) in REGISTERS.iter().enumerate() {
+        let value = hex.get(offset..offset + width * 2)?;
+        write_register(debugger, reg, value)?;
+        offset += width * 2;
+    }
+
+    Some(String::from("OK"))
+}
+
+fn parse_range(args: &str) -> Option<(u16, usize)> {
+    let (addr, len) = args.split_once(',')?;
+    let addr = parse_hex(addr.as_bytes())?;
+    let len = parse_hex(len.as_bytes())? as usize;
+
+    match addr as usize + len {
+        0..=4096 => Some((addr as u16, len)),
+        _ => None,
+    }
+}
+
+fn read_memory<S, K, B, R, D, O>(
+    debugger: &Debugger<S, K, B, R, D, O>,
+    args: &str,
+) -> Option<String>
+where
+    S: Screen,
+    K: KeyState,
+    B: Buzzer,
+    R: Rng,
+    D: Delay,
+    O: Observer,
+{
+    let (addr, len) = parse_range(args)?;
+    let ram = &debugger.state().ram;
+
+    let bytes = (addr..addr + len as u16)
+        .map(|loc| ram.read_byte(loc).ok())
+        .collect::<Option<Vec<u8>>>()?;
+
+    Some(encode_hex(&bytes))
+}
+
+fn write_memory<S, K, B, R, D, O>(
+    debugger: &mut Debugger<S, K, B, R, D, O>,
+    args: &str,
+) -> Option<String>
+where
+    S: Screen,
+    K: KeyState,
+    B: Buzzer,
+    R: Rng,
+    D: Delay,
+    O: Observer,
+{
+    let (range, data) = args.split_once(':')?;
+    let (addr, len) = parse_range(range)?;
+    let bytes = decode_hex(data).filter(|bytes| bytes.len() == len)?;
+
+    debugger.state_mut().ram.load(addr, &bytes[..]).ok()?;
+    Some(String::from("OK"))
+}
+
+fn set_breakpoint<S, K, B, R, D, O>(
+    debugger: &mut Debugger<S, K, B, R, D, O>,
+    insert: bool,
+    args: &str,
+) -> Option<String>
+where
+    S: Screen,
+    K: KeyState,
+    B: Buzzer,
+    R: Rng,
+    D: Delay,
+    O: Observer,
+{
+    let mut parts = args.split(',');
+    let kind = parts.next()?;
+    let addr = parse_hex(parts.next()?.as_bytes())? as u16;
+    let len = parse_hex(parts.next()?.as_bytes())? as u16;
+
+    let watch = match kind {
+        "0" | "1" => None,
+        "2" => Some(Watchpoint::write(addr, len)),
+        "3" => Some(Watchpoint::read(addr, len)),
+        "4" => Some(Watchpoint::access(addr, len)),
+        _ => return Some(String::new()),
+    };
+
+    match (insert, watch) {
+        (true, None) => {
+            debugger.add_breakpoint(Breakpoint::new(addr))?;
+        }
+        (true, Some(watch)) => {
+            debugger.add_watchpoint(watch)?;
+        }
+        (false, None) => {
+            let (id, _) = debugger.breakpoints().find(|(_, bp)| bp.addr == addr)?;
+            debugger.remove_breakpoint(id);
+        }
+        (false, Some(watch)) => {
+            // Hit counts differ from the freshly built watchpoint
+            let (id, _) = debugger.watchpoints().find(|(_, w)| {
+                (w.addr, w.len, w.read, w.write) == (watch.addr, watch.len, watch.read, watch.write)
+            })?;
+            debugger.remove_watchpoint(id);
+        }
+    }
+
+    Some(String::from("OK"))
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn parse_hex(hex: &[u8]) -> Option<u32> {
+    core::str::from_utf8(hex)
+        .ok()
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,17 @@
+mod condition;
+mod debugger;
+
+#[cfg(feature = "dap")]
+pub mod dap;
+#[cfg(feature = "std")]
+pub mod gdb;
+#[cfg(feature = "std")]
+pub mod repl;
+#[cfg(feature = "std")]
+pub mod rewind;
+
+pub use condition::{Condition, ParseError, ParseErrorKind, MAX_DEPTH, MAX_TERMS};
+pub use debugger::{
+    Breakpoint, Debugger, Monitor, OpcodeMatch, Register, Stop, Watchpoint, MAX_BREAKPOINTS,
+    MAX_OPCODES, MAX_REGISTERS, MAX_WATCHPOINTS,
+};
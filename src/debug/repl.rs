@@ -0,0 +1,593 @@
+//! Line-oriented machine monitor.
+//!
+//! Runs a ROM on headless peripherals. The keypad is driven by the `press`
+//! and `release` commands and the screen is kept in a [`FrameBuffer`] for the
+//! `screen` command. Numbers are decimal or `0x` hex.
+
+use core::fmt::{self, Write as _};
+use std::io::{self, Write};
+use std::string::String;
+use std::vec::Vec;
+
+use super::{Breakpoint, Condition, Debugger, ParseError, Stop, Watchpoint};
+use crate::asm::number;
+use crate::hal::framebuffer::FrameBuffer;
+use crate::hal::headless::{HeldKeys, NoDelay, NullBuzzer, XorShift};
+use crate::instruction::Instruction;
+use crate::vm::mem::{self, Load};
+use crate::vm::{self, Chip8};
+
+pub type Machine = Debugger<FrameBuffer, HeldKeys, NullBuzzer, XorShift, NoDelay>;
+type MachineError = vm::ChipError<FrameBuffer, HeldKeys, NullBuzzer, XorShift, NoDelay>;
+
+const HELP: &str = "\
+step [n]               execute n instructions (default 1)
+next                   step over subroutine calls
+finish                 run until the current subroutine returns
+continue               run until a breakpoint or watchpoint
+break <addr>           stop before executing addr
+watch <addr> [len]     stop when addr is written
+  ... if <cond>        only stop when cond holds, e.g. v3 == 0x10 && hits > 5
+delete <id>            remove breakpoint id
+unwatch <id>           remove watchpoint id
+regs                   show registers and timers
+set <reg> <value>      set v0-vf, i, pc, dt or st
+mem <addr> <len>       hex dump memory
+poke <addr> <byte>..   write bytes to memory
+disasm [addr] [n]      disassemble n instructions (default 8 from pc)
+stack                  show the call stack
+screen                 show the display
+press <key>            hold a key down
+release [key]          release a key, or all keys
+quit                   exit
+";
+
+enum Error {
+    Usage(&'static str),
+    Number(String),
+    Condition(ParseError),
+    Vm(vm::Error),
+    Full,
+    Fmt,
+}
+
+/// Bytes of ram the monitor can dump or disassemble.
+const RAM_SIZE: usize = 4096;
+
+/// Parse a number which must fit in a byte.
+fn byte(word: &str) -> Result<u8, Error> {
+    number(word)
+        .and_then(|value| u8::try_from(value).ok())
+        .ok_or_else(|| Error::Number(String::from(word)))
+}
+
+/// Use `default` for a missing optional argument, but still reject one that
+/// is not a number.
+fn optional(arg: Result<u16, Error>, default: u16) -> Result<u16, Error> {
+    match arg {
+        Err(Error::Usage(_)) => Ok(default),
+        arg => arg,
+    }
+}
+
+impl From<vm::Error> for Error {
+    fn from(err: vm::Error) -> Self {
+        Error::Vm(err)
+    }
+}
+
+impl From<mem::Error> for Error {
+    fn from(err: mem::Error) -> Self {
+        Error::Vm(vm::Error::Memory(err))
+    }
+}
+
+impl From<MachineError> for Error {
+    fn from(err: MachineError) -> Self {
+        Error::Vm(err.classify())
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Error::Condition(err)
+    }
+}
+
+impl From<fmt::Error> for Error {
+    fn from(_: fmt::Error) -> Self {
+        Error::Fmt
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Usage(usage) => write!(f, "usage: {usage}"),
+            Error::Number(text) => write!(f, "not a number: {text}"),
+            Error::Condition(err) => write!(f, "bad condition: {err}"),
+            Error::Vm(err) => write!(f, "{err:?}"),
+            Error::Full => f.write_str("no free slots"),
+            Error::Fmt => f.write_str("formatting failed"),
+        }
+    }
+}
+
+pub struct Repl {
+    machine: Machine,
+    last: String,
+}
+
+impl Repl {
+    /// Load `rom` at 0x200 on a fresh machine.
+    pub fn new(rom: &[u8]) -> vm::Result<Self> {
+        let mut chip = Chip8::new(
+            FrameBuffer::new(),
+            HeldKeys::default(),
+            NullBuzzer::default(),
+            XorShift::default(),
+            NoDelay,
+        );
+
+        chip.state_mut().ram.load(0x200, rom)?;
+        chip.init()?;
+
+        let mut machine = Debugger::new(chip);
+        machine.set_limit(Some(1_000_000));
+        Ok(Self::from_debugger(machine))
+    }
+
+    pub fn from_debugger(machine: Machine) -> Self {
+        Self {
+            machine,
+            last: String::new(),
+        }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    /// Run one command line and write its output. An empty line repeats the
+    /// previous command. Returns `false` once the user asks to quit.
+    pub fn execute<W: Write>(&mut self, line: &str, out: &mut W) -> io::Result<bool> {
+        let line = match line.trim() {
+            "" => core::mem::take(&mut self.last),
+            line => String::from(line),
+        };
+
+        let mut text = String::new();
+        let result = self.command(&line, &mut text);
+        out.write_all(text.as_bytes())?;
+
+        self.last = line;
+        match result {
+            Ok(more) => Ok(more),
+            Err(err) => {
+                writeln!(out, "error: {err}")?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Read commands from `input` until it ends or the user quits.
+    pub fn run<R: io::BufRead, W: Write>(&mut self, mut input: R, mut out: W) -> io::Result<()> {
+        loop {
+            write!(out, "(chip8) ")?;
+            out.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 || !self.execute(&line, &mut out)? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn command(&mut self, line: &str, out: &mut String) -> Result<bool, Error> {
+        let (line, condition) = match line.split_once(" if ") {
+            Some((line, condition)) => (line, Some(Condition::parse(condition)?)),
+            None => (line, None),
+        };
+
+        let mut words = line.split_whitespace();
+        let Some(name) = words.next() else {
+            return Ok(true);
+        };
+
+        if condition.is_some() && !matches!(name, "break" | "b" | "watch" | "w") {
+            return Err(Error::Usage("only break and watch take a condition"));
+        }
+
+        let mut arg = |usage| -> Result<u16, Error> {
+            let word = words.next().ok_or(Error::Usage(usage))?;
+            number(word).ok_or_else(|| Error::Number(String::from(word)))
+        };
+
+        match name {
+            "help" | "h" | "?" => out.push_str(HELP),
+            "quit" | "q" | "exit" => return Ok(false),
+
+            "step" | "s" => {
+                let count = optional(arg("step [n]"), 1)?;
+                for _ in 0..count {
+                    match self.machine.step_into()? {
+                        Stop::Step => (),
+                        stop => {
+                            writeln!(out, "{stop}")?;
+                            break;
+                        }
+                    }
+                }
+                self.location(out)?;
+            }
+            "next" | "n" => self.resume(Machine::step_over, out)?,
+            "finish" | "f" => self.resume(Machine::step_out, out)?,
+            "continue" | "c" => self.resume(Machine::cont, out)?,
+
+            "break" | "b" => {
+                let addr = arg("break <addr> [if <cond>]")?;
+                let mut bp = Breakpoint::new(addr);
+                bp.condition = condition;
+
+                let id = self.machine.add_breakpoint(bp).ok_or(Error::Full)?;
+                writeln!(out, "breakpoint {id} at 0x{addr:03X}")?;
+            }
+            "watch" | "w" => {
+                let addr = arg("watch <addr> [len] [if <cond>]")?;
+                let len = optional(arg("watch <addr> [len] [if <cond>]"), 1)?;
+                let mut watch = Watchpoint::write(addr, len);
+                watch.condition = condition;
+
+                let id = self.machine.add_watchpoint(watch).ok_or(Error::Full)?;
+                writeln!(out, "watchpoint {id} at 0x{addr:03X}")?;
+            }
+            "delete" | "d" => {
+                let id = arg("delete <id>")?;
+                if self.machine.remove_breakpoint(id as usize).is_none() {
+                    writeln!(out, "no breakpoint {id}")?;
+                }
+            }
+            "unwatch" => {
+                let id = arg("unwatch <id>")?;
+                if self.machine.remove_watchpoint(id as usize).is_none() {
+                    writeln!(out, "no watchpoint {id}")?;
+                }
+            }
+
+            "regs" | "r" => self.registers(out)?,
+            "set" => {
+                const USAGE: &str = "set <reg> <value>";
+                let reg = words
+                    .next()
+                    .ok_or(Error::Usage(USAGE))?
+                    .to_ascii_lowercase();
+                let word = words.next().ok_or(Error::Usage(USAGE))?;
+                let value = number(word).ok_or_else(|| Error::Number(String::from(word)))?;
+                let as_byte = || byte(word);
+
+                let mem = self.machine.state_mut();
+                match reg.as_str() {
+                    "i" => mem.i = value,
+                    "pc" => mem.pc = value,
+                    "dt" => mem.dt = as_byte()?,
+                    "st" => mem.st = as_byte()?,
+                    _ => match reg.strip_prefix('v').map(|idx| u8::from_str_radix(idx, 16)) {
+                        Some(Ok(idx)) => mem.reg.set(idx, as_byte()?)?,
+                        _ => return Err(Error::Usage(USAGE)),
+                    },
+                }
+            }
+
+            "mem" | "m" => {
+                const USAGE: &str = "mem <addr> <len>, ending by 0x1000";
+                let addr = arg(USAGE)?;
+                let len = arg(USAGE)?;
+                if addr as usize + len as usize > RAM_SIZE {
+                    return Err(Error::Usage(USAGE));
+                }
+                self.dump(addr, len, out)?;
+            }
+            "poke" => {
+                const USAGE: &str = "poke <addr> <byte>..";
+                let addr = arg(USAGE)?;
+                let bytes = words.map(byte).collect::<Result<Vec<u8>, _>>()?;
+                if bytes.is_empty() {
+                    return Err(Error::Usage(USAGE));
+                }
+                self.machine.state_mut().ram.write_bytes(addr, &bytes)?;
+            }
+            "disasm" | "x" => {
+                const USAGE: &str = "disasm [addr] [n], ending by 0x1000";
+                let addr = optional(arg(USAGE), self.machine.state().pc)?;
+                let count = optional(arg(USAGE), 8)?;
+                if addr as usize + count as usize * 2 > RAM_SIZE {
+                    return Err(Error::Usage(USAGE));
+                }
+                for idx in 0..count {
+                    self.disassemble(addr + idx * 2, out)?;
+                }
+            }
+            "stack" | "bt" => self.stack(out)?,
+            "screen" => self.screen(out)?,
+
+            "press" => {
+                let key = arg("press <key>")?;
+                self.machine.chip_mut().keypad_mut().press(key as u8);
+            }
+            "release" => match arg("release [key]") {
+                Ok(key) => self.machine.chip_mut().keypad_mut().release(key as u8),
+                Err(_) => self.machine.chip_mut().keypad_mut().release_all(),
+            },
+
+            _ => writeln!(out, "unknown command: {name} (try help)")?,
+        }
+
+        Ok(true)
+    }
+
+    fn resume(
+        &mut self,
+        run: fn(&mut Machine) -> Result<Stop, MachineError>,
+        out: &mut String,
+    ) -> Result<(), Error> {
+        match run(&mut self.machine)? {
+            Stop::Step => (),
+            stop => writeln!(out, "{stop}")?,
+        }
+        self.location(out)
+    }
+
+    fn location(&self, out: &mut String) -> Result<(), Error> {
+        self.disassemble(self.machine.state().pc, out)
+    }
+
+    fn disassemble(&self, addr: u16, out: &mut String) -> Result<(), Error> {
+        let mem = self.machine.state();
+        let marker = if addr == mem.pc { '>' } else { ' ' };
+
+        match mem.ram.read_bytes(addr, 2) {
+            Ok(&[msb, lsb]) => {
+                let opcode = u16::from_be_bytes([msb, lsb]);
+                write!(out, "{marker} {addr:03X}: {opcode:04X}")?;
+                match Instruction::decode(opcode) {
+                    Some(inst) => writeln!(out, "  {inst}")?,
+                    None => writeln!(out)?,
+                }
+            }
+            _ => writeln!(out, "{marker} {addr:03X}: ????")?,
+        }
+
+        Ok(())
+    }
+
+    fn registers(&self, out: &mut String) -> Result<(), Error> {
+        let mem = self.machine.state();
+        for (idx, val) in mem.reg.as_array().iter().enumerate() {
+            let sep = if idx % 8 == 7 { '\n' } else { ' ' };
+            write!(out, "V{idx:X}={val:02X}{sep}")?;
+        }
+
+        writeln!(
+            out,
+            "I={:03X} PC={:03X} DT={:02X} ST={:02X} SP={}",
+            mem.i,
+            mem.pc,
+            mem.dt,
+            mem.st,
+            mem.stack.depth()
+        )?;
+        Ok(())
+    }
+
+    fn dump(&self, addr: u16, len: u16, out: &mut String) -> Result<(), Error> {
+        let ram = &self.machine.state().ram;
+        let end = addr.saturating_add(len);
+
+        for line in (addr..end).step_by(16) {
+            write!(out, "{line:03X}:")?;
+            let mut ascii = String::new();
+            for at in line..end.min(line + 16) {
+                let byte = ram.read_byte(at)?;
+                write!(out, " {byte:02X}")?;
+                ascii.push(if byte.is_ascii_graphic() {
+                    byte as char
+                } else {
+                    '.'
+                });
+            }
+            writeln!(out, "  {ascii}")?;
+        }
+
+        Ok(())
+    }
+
+    fn stack(&self, out: &mut String) -> Result<(), Error> {
+        let mem = self.machine.state();
+        writeln!(out, "#0 {:03X}", mem.pc)?;
+
+        // Frames hold return addresses, the call is the instruction before
+        for (idx, ret) in mem.stack.iter().rev().enumerate() {
+            writeln!(out, "#{} {:03X}", idx + 1, ret.wrapping_sub(2))?;
+        }
+
+        Ok(())
+    }
+
+    fn screen(&self, out: &mut String) -> Result<(), Error> {
+        let screen = self.machine.chip().screen();
+        for y in 0..screen.height() {
+            for x in 0..screen.width() {
+                out.push(if screen.pixel(x, y) { '#' } else { '.' });
+            }
+            out.push('\n');
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+    use std::vec::Vec;
+
+    use super::Repl;
+
+    fn run(repl: &mut Repl, line: &str) -> String {
+        let mut out = Vec::new();
+        assert!(repl.execute(line, &mut out).unwrap());
+        String::from_utf8(out).unwrap()
+    }
+
+    // 0x200: LD V0, 0; 0x202: CALL 0x20A; 0x204: LD I, 0x300; 0x206: LD [I], V0
+    // 0x208: JP 0x202; 0x20A: ADD V0, 1; 0x20C: RET
+    const COUNT: [u8; 14] = [
+        0x60, 0x00, 0x22, 0x0A, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x02, 0x70, 0x01, 0x00, 0xEE,
+    ];
+
+    #[test]
+    fn stepping() {
+        let mut repl = Repl::new(&COUNT).unwrap();
+
+        assert_eq!(run(&mut repl, "step"), "> 202: 220A  CALL 0x20A\n");
+        assert_eq!(run(&mut repl, "step"), "> 20A: 7001  ADD V0, 0x01\n");
+        assert_eq!(run(&mut repl, "stack"), "#0 20A\n#1 202\n");
+        assert_eq!(
+            run(&mut repl, "finish"),
+            "returned to 0x204\n> 204: A300  LD I, 0x300\n"
+        );
+        assert_eq!(run(&mut repl, "next"), "> 206: F055  LD [I], V0\n");
+        assert_eq!(run(&mut repl, ""), "> 208: 1202  JP 0x202\n");
+        assert_eq!(run(&mut repl, "step 2"), "> 20A: 7001  ADD V0, 0x01\n");
+
+        assert_eq!(run(&mut repl, "break 0x20a"), "breakpoint 0 at 0x20A\n");
+        assert_eq!(
+            run(&mut repl, "continue"),
+            "breakpoint 0 at 0x20A (hit 1)\n> 20A: 7001  ADD V0, 0x01\n"
+        );
+        assert_eq!(run(&mut repl, "delete 0"), "");
+        assert_eq!(run(&mut repl, "delete 0"), "no breakpoint 0\n");
+
+        assert_eq!(run(&mut repl, "watch 0x300"), "watchpoint 0 at 0x300\n");
+        assert_eq!(
+            run(&mut repl, "c"),
+            "watchpoint 0: write 0x03 at 0x300 by 0x206\n> 208: 1202  JP 0x202\n"
+        );
+
+        assert_eq!(
+            run(&mut repl, "disasm 0x200 3"),
+            "  200: 6000  LD V0, 0x00\n  202: 220A  CALL 0x20A\n  204: A300  LD I, 0x300\n"
+        );
+        assert_eq!(run(&mut repl, "unwatch 0"), "");
+        assert_eq!(
+            run(&mut repl, "break 0x204 if v0 == 5"),
+            "breakpoint 0 at 0x204\n"
+        );
+        assert_eq!(
+            run(&mut repl, "c"),
+            "breakpoint 0 at 0x204 (hit 2)\n> 204: A300  LD I, 0x300\n"
+        );
+        assert_eq!(
+            run(&mut repl, "break 0x204 if v0 =="),
+            "error: bad condition: unexpected end of expression at 5\n"
+        );
+        assert_eq!(
+            run(&mut repl, "step if v0"),
+            "error: usage: only break and watch take a condition\n"
+        );
+        assert_eq!(
+            run(&mut repl, "break"),
+            "error: usage: break <addr> [if <cond>]\n"
+        );
+        assert_eq!(run(&mut repl, "break x"), "error: not a number: x\n");
+        assert_eq!(run(&mut repl, "step x"), "error: not a number: x\n");
+        assert_eq!(
+            run(&mut repl, "disasm 0x200 40000"),
+            "error: usage: disasm [addr] [n], ending by 0x1000\n"
+        );
+        assert_eq!(run(&mut repl, "disasm 0xFFC 1"), "  FFC: 0000\n");
+
+        let mut out = Vec::new();
+        assert!(!repl.execute("quit", &mut out).unwrap());
+    }
+
+    #[test]
+    fn memory() {
+        let mut repl = Repl::new(&COUNT).unwrap();
+
+        assert_eq!(run(&mut repl, "poke 0x300 0x41 0x42 0"), "");
+        assert_eq!(run(&mut repl, "mem 0x300 3"), "300: 41 42 00  AB.\n");
+        assert_eq!(
+            run(&mut repl, "mem 0x200 18"),
+            "200: 60 00 22 0A A3 00 F0 55 12 02 70 01 00 EE 00 00  `.\"....U..p.....\n\
+             210: 00 00  ..\n"
+        );
+        assert_eq!(
+            run(&mut repl, "mem 0xFFF0 5"),
+            "error: usage: mem <addr> <len>, ending by 0x1000\n"
+        );
+        assert_eq!(run(&mut repl, "mem 0xFFF 1"), "FFF: 00  .\n");
+
+        assert_eq!(run(&mut repl, "set v3 0x10"), "");
+        assert_eq!(run(&mut repl, "set i 0x300"), "");
+        assert_eq!(run(&mut repl, "set dt 60"), "");
+        assert_eq!(
+            run(&mut repl, "set vg 1"),
+            "error: usage: set <reg> <value>\n"
+        );
+        assert_eq!(
+            run(&mut repl, "set v3 0x1FF"),
+            "error: not a number: 0x1FF\n"
+        );
+        assert_eq!(
+            run(&mut repl, "set dt 0x100"),
+            "error: not a number: 0x100\n"
+        );
+
+        assert_eq!(
+            run(&mut repl, "poke 0x300 1 zz 2"),
+            "error: not a number: zz\n"
+        );
+        assert_eq!(
+            run(&mut repl, "poke 0x300 1 0x100"),
+            "error: not a number: 0x100\n"
+        );
+        assert_eq!(run(&mut repl, "mem 0x300 3"), "300: 41 42 00  AB.\n");
+        assert_eq!(
+            run(&mut repl, "regs"),
+            "V0=00 V1=00 V2=00 V3=10 V4=00 V5=00 V6=00 V7=00\n\
+             V8=00 V9=00 VA=00 VB=00 VC=00 VD=00 VE=00 VF=00\n\
+             I=300 PC=200 DT=3C ST=00 SP=0\n"
+        );
+    }
+
+    #[test]
+    fn screen_and_keypad() {
+        // LD V0, K; LD F, V0; DRW V1, V1, 5
+        let mut repl = Repl::new(&[0xF0, 0x0A, 0xF0, 0x29, 0xD1, 0x15]).unwrap();
+
+        // LD V0, K waits for the key to be pressed and released, so it
+        // polls until the step limit while the key is held.
+        assert_eq!(run(&mut repl, "press 1"), "");
+        assert_eq!(
+            run(&mut repl, "step"),
+            "step limit reached\n> 200: F00A  LD V0, K\n"
+        );
+        assert_eq!(run(&mut repl, "release"), "");
+        assert_eq!(run(&mut repl, "step 3"), "> 206: 0000\n");
+
+        let screen = run(&mut repl, "screen");
+        let rows: Vec<&str> = screen.lines().collect();
+        assert_eq!(rows.len(), 32);
+        assert_eq!(&rows[0][..8], "..#.....");
+        assert_eq!(&rows[1][..8], ".##.....");
+        assert_eq!(&rows[4][..8], ".###....");
+        assert!(rows[5].chars().all(|pixel| pixel == '.'));
+    }
+}
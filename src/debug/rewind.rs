@@ -0,0 +1,627 @@
+//! Reverse execution for the [`Debugger`].
+//!
+//! [`Rewind`] keeps a full copy of the machine every few instructions and
+//! records every result returned by the [`KeyState`] and [`Rng`]. Stepping
+//! backwards restores the nearest earlier checkpoint and executes forward
+//! again, reading inputs from the recording so that the replay follows the
+//! original run exactly.
+
+use std::vec::Vec;
+
+use super::{Debugger, Monitor, Stop};
+use crate::hal::{Buzzer, Delay, KeyState, Keys, Rng, Screen};
+use crate::vm::mem::{self, Mem};
+use crate::vm::observer::{Access, Cpu, Exec, Observer};
+use crate::vm::{Chip8, ChipError};
+
+/// Peripheral wrapper which records the results it returns, and returns
+/// them again after being rewound.
+///
+/// Every [`KeyState::keys`] sample and [`Rng::random`] number is recorded.
+#[derive(Debug, Clone)]
+pub struct Tape<T> {
+    inner: T,
+    /// Key masks sampled, or each random number.
+    log: Vec<u16>,
+    pos: usize,
+}
+
+impl<T> Tape<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            log: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// The wrapped peripheral. While replaying it is not consulted, so
+    /// changes only take effect once the recording is exhausted.
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Number of recorded results.
+    pub fn len(&self) -> usize {
+        self.log.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.log.is_empty()
+    }
+
+    /// Whether results are currently being read back from the recording.
+    pub fn replaying(&self) -> bool {
+        self.pos < self.log.len()
+    }
+
+    fn play<E>(&mut self, live: impl FnOnce(&mut T) -> Result<u16, E>) -> Result<u16, E> {
+        let value = match self.log.get(self.pos) {
+            Some(&value) => value,
+            None => {
+                let value = live(&mut self.inner)?;
+                self.log.push(value);
+                value
+            }
+        };
+
+        self.pos += 1;
+        Ok(value)
+    }
+
+    fn truncate(&mut self) {
+        self.log.truncate(self.pos);
+    }
+}
+
+impl<K: KeyState> KeyState for Tape<K> {
+    type Error = K::Error;
+
+    fn keys<D: Delay>(&mut self, delay: &mut D, held: Keys) -> Result<Keys, Self::Error> {
+        self.play(|keypad| keypad.keys(delay, held).map(Keys::mask))
+            .map(Keys)
+    }
+}
+
+impl<R: Rng> Rng for Tape<R> {
+    type Error = R::Error;
+
+    fn random(&mut self) -> Result<u8, Self::Error> {
+        self.play(|rng| rng.random().map(u16::from))
+            .map(|value| value as u8)
+    }
+}
+
+/// A write to memory, made by the instruction at `pc`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Write {
+    /// Number of instructions executed before the one which wrote.
+    pub step: u64,
+    pub pc: u16,
+    pub addr: u16,
+    pub value: u8,
+}
+
+/// Observer which counts instructions and records memory writes.
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    step: u64,
+    /// Steps before this have already been recorded.
+    horizon: u64,
+    writes: Vec<Write>,
+}
+
+impl Journal {
+    /// Instructions executed since the recording started.
+    pub fn step(&self) -> u64 {
+        self.step
+    }
+
+    /// Every recorded write in execution order, including those after the
+    /// current step when the machine has been rewound.
+    pub fn writes(&self) -> &[Write] {
+        &self.writes
+    }
+
+    fn truncate(&mut self) {
+        let step = self.step;
+        self.writes.retain(|write| write.step < step);
+        self.horizon = step;
+    }
+}
+
+impl Observer for Journal {
+    fn after(&mut self, _exec: &Exec, _after: &Cpu) {
+        self.step += 1;
+        self.horizon = self.horizon.max(self.step);
+    }
+
+    fn access(&mut self, access: &Access) {
+        if access.kind.is_write() && self.step >= self.horizon {
+            self.writes.push(Write {
+                step: self.step,
+                pc: access.pc,
+                addr: access.addr,
+                value: access.value,
+            });
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Checkpoint<S> {
+    step: u64,
+    mem: Mem,
+    screen: S,
+    keys: usize,
+    random: usize,
+}
+
+type Machine<S, K, B, R, D> = Chip8<S, Tape<K>, B, Tape<R>, D, Monitor<Journal>>;
+
+impl<S: Clone> Checkpoint<S> {
+    fn take<K, B, R, D>(chip: &Machine<S, K, B, R, D>) -> Self
+    where
+        S: Screen,
+        K: KeyState,
+        B: Buzzer,
+        R: Rng,
+        D: Delay,
+    {
+        Self {
+            step: chip.observer().inner().step,
+            mem: *chip.state(),
+            screen: chip.screen().clone(),
+            keys: chip.keypad().pos,
+            random: chip.rng().pos,
+        }
+    }
+}
+
+/// A [`Debugger`] which can also run backwards.
+///
+/// Going back and then forward again replays the recorded run. Changing the
+/// machine through [`state_mut`](Self::state_mut) discards everything
+/// recorded after the current step, so that execution continues from the
+/// modified state. Changes made through [`debugger_mut`](Self::debugger_mut)
+/// are not recorded and are lost when stepping back over them.
+pub struct Rewind<S, K, B, R, D>
+where
+    S: Screen + Clone,
+    K: KeyState,
+    B: Buzzer,
+    R: Rng,
+    D: Delay,
+{
+    dbg: Debugger<S, Tape<K>, B, Tape<R>, D, Journal>,
+    checkpoints: Vec<Checkpoint<S>>,
+    interval: u64,
+    /// The state was modified and needs a new checkpoint.
+    dirty: bool,
+}
+
+impl<S, K, B, R, D> Rewind<S, K, B, R, D>
+where
+    S: Screen + Clone,
+    K: KeyState,
+    B: Buzzer,
+    R: Rng,
+    D: Delay,
+{
+    /// Start recording `chip`, taking a checkpoint every `interval`
+    /// instructions.
+    pub fn new(chip: Chip8<S, K, B, R, D>, interval: u64) -> Self {
+        let (screen, keypad, buzzer, rng, delay, mem) = chip.free();
+        let chip = Chip8::from_state(
+            screen,
+            Tape::new(keypad),
+            buzzer,
+            Tape::new(rng),
+            delay,
+            mem,
+        )
+        .with_observer(Journal::default());
+
+        let dbg = Debugger::new(chip);
+        let checkpoints = std::vec![Checkpoint::take(dbg.chip())];
+
+        Self {
+            dbg,
+            checkpoints,
+            interval: interval.max(1),
+            dirty: false,
+        }
+    }
+
+    pub fn debugger(&self) -> &Debugger<S, Tape<K>, B, Tape<R>, D, Journal> {
+        &self.dbg
+    }
+
+    /// Access to breakpoints, watchpoints and the step limit.
+    pub fn debugger_mut(&mut self) -> &mut Debugger<S, Tape<K>, B, Tape<R>, D, Journal> {
+        &mut self.dbg
+    }
+
+    pub fn journal(&self) -> &Journal {
+        self.dbg.chip().observer().inner()
+    }
+
+    pub fn state(&self) -> &Mem {
+        self.dbg.state()
+    }
+
+    /// Modify the machine, discarding any recorded future.
+    pub fn state_mut(&mut self) -> &mut Mem {
+        self.truncate();
+        self.dirty = true;
+        self.dbg.state_mut()
+    }
+
+    pub fn keypad_mut(&mut self) -> &mut K {
+        self.dbg.chip_mut().keypad_mut().inner_mut()
+    }
+
+    /// Instructions executed since the recording started.
+    pub fn position(&self) -> u64 {
+        self.journal().step
+    }
+
+    pub fn into_inner(self) -> Chip8<S, K, B, R, D> {
+        let (screen, keypad, buzzer, rng, delay, mem) = self.dbg.into_inner().free();
+        Chip8::from_state(
+            screen,
+            keypad.into_inner(),
+            buzzer,
+            rng.into_inner(),
+            delay,
+            mem,
+        )
+    }
+
+    /// See [`Debugger::step_into`].
+    pub fn step_into(&mut self) -> Result<Stop, ChipError<S, K, B, R, D>> {
+        self.resume(|_| Some(Stop::Step))
+    }
+
+    /// See [`Debugger::step_over`].
+    pub fn step_over(&mut self) -> Result<Stop, ChipError<S, K, B, R, D>> {
+        let mem = self.state();
+        let (pc, depth) = (mem.pc, mem.stack.depth());
+
+        match mem.ram.read_bytes(pc, 2) {
+            Ok(&[msb, _]) if msb & 0xF0 == 0x20 => self.resume(|mem| {
+                (mem.pc == pc + 2 && mem.stack.depth() == depth).then_some(Stop::Step)
+            }),
+            _ => self.step_into(),
+        }
+    }
+
+    /// See [`Debugger::step_out`].
+    pub fn step_out(&mut self) -> Result<Stop, ChipError<S, K, B, R, D>> {
+        let depth = self.state().stack.depth();
+        if depth == 0 {
+            return Err(mem::Error::StackEmpty.into());
+        }
+        self.resume(|mem| (mem.stack.depth() < depth).then_some(Stop::Returned(mem.pc)))
+    }
+
+    /// See [`Debugger::run_to`].
+    pub fn run_to(&mut self, addr: u16) -> Result<Stop, ChipError<S, K, B, R, D>> {
+        self.resume(|mem| (mem.pc == addr).then_some(Stop::Reached(addr)))
+    }
+
+    /// See [`Debugger::cont`].
+    pub fn cont(&mut self) -> Result<Stop, ChipError<S, K, B, R, D>> {
+        self.resume(|_| None)
+    }
+
+    /// Undo the last instruction, or stop with [`Stop::Start`] if there is
+    /// none.
+    pub fn reverse_step(&mut self) -> Result<Stop, ChipError<S, K, B, R, D>> {
+        self.settle();
+
+        match self.position() {
+            0 => Ok(Stop::Start),
+            step => self.seek(step - 1).map(|_| Stop::Step),
+        }
+    }
+
+    /// Run backwards to the most recent step at which a breakpoint was
+    /// reached, or to the start of the recording.
+    ///
+    /// Conditions are evaluated with each breakpoint's current hit count,
+    /// which is not changed, and [`Breakpoint::after`](super::Breakpoint::after)
+    /// is ignored.
+    pub fn reverse_continue(&mut self) -> Result<Stop, ChipError<S, K, B, R, D>> {
+        self.settle();
+
+        let mut end = self.position();
+        for idx in (0..self.checkpoints.len()).rev() {
+            let start = self.checkpoints[idx].step;
+            if start >= end {
+                continue;
+            }
+
+            self.restore(idx);
+            let mut found = None;
+            for step in start..end {
+                if let Some(stop) = self.breakpoint() {
+                    found = Some((step, stop));
+                }
+                self.dbg.replay_step()?;
+            }
+
+            if let Some((step, stop)) = found {
+                self.seek(step)?;
+                return Ok(stop);
+            }
+
+            end = start;
+        }
+
+        self.seek(0)?;
+        Ok(Stop::Start)
+    }
+
+    /// The most recent write to `addr` before the current step.
+    pub fn last_write(&self, addr: u16) -> Option<Write> {
+        let step = self.position();
+        self.journal()
+            .writes
+            .iter()
+            .rev()
+            .find(|write| write.addr == addr && write.step < step)
+            .copied()
+    }
+
+    /// Restore the machine to how it was after `step` instructions. Steps
+    /// beyond the recording are executed normally.
+    pub fn seek(&mut self, step: u64) -> Result<(), ChipError<S, K, B, R, D>> {
+        self.settle();
+
+        let idx = self
+            .checkpoints
+            .iter()
+            .rposition(|cp| cp.step <= step)
+            .unwrap_or(0);
+
+        if !(self.checkpoints[idx].step..=step).contains(&self.position()) {
+            self.restore(idx);
+        }
+
+        while self.position() < step {
+            self.dbg.replay_step()?;
+            self.checkpoint();
+        }
+
+        Ok(())
+    }
+
+    /// The breakpoint which would stop execution at the current step.
+    fn breakpoint(&self) -> Option<Stop> {
+        let mem = self.state();
+        self.dbg.breakpoints().find_map(|(id, bp)| {
+            let holds = bp.condition.is_none_or(|cond| cond.test(mem, bp.hits));
+            (bp.addr == mem.pc && holds).then_some(Stop::Breakpoint {
+                id,
+                addr: bp.addr,
+                hits: bp.hits,
+            })
+        })
+    }
+
+    fn resume<F>(&mut self, done: F) -> Result<Stop, ChipError<S, K, B, R, D>>
+    where
+        F: FnMut(&Mem) -> Option<Stop>,
+    {
+        self.settle();
+
+        let (checkpoints, interval) = (&mut self.checkpoints, self.interval);
+        self.dbg.resume_with(done, |chip| {
+            Self::checkpoint_into(checkpoints, interval, chip)
+        })
+    }
+
+    fn checkpoint(&mut self) {
+        Self::checkpoint_into(&mut self.checkpoints, self.interval, self.dbg.chip());
+    }
+
+    fn checkpoint_into(
+        checkpoints: &mut Vec<Checkpoint<S>>,
+        interval: u64,
+        chip: &Machine<S, K, B, R, D>,
+    ) {
+        let step = chip.observer().inner().step;
+        let last = checkpoints.last().map_or(0, |cp| cp.step);
+
+        if step.is_multiple_of(interval) && step > last {
+            checkpoints.push(Checkpoint::take(chip));
+        }
+    }
+
+    fn restore(&mut self, idx: usize) {
+        let cp = &self.checkpoints[idx];
+        let chip = self.dbg.chip_mut();
+
+        *chip.state_mut() = cp.mem;
+        *chip.screen_mut() = cp.screen.clone();
+        chip.keypad_mut().pos = cp.keys;
+        chip.rng_mut().pos = cp.random;
+        chip.observer_mut().inner_mut().step = cp.step;
+    }
+
+    /// Forget everything recorded after the current step.
+    fn truncate(&mut self) {
+        let step = self.position();
+        self.checkpoints.retain(|cp| cp.step < step);
+
+        let chip = self.dbg.chip_mut();
+        chip.keypad_mut().truncate();
+        chip.rng_mut().truncate();
+        chip.observer_mut().inner_mut().truncate();
+    }
+
+    /// Checkpoint a modified state so replays start from it.
+    fn settle(&mut self) {
+        if core::mem::take(&mut self.dirty) {
+            self.checkpoints.push(Checkpoint::take(self.dbg.chip()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debug::Breakpoint;
+    use crate::hal::framebuffer::FrameBuffer;
+    use crate::hal::headless::{HeldKeys, NoDelay, NullBuzzer, XorShift};
+    use crate::vm::mem::{self, Load};
+
+    type Machine = Rewind<FrameBuffer, HeldKeys, NullBuzzer, XorShift, NoDelay>;
+
+    fn rewind(rom: &[u16], interval: u64) -> Machine {
+        let mut chip = Chip8::new(
+            FrameBuffer::default(),
+            HeldKeys::default(),
+            NullBuzzer::default(),
+            XorShift::default(),
+            NoDelay,
+        );
+        chip.state_mut().ram.load(0x200, rom).unwrap();
+        chip.init().unwrap();
+
+        let mut rewind = Rewind::new(chip, interval);
+        rewind.debugger_mut().set_limit(Some(1000));
+        rewind
+    }
+
+    #[test]
+    fn deterministic() {
+        let mut rw = rewind(
+            &crate::chip8_asm! {
+                rnd 0, 0x3F;  // 0x200
+                rnd 1, 0x1F;  // 0x202
+                rnd 2, 0x0F;  // 0x204
+                sprite 2;     // 0x206
+                drw 0, 1, 5;  // 0x208
+                skp 3;        // 0x20A
+                add 3, 1;     // 0x20C
+                jp 0x200;     // 0x20E
+            },
+            4,
+        );
+        rw.keypad_mut().press(7);
+
+        let mut history = Vec::new();
+        for _ in 0..30 {
+            history.push((*rw.state(), *rw.debugger().chip().screen()));
+            assert_eq!(rw.step_into().unwrap(), Stop::Step);
+        }
+        history.push((*rw.state(), *rw.debugger().chip().screen()));
+
+        // Inputs now differ, so the replay must come from the recording.
+        rw.keypad_mut().press(3);
+
+        for step in (0..30).rev() {
+            assert_eq!(rw.reverse_step().unwrap(), Stop::Step);
+            assert_eq!(rw.position(), step);
+            assert_eq!(
+                &(*rw.state(), *rw.debugger().chip().screen()),
+                &history[step as usize]
+            );
+        }
+        assert_eq!(rw.reverse_step().unwrap(), Stop::Start);
+
+        for expected in &history[1..] {
+            rw.step_into().unwrap();
+            assert_eq!(&(*rw.state(), *rw.debugger().chip().screen()), expected);
+        }
+
+        // Past the recording the live peripherals are used again, and with
+        // key 4 held SKP stops V3 from counting past 4.
+        rw.keypad_mut().press(4);
+        rw.run_to(0x20E).unwrap();
+        rw.run_to(0x20E).unwrap();
+        assert_eq!(rw.state().reg.get(3).unwrap(), 4);
+    }
+
+    #[test]
+    fn breakpoints_and_writes() {
+        let mut rw = rewind(
+            &crate::chip8_asm! {
+                ld 0, 0;      // 0x200
+                ldi 0x300;    // 0x202
+                add 0, 1;     // 0x204
+                sviv 0;       // 0x206
+                se 0, 10;     // 0x208
+                jp 0x204;     // 0x20A
+                jp 0x20C;     // 0x20C
+            },
+            5,
+        );
+
+        rw.debugger_mut().add_breakpoint(Breakpoint::new(0x206));
+        assert_eq!(
+            rw.cont().unwrap(),
+            Stop::Breakpoint {
+                id: 0,
+                addr: 0x206,
+                hits: 1
+            }
+        );
+        assert_eq!(rw.last_write(0x300), None);
+
+        rw.debugger_mut().remove_breakpoint(0);
+        rw.run_to(0x20C).unwrap();
+
+        let write = rw.last_write(0x300).unwrap();
+        assert_eq!((write.pc, write.value), (0x206, 10));
+
+        let id = rw
+            .debugger_mut()
+            .add_breakpoint(Breakpoint::new(0x206).when("v0 < 5".parse().unwrap()))
+            .unwrap();
+        assert_eq!(
+            rw.reverse_continue().unwrap(),
+            Stop::Breakpoint {
+                id,
+                addr: 0x206,
+                hits: 0
+            }
+        );
+        assert_eq!(rw.state().reg.get(0).unwrap(), 4);
+        assert_eq!(rw.last_write(0x300).unwrap().value, 3);
+
+        rw.debugger_mut().remove_breakpoint(id);
+        assert_eq!(rw.reverse_continue().unwrap(), Stop::Start);
+        assert_eq!(rw.position(), 0);
+        assert_eq!(rw.last_write(0x300), None);
+
+        // Changing the past discards the recorded future.
+        rw.step_into().unwrap();
+        rw.state_mut().reg.set(0, 7).unwrap();
+        rw.run_to(0x20C).unwrap();
+        assert_eq!(rw.journal().writes().len(), 3);
+        assert_eq!(rw.reverse_step().unwrap(), Stop::Step);
+        assert_eq!(rw.last_write(0x300).unwrap().value, 10);
+    }
+
+    #[test]
+    fn step_out_without_frame() {
+        let mut rw = rewind(&crate::chip8_asm! { jp 0x200; }, 4);
+
+        assert_eq!(
+            rw.step_out().unwrap_err(),
+            crate::vm::Error::Memory(mem::Error::StackEmpty)
+        );
+        assert_eq!(rw.position(), 0);
+    }
+}
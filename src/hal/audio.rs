@@ -0,0 +1,366 @@
+//! PCM audio for the buzzer.
+//!
+//! [`Beeper`] is a [`Buzzer`] which synthesizes a square wave while it is on.
+//! [`Chip8::run`](crate::vm::Chip8::run) turns it on while the sound timer
+//! is, and once per 60 Hz frame it writes that frame's samples to a
+//! [`SampleBuffer`] provided by the host, such as a [`RingBuffer`] drained by
+//! the audio device. With the `std` feature, `write_wav` renders samples
+//! to a file instead.
+
+#[cfg(feature = "alloc")]
+use alloc::{collections::VecDeque, vec::Vec};
+use core::convert::Infallible;
+
+use super::Buzzer;
+
+/// Destination for signed 16 bit mono samples.
+pub trait SampleBuffer {
+    /// Append as many of `samples` as fit, returning how many were taken.
+    fn write(&mut self, samples: &[i16]) -> usize;
+}
+
+impl<T: SampleBuffer + ?Sized> SampleBuffer for &mut T {
+    fn write(&mut self, samples: &[i16]) -> usize {
+        (**self).write(samples)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl SampleBuffer for Vec<i16> {
+    fn write(&mut self, samples: &[i16]) -> usize {
+        self.extend_from_slice(samples);
+        samples.len()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl SampleBuffer for VecDeque<i16> {
+    fn write(&mut self, samples: &[i16]) -> usize {
+        self.extend(samples);
+        samples.len()
+    }
+}
+
+/// Shared with an audio callback on another thread.
+#[cfg(feature = "std")]
+impl<T: SampleBuffer> SampleBuffer for std::sync::Arc<std::sync::Mutex<T>> {
+    fn write(&mut self, samples: &[i16]) -> usize {
+        match self.lock() {
+            Ok(mut buffer) => buffer.write(samples),
+            Err(_) => 0,
+        }
+    }
+}
+
+/// Fixed capacity queue of samples. Samples which do not fit are dropped.
+#[derive(Debug, Clone)]
+pub struct RingBuffer<const N: usize> {
+    samples: [i16; N],
+    start: usize,
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    pub fn new() -> Self {
+        Self {
+            samples: [0; N],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn pop(&mut self) -> Option<i16> {
+        (self.len > 0).then(|| {
+            let sample = self.samples[self.start];
+            self.start = (self.start + 1) % N;
+            self.len -= 1;
+            sample
+        })
+    }
+
+    /// Move samples into `out`, returning how many were moved.
+    pub fn read(&mut self, out: &mut [i16]) -> usize {
+        let mut count = 0;
+        for (slot, sample) in out.iter_mut().zip(core::iter::from_fn(|| self.pop())) {
+            *slot = sample;
+            count += 1;
+        }
+        count
+    }
+}
+
+impl<const N: usize> Default for RingBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> SampleBuffer for RingBuffer<N> {
+    fn write(&mut self, samples: &[i16]) -> usize {
+        let count = samples.len().min(N - self.len);
+        for &sample in &samples[..count] {
+            self.samples[(self.start + self.len) % N] = sample;
+            self.len += 1;
+        }
+        count
+    }
+}
+
+/// [`Buzzer`] which generates a square wave into a [`SampleBuffer`].
+#[derive(Debug, Clone)]
+pub struct Beeper<T> {
+    buffer: T,
+    sample_rate: u32,
+    frequency: u32,
+    amplitude: i16,
+    on: bool,
+    /// Position in the current period, a full period is `2^32`.
+    phase: u32,
+    /// Sixtieths of a sample carried over from the last frame.
+    carry: u32,
+}
+
+impl<T: SampleBuffer> Beeper<T> {
+    /// Generate `sample_rate` samples a second, a 440 Hz tone at a quarter
+    /// of full volume by default.
+    pub fn new(buffer: T, sample_rate: u32) -> Self {
+        Self {
+            buffer,
+            sample_rate,
+            frequency: 440,
+            amplitude: i16::MAX / 4,
+            on: false,
+            phase: 0,
+            carry: 0,
+        }
+    }
+
+    pub fn with_frequency(mut self, hz: u32) -> Self {
+        self.frequency = hz;
+        self
+    }
+
+    /// Set the volume, from `0.0` for silence to `1.0` for full scale.
+    pub fn with_volume(mut self, volume: f32) -> Self {
+        self.amplitude = (volume.clamp(0.0, 1.0) * i16::MAX as f32) as i16;
+        self
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    pub fn buffer(&self) -> &T {
+        &self.buffer
+    }
+
+    pub fn buffer_mut(&mut self) -> &mut T {
+        &mut self.buffer
+    }
+
+    pub fn into_inner(self) -> T {
+        self.buffer
+    }
+
+    /// Fill `out` with the next samples, silence while off.
+    pub fn fill(&mut self, out: &mut [i16]) {
+        if !self.on {
+            out.fill(0);
+            return;
+        }
+
+        let step = ((self.frequency as u64) << 32) / self.sample_rate.max(1) as u64;
+        for sample in out {
+            *sample = match self.phase < 1 << 31 {
+                true => self.amplitude,
+                false => -self.amplitude,
+            };
+            self.phase = self.phase.wrapping_add(step as u32);
+        }
+    }
+
+    /// Write one 60 Hz frame of samples to the buffer, returning how many
+    /// it took.
+    pub fn write_frame(&mut self) -> usize {
+        self.carry += self.sample_rate;
+        let mut remaining = (self.carry / 60) as usize;
+        self.carry %= 60;
+
+        let mut chunk = [0; 256];
+        let mut written = 0;
+        while remaining > 0 {
+            let len = remaining.min(chunk.len());
+            self.fill(&mut chunk[..len]);
+            written += self.buffer.write(&chunk[..len]);
+            remaining -= len;
+        }
+        written
+    }
+}
+
+impl<T: SampleBuffer> Buzzer for Beeper<T> {
+    type Error = Infallible;
+
+    /// Start the tone at the beginning of a period.
+    fn on(&mut self) -> Result<(), Self::Error> {
+        if !self.on {
+            self.phase = 0;
+        }
+        self.on = true;
+        Ok(())
+    }
+
+    fn off(&mut self) -> Result<(), Self::Error> {
+        self.on = false;
+        Ok(())
+    }
+
+    fn frame(&mut self) -> Result<(), Self::Error> {
+        self.write_frame();
+        Ok(())
+    }
+}
+
+/// Write `samples` as a 16 bit mono PCM WAV file.
+#[cfg(feature = "std")]
+pub fn write_wav<W: std::io::Write>(
+    mut out: W,
+    sample_rate: u32,
+    samples: &[i16],
+) -> std::io::Result<()> {
+    let data_len = samples.len() as u32 * 2;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    // PCM, one channel
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&sample_rate.to_le_bytes())?;
+    // Bytes per second, bytes per frame and bits per sample
+    out.write_all(&(sample_rate * 2).to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?;
+
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+    for sample in samples {
+        out.write_all(&sample.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn square() {
+        let mut beeper = Beeper::new(RingBuffer::<1024>::new(), 8000)
+            .with_frequency(1000)
+            .with_volume(0.5);
+
+        // Silent until turned on.
+        assert_eq!(beeper.write_frame(), 133);
+        assert!(core::iter::from_fn(|| beeper.buffer_mut().pop()).all(|s| s == 0));
+
+        beeper.on().unwrap();
+        let mut out = [0; 16];
+        beeper.fill(&mut out);
+        let (high, low) = (i16::MAX / 2, -(i16::MAX / 2));
+        assert_eq!(out[..8], [high, high, high, high, low, low, low, low]);
+        assert_eq!(out[..8], out[8..]);
+
+        // Frames carry fractions of a sample, 8000 / 60 = 133.33...
+        let frames: [usize; 3] = core::array::from_fn(|_| beeper.write_frame());
+        assert_eq!(frames, [133, 134, 133]);
+        assert_eq!(beeper.buffer().len(), 400);
+
+        // The ring keeps what fits.
+        for _ in 0..5 {
+            beeper.write_frame();
+        }
+        assert_eq!(beeper.buffer().len(), 1024);
+        let mut out = [0; 2000];
+        assert_eq!(beeper.buffer_mut().read(&mut out), 1024);
+        assert!(beeper.buffer().is_empty());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn sound_timer() {
+        extern crate std;
+
+        use crate::hal::headless::{HeldKeys, NoDelay, NullScreen, XorShift};
+        use crate::vm::mem::Load;
+        use crate::vm::{Chip8, Error};
+
+        let beeper = Beeper::new(Vec::new(), 6000).with_volume(1.0);
+        let mut chip = Chip8::new(
+            NullScreen,
+            HeldKeys::default(),
+            beeper,
+            XorShift::default(),
+            NoDelay,
+        );
+        chip.state_mut()
+            .ram
+            .load(
+                0x200,
+                &crate::chip8_asm! {
+                    ld 0, 6;
+                    ldst 0;
+                    add 1, 1;
+                    sne 1, 0;
+                    jp 0x20C;
+                    jp 0x204;
+                },
+            )
+            .unwrap();
+        chip.init().unwrap();
+
+        // Runs for 256 loops, about a second, then hits empty memory.
+        assert!(matches!(chip.run_at(600, 60), Err(Error::Instruction(_))));
+        assert!(!chip.buzzer().is_on());
+
+        let (_, _, beeper, _, _, _) = chip.free();
+        let samples = beeper.into_inner();
+        assert_eq!(samples.len() % 100, 0);
+        assert!(samples.len() >= 60 * 100);
+
+        // Six frames of tone at the start, then silence.
+        let tone = samples.iter().rposition(|&s| s != 0).unwrap() + 1;
+        assert!((500..=700).contains(&tone), "{tone}");
+        assert!(samples[..tone].iter().all(|s| s.abs() == i16::MAX));
+
+        let mut wav = Vec::new();
+        write_wav(&mut wav, 6000, &samples).unwrap();
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(
+            u32::from_le_bytes(wav[4..8].try_into().unwrap()) as usize,
+            wav.len() - 8
+        );
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 6000);
+        assert_eq!(&wav[36..40], b"data");
+
+        let decoded: Vec<i16> = wav[44..]
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(decoded, samples);
+    }
+}
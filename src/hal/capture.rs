@@ -0,0 +1,657 @@
+//! Screenshots and recordings of a [`FrameBuffer`].
+//!
+//! Pixels are converted to RGB through a [`Palette`]. Single frames are
+//! written as PPM or PNG, and sequences as animated GIFs by [`GifRecorder`].
+//! The encoders are kept minimal: PNG data is stored without compression,
+//! GIF data is LZW compressed as the format requires.
+
+use std::io::{self, Write};
+use std::vec::Vec;
+
+use super::framebuffer::FrameBuffer;
+use super::Resolution;
+
+pub type Rgb = [u8; 3];
+
+/// Colors of unlit and lit pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub off: Rgb,
+    pub on: Rgb,
+}
+
+impl Palette {
+    pub fn color(&self, lit: bool) -> Rgb {
+        if lit {
+            self.on
+        } else {
+            self.off
+        }
+    }
+}
+
+impl Default for Palette {
+    /// White on black.
+    fn default() -> Self {
+        Self {
+            off: [0x00; 3],
+            on: [0xFF; 3],
+        }
+    }
+}
+
+/// Three bytes per pixel, row by row.
+pub fn rgb(frame: &FrameBuffer, palette: &Palette) -> Vec<u8> {
+    frame
+        .pixels()
+        .iter()
+        .flat_map(|&px| palette.color(px == FrameBuffer::ON))
+        .collect()
+}
+
+/// Write a binary PPM (`P6`) image.
+pub fn write_ppm<W: Write>(mut out: W, frame: &FrameBuffer, palette: &Palette) -> io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", frame.width(), frame.height())?;
+    out.write_all(&rgb(frame, palette))
+}
+
+/// Write an 8 bit RGB PNG image.
+pub fn write_png<W: Write>(mut out: W, frame: &FrameBuffer, palette: &Palette) -> io::Result<()> {
+    const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+    let mut header = Vec::with_capacity(13);
+    header.extend((frame.width() as u32).to_be_bytes());
+    header.extend((frame.height() as u32).to_be_bytes());
+    // Bit depth, RGB, deflate, adaptive filtering, not interlaced
+    header.extend([8, 2, 0, 0, 0]);
+
+    // Each scanline starts with its filter type, none.
+    let stride = frame.width() * 3;
+    let mut scanlines = Vec::with_capacity((stride + 1) * frame.height());
+    for line in rgb(frame, palette).chunks(stride) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(line);
+    }
+
+    out.write_all(&SIGNATURE)?;
+    write_chunk(&mut out, b"IHDR", &header)?;
+    write_chunk(&mut out, b"IDAT", &zlib_stored(&scanlines))?;
+    write_chunk(&mut out, b"IEND", &[])
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+
+    let crc = !crc32(crc32(!0, kind), data);
+    out.write_all(&crc.to_be_bytes())
+}
+
+/// Update a CRC-32 (ISO-HDLC) register with `data`.
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    b << 16 | a
+}
+
+/// A zlib stream holding `data` in uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 0xFFFF * 5 + 11);
+    out.extend([0x78, 0x01]);
+
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend([0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        out.push(blocks.peek().is_none() as u8);
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+/// Records the frames of a running machine as a looping animated GIF.
+///
+/// [`frame`](Self::frame) is called once per 60 Hz frame. Runs of identical
+/// frames are written once, shown for as long as they lasted. The size of
+/// the animation is that of the first frame, frames at other resolutions
+/// are rejected.
+#[derive(Debug)]
+pub struct GifRecorder<W> {
+    out: W,
+    palette: Palette,
+    /// The frame waiting for its delay, with the tick it was first shown.
+    pending: Option<(FrameBuffer, u64)>,
+    resolution: Option<Resolution>,
+    ticks: u64,
+}
+
+impl<W: Write> GifRecorder<W> {
+    pub fn new(out: W, palette: Palette) -> Self {
+        Self {
+            out,
+            palette,
+            pending: None,
+            resolution: None,
+            ticks: 0,
+        }
+    }
+
+    /// Number of frames recorded.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Record the display as shown for one 60 Hz frame.
+    pub fn frame(&mut self, frame: &FrameBuffer) -> io::Result<()> {
+        match self.resolution {
+            None => {
+                self.header(frame)?;
+                self.resolution = Some(frame.resolution());
+            }
+            Some(resolution) if resolution != frame.resolution() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "resolution changed while recording",
+                ));
+            }
+            Some(_) => {}
+        }
+
+        match self.pending {
+            Some((ref shown, _)) if shown == frame => {}
+            _ => {
+                self.flush_pending()?;
+                self.pending = Some((*frame, self.ticks));
+            }
+        }
+
+        self.ticks += 1;
+        Ok(())
+    }
+
+    /// Write the last frame and the trailer, returning the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.resolution.is_some() {
+            self.flush_pending()?;
+            self.out.write_all(&[0x3B])?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn header(&mut self, frame: &FrameBuffer) -> io::Result<()> {
+        let (width, height) = (frame.width() as u16, frame.height() as u16);
+
+        self.out.write_all(b"GIF89a")?;
+        self.out.write_all(&width.to_le_bytes())?;
+        self.out.write_all(&height.to_le_bytes())?;
+        // Global color table of two colors, background color 0, no aspect
+        self.out.write_all(&[0x80, 0, 0])?;
+        self.out.write_all(&self.palette.off)?;
+        self.out.write_all(&self.palette.on)?;
+        // Loop forever
+        self.out
+            .write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")
+    }
+
+    /// Write the pending frame, shown until the current tick.
+    fn flush_pending(&mut self) -> io::Result<()> {
+        let Some((frame, start)) = self.pending.take() else {
+            return Ok(());
+        };
+
+        // Delays are in hundredths of a second. Rounding each frame's start
+        // and end, rather than its length, keeps the animation in time.
+        let centis = |tick: u64| tick * 100 / 60;
+        let delay = (centis(self.ticks) - centis(start)).min(u16::MAX as u64) as u16;
+
+        self.out.write_all(&[0x21, 0xF9, 4, 0])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0, 0])?;
+
+        self.out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        self.out.write_all(&(frame.width() as u16).to_le_bytes())?;
+        self.out.write_all(&(frame.height() as u16).to_le_bytes())?;
+        self.out.write_all(&[0])?;
+
+        let indices: Vec<u8> = frame.pixels().iter().map(|&px| (px != 0) as u8).collect();
+        self.out.write_all(&[MIN_CODE_SIZE])?;
+        for block in lzw(&indices).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0])
+    }
+}
+
+/// The smallest code size GIF allows, enough for two colors.
+const MIN_CODE_SIZE: u8 = 2;
+
+/// Largest code size in a GIF LZW stream.
+const MAX_CODE_SIZE: u32 = 12;
+
+/// Packs variable width codes, least significant bit first.
+struct Bits {
+    out: Vec<u8>,
+    acc: u32,
+    len: u32,
+}
+
+impl Bits {
+    fn push(&mut self, code: u16, width: u32) {
+        self.acc |= (code as u32) << self.len;
+        self.len += width;
+        while self.len >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+/// LZW compress color indices for a GIF image.
+fn lzw(indices: &[u8]) -> Vec<u8> {
+    let clear = 1u16 << MIN_CODE_SIZE;
+    let end = clear + 1;
+
+    // Codes for a prefix code followed by each of the 4 possible indices.
+    let mut table = Vec::new();
+    let reset = |table: &mut Vec<[u16; 4]>| {
+        table.clear();
+        table.resize(end as usize + 1, [0; 4]);
+    };
+    reset(&mut table);
+
+    let mut bits = Bits {
+        out: Vec::new(),
+        acc: 0,
+        len: 0,
+    };
+    let mut width = MIN_CODE_SIZE as u32 + 1;
+    bits.push(clear, width);
+
+    let mut prefix: Option<u16> = None;
+    for &index in indices {
+        let Some(code) = prefix else {
+            prefix = Some(index as u16);
+            continue;
+        };
+
+        match table[code as usize][index as usize] {
+            0 => {
+                bits.push(code, width);
+
+                let next = table.len() as u16;
+                if next as u32 == 1 << MAX_CODE_SIZE {
+                    bits.push(clear, width);
+                    reset(&mut table);
+                    width = MIN_CODE_SIZE as u32 + 1;
+                } else {
+                    table[code as usize][index as usize] = next;
+                    table.push([0; 4]);
+                    // The decoder widens its codes one code later.
+                    if next as u32 == 1 << width {
+                        width += 1;
+                    }
+                }
+                prefix = Some(index as u16);
+            }
+            longer => prefix = Some(longer),
+        }
+    }
+
+    if let Some(code) = prefix {
+        bits.push(code, width);
+    }
+    bits.push(end, width);
+    bits.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec;
+
+    use super::*;
+
+    const PALETTE: Palette = Palette {
+        off: [0x10, 0x20, 0x30],
+        on: [0xE0, 0xD0, 0xC0],
+    };
+
+    fn frame(sprites: &[(u8, u8, &[u8])]) -> FrameBuffer {
+        let mut fb = FrameBuffer::new();
+        for &(x, y, data) in sprites {
+            fb.draw_sprite(x, y, data);
+        }
+        fb
+    }
+
+    fn be32(data: &[u8]) -> u32 {
+        u32::from_be_bytes(data[..4].try_into().unwrap())
+    }
+
+    fn le16(data: &[u8]) -> usize {
+        u16::from_le_bytes([data[0], data[1]]) as usize
+    }
+
+    /// Decode a PNG as written by `write_png`, returning its size and RGB data.
+    fn decode_png(png: &[u8]) -> (usize, usize, Vec<u8>) {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1A\n");
+
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = be32(rest) as usize;
+            let (kind, data) = (&rest[4..8], &rest[8..8 + len]);
+            assert_eq!(be32(&rest[8 + len..]), !crc32(crc32(!0, kind), data));
+            chunks.push((kind, data));
+            rest = &rest[12 + len..];
+        }
+
+        let kinds: Vec<_> = chunks.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        let header = chunks[0].1;
+        let (width, height) = (be32(header) as usize, be32(&header[4..]) as usize);
+        assert_eq!(&header[8..], [8, 2, 0, 0, 0]);
+
+        let data = inflate_stored(chunks[1].1);
+        let mut rgb = Vec::new();
+        for line in data.chunks(width * 3 + 1) {
+            assert_eq!(line[0], 0);
+            rgb.extend_from_slice(&line[1..]);
+        }
+        assert_eq!(rgb.len(), width * height * 3);
+        (width, height, rgb)
+    }
+
+    /// Decompress a zlib stream made of stored deflate blocks.
+    fn inflate_stored(zlib: &[u8]) -> Vec<u8> {
+        assert_eq!(u16::from_be_bytes([zlib[0], zlib[1]]) % 31, 0);
+        let mut data = Vec::new();
+        let mut pos = 2;
+        loop {
+            let last = zlib[pos] & 1 != 0;
+            assert_eq!(zlib[pos] >> 1, 0, "not a stored block");
+            let len = le16(&zlib[pos + 1..]);
+            assert_eq!(len, !le16(&zlib[pos + 3..]) & 0xFFFF);
+            data.extend_from_slice(&zlib[pos + 5..pos + 5 + len]);
+            pos += 5 + len;
+            if last {
+                break;
+            }
+        }
+        assert_eq!(be32(&zlib[pos..]), adler32(&data));
+        assert_eq!(pos + 4, zlib.len());
+        data
+    }
+
+    struct Gif {
+        width: usize,
+        height: usize,
+        colors: Vec<Rgb>,
+        /// Delay and color indices of each frame.
+        frames: Vec<(usize, Vec<u8>)>,
+    }
+
+    fn decode_lzw(min: u8, data: &[u8]) -> Vec<u8> {
+        let clear = 1usize << min;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut width = min as u32 + 1;
+        let mut prev: Option<usize> = None;
+        let mut out = Vec::new();
+        let (mut acc, mut len, mut bytes) = (0u32, 0u32, data.iter());
+
+        loop {
+            while len < width {
+                acc |= (*bytes.next().unwrap() as u32) << len;
+                len += 8;
+            }
+            let code = (acc & ((1 << width) - 1)) as usize;
+            acc >>= width;
+            len -= width;
+
+            if code == clear {
+                table = (0..clear + 2).map(|n| vec![n as u8]).collect();
+                width = min as u32 + 1;
+                prev = None;
+                continue;
+            }
+            if code == clear + 1 {
+                return out;
+            }
+
+            let entry = match (table.get(code), prev) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(prev)) if code == table.len() => {
+                    let mut entry = table[prev].clone();
+                    entry.push(entry[0]);
+                    entry
+                }
+                _ => panic!("bad code {code}"),
+            };
+            if let Some(prev) = prev {
+                let mut new = table[prev].clone();
+                new.push(entry[0]);
+                table.push(new);
+                if table.len() == 1 << width && width < MAX_CODE_SIZE {
+                    width += 1;
+                }
+            }
+            out.extend_from_slice(&entry);
+            prev = Some(code);
+        }
+    }
+
+    fn decode_gif(gif: &[u8]) -> Gif {
+        assert_eq!(&gif[..6], b"GIF89a");
+        let (width, height) = (le16(&gif[6..]), le16(&gif[8..]));
+        let flags = gif[10];
+        assert_ne!(flags & 0x80, 0);
+        let count = 2 << (flags & 7);
+        let colors = gif[13..13 + count * 3]
+            .chunks(3)
+            .map(|c| [c[0], c[1], c[2]])
+            .collect();
+
+        let mut pos = 13 + count * 3;
+        let mut frames = Vec::new();
+        let mut delay = 0;
+        let sub_blocks = |pos: &mut usize| {
+            let mut data = Vec::new();
+            while gif[*pos] != 0 {
+                let len = gif[*pos] as usize;
+                data.extend_from_slice(&gif[*pos + 1..*pos + 1 + len]);
+                *pos += 1 + len;
+            }
+            *pos += 1;
+            data
+        };
+
+        loop {
+            match gif[pos] {
+                0x21 => {
+                    let label = gif[pos + 1];
+                    pos += 2;
+                    let data = sub_blocks(&mut pos);
+                    if label == 0xF9 {
+                        delay = le16(&data[1..]);
+                    }
+                }
+                0x2C => {
+                    assert_eq!(
+                        (le16(&gif[pos + 5..]), le16(&gif[pos + 7..])),
+                        (width, height)
+                    );
+                    assert_eq!(gif[pos + 9], 0);
+                    let min = gif[pos + 10];
+                    pos += 11;
+                    let indices = decode_lzw(min, &sub_blocks(&mut pos));
+                    assert_eq!(indices.len(), width * height);
+                    frames.push((delay, indices));
+                }
+                0x3B => break,
+                other => panic!("unexpected block {other:02X}"),
+            }
+        }
+        assert_eq!(pos + 1, gif.len());
+
+        Gif {
+            width,
+            height,
+            colors,
+            frames,
+        }
+    }
+
+    /// Decode a PPM as written by `write_ppm`, returning its size and RGB data.
+    fn decode_ppm(ppm: &[u8]) -> (usize, usize, Vec<u8>) {
+        let mut fields = Vec::new();
+        let mut pos = 0;
+        while fields.len() < 4 {
+            let start = pos;
+            while !ppm[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            fields.push(std::str::from_utf8(&ppm[start..pos]).unwrap());
+            pos += 1;
+        }
+        assert_eq!(fields[0], "P6");
+        assert_eq!(fields[3], "255");
+
+        let width = fields[1].parse().unwrap();
+        let height = fields[2].parse().unwrap();
+        let pixels = ppm[pos..].to_vec();
+        assert_eq!(pixels.len(), width * height * 3);
+        (width, height, pixels)
+    }
+
+    #[test]
+    fn ppm() {
+        let mut fb = frame(&[(1, 0, &[0x80]), (60, 31, &[0xF0])]);
+        let mut out = Vec::new();
+        write_ppm(&mut out, &fb, &PALETTE).unwrap();
+        assert_eq!(decode_ppm(&out), (64, 32, rgb(&fb, &PALETTE)));
+
+        let (_, _, pixels) = decode_ppm(&out);
+        assert_eq!(pixels[..6], [0x10, 0x20, 0x30, 0xE0, 0xD0, 0xC0]);
+
+        fb.set_resolution(Resolution::Extended);
+        fb.draw_sprite(127, 63, &[0x80]);
+        let mut out = Vec::new();
+        write_ppm(&mut out, &fb, &PALETTE).unwrap();
+        assert_eq!(decode_ppm(&out), (128, 64, rgb(&fb, &PALETTE)));
+    }
+
+    #[test]
+    fn png() {
+        let mut fb = frame(&[(0, 0, &[0xA0]), (60, 31, &[0xF0])]);
+        let mut out = Vec::new();
+        write_png(&mut out, &fb, &PALETTE).unwrap();
+        assert_eq!(decode_png(&out), (64, 32, rgb(&fb, &PALETTE)));
+
+        fb.set_resolution(Resolution::Extended);
+        fb.draw_sprite(127, 63, &[0x80]);
+        let mut out = Vec::new();
+        write_png(&mut out, &fb, &Palette::default()).unwrap();
+
+        let (width, height, rgb) = decode_png(&out);
+        assert_eq!((width, height), (128, 64));
+        assert_eq!(rgb[rgb.len() - 3..], [0xFF; 3]);
+        assert!(rgb[..rgb.len() - 3].iter().all(|&c| c == 0));
+
+        for len in [0, 10, 0xFFFF, 70_000] {
+            let data: Vec<u8> = (0..len).map(|n| n as u8).collect();
+            assert_eq!(inflate_stored(&zlib_stored(&data)), data);
+        }
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(!crc32(!0, b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn gif() {
+        let blank = FrameBuffer::new();
+        let sprite = frame(&[(3, 2, &[0xFF, 0x81, 0xFF])]);
+        let mut busy = FrameBuffer::new();
+        for n in 0..64u8 {
+            busy.draw_sprite(n.wrapping_mul(7), n * 3, &[n, n ^ 0x5A, !n]);
+        }
+
+        let mut recorder = GifRecorder::new(Vec::new(), PALETTE);
+        for fb in [&blank; 3]
+            .into_iter()
+            .chain([&sprite; 40])
+            .chain([&busy; 2])
+            .chain([&blank; 1])
+        {
+            recorder.frame(fb).unwrap();
+        }
+        recorder.frame(&frame(&[])).unwrap();
+        assert_eq!(recorder.ticks(), 47);
+
+        let mut wrong = FrameBuffer::new();
+        wrong.set_resolution(Resolution::Hires);
+        assert_eq!(
+            recorder.frame(&wrong).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        let gif = decode_gif(&recorder.finish().unwrap());
+        assert_eq!((gif.width, gif.height), (64, 32));
+        assert_eq!(gif.colors, [PALETTE.off, PALETTE.on]);
+
+        // Ticks 0-3, 3-43, 43-45 and 45-47 at 60 Hz, in 1/100 s.
+        let delays: Vec<_> = gif.frames.iter().map(|(delay, _)| *delay).collect();
+        assert_eq!(delays, [5, 66, 4, 3]);
+
+        for ((_, indices), fb) in gif.frames.iter().zip([&blank, &sprite, &busy, &blank]) {
+            let expected: Vec<u8> = fb.pixels().iter().map(|&px| (px != 0) as u8).collect();
+            assert_eq!(indices, &expected);
+        }
+
+        // Nothing recorded, nothing written.
+        let empty = GifRecorder::new(Vec::new(), PALETTE).finish().unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn long_lzw() {
+        // Enough varied data to fill the code table and start again.
+        let mut state = 1u32;
+        let indices: Vec<u8> = (0..64 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state & 1) as u8
+            })
+            .collect();
+
+        assert_eq!(decode_lzw(MIN_CODE_SIZE, &lzw(&indices)), indices);
+    }
+}
@@ -0,0 +1,442 @@
+//! Object-safe versions of the HAL traits, so that peripherals can be chosen
+//! at runtime.
+//!
+//! Every peripheral implements the matching `Dyn` trait, with its errors
+//! converted into a common type `E`. References to the `Dyn` traits, and
+//! boxes with the `alloc` feature, implement the original traits again so
+//! they can be passed to [`Chip8`]:
+//!
+//! ```
+//! # #[cfg(feature = "alloc")] {
+//! # extern crate alloc;
+//! use alloc::boxed::Box;
+//! use chip8::hal::dynamic::{BoxedChip8, DynScreen};
+//! use chip8::hal::framebuffer::FrameBuffer;
+//! use chip8::hal::headless::{HeldKeys, NoDelay, NullBuzzer, NullScreen, XorShift};
+//!
+//! let headless = true;
+//! let screen: Box<dyn DynScreen> = match headless {
+//!     true => Box::new(NullScreen),
+//!     false => Box::new(FrameBuffer::new()),
+//! };
+//!
+//! let chip = BoxedChip8::boxed(
+//!     screen,
+//!     Box::new(HeldKeys::default()),
+//!     Box::new(NullBuzzer::default()),
+//!     Box::new(XorShift::default()),
+//!     Box::new(NoDelay),
+//! );
+//! # }
+//! ```
+//!
+//! [`Chip8`]: crate::vm::Chip8
+
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+
+#[cfg(feature = "video")]
+use super::framebuffer::{FrameBuffer, Region};
+use super::{Buzzer, Delay, Error, KeyState, Keys, Resolution, Rng, Screen};
+#[cfg(feature = "alloc")]
+use crate::vm::Chip8;
+
+/// A [`Chip8`] with boxed peripherals, whose errors are converted to `E`.
+#[cfg(feature = "alloc")]
+pub type BoxedChip8<'a, E = Error> = Chip8<
+    Box<dyn DynScreen<E> + 'a>,
+    Box<dyn DynKeypad<E> + 'a>,
+    Box<dyn DynBuzzer<E> + 'a>,
+    Box<dyn DynRng<E> + 'a>,
+    Box<dyn DynDelay<E> + 'a>,
+>;
+
+#[cfg(feature = "alloc")]
+impl<'a, E: From<Error>> BoxedChip8<'a, E> {
+    pub fn boxed(
+        screen: Box<dyn DynScreen<E> + 'a>,
+        keypad: Box<dyn DynKeypad<E> + 'a>,
+        buzzer: Box<dyn DynBuzzer<E> + 'a>,
+        rng: Box<dyn DynRng<E> + 'a>,
+        delay: Box<dyn DynDelay<E> + 'a>,
+    ) -> Self {
+        Chip8::new(screen, keypad, buzzer, rng, delay)
+    }
+}
+
+/// Object-safe [`Screen`].
+pub trait DynScreen<E = Error> {
+    fn draw(&mut self, x: u8, y: u8, data: &[u8]) -> Result<bool, E>;
+    fn draw_wide(&mut self, x: u8, y: u8, data: &[u16]) -> Result<bool, E>;
+    fn clear(&mut self) -> Result<(), E>;
+    fn set_resolution(&mut self, resolution: Resolution) -> Result<(), E>;
+    fn present(&mut self) -> Result<(), E>;
+    #[cfg(feature = "video")]
+    fn presents_frame(&self) -> bool;
+    #[cfg(feature = "video")]
+    fn present_frame(&mut self, frame: &FrameBuffer, regions: &[Region]) -> Result<(), E>;
+}
+
+/// Object-safe [`KeyState`], taking the delay as a trait object. Every
+/// [`Keypad`](super::Keypad) is one as well.
+pub trait DynKeypad<E = Error> {
+    fn keys(&mut self, delay: &mut dyn DynDelay<E>, held: Keys) -> Result<Keys, E>;
+}
+
+/// Object-safe [`Buzzer`].
+pub trait DynBuzzer<E = Error> {
+    fn on(&mut self) -> Result<(), E>;
+    fn off(&mut self) -> Result<(), E>;
+    fn frame(&mut self) -> Result<(), E>;
+}
+
+/// Object-safe [`Rng`].
+pub trait DynRng<E = Error> {
+    fn random(&mut self) -> Result<u8, E>;
+}
+
+/// Object-safe [`Delay`].
+pub trait DynDelay<E = Error> {
+    fn delay_us(&mut self, us: u32) -> Result<(), E>;
+}
+
+impl<T: Screen, E> DynScreen<E> for T
+where
+    T::Error: Into<E>,
+{
+    fn draw(&mut self, x: u8, y: u8, data: &[u8]) -> Result<bool, E> {
+        Screen::draw(self, x, y, data).map_err(Into::into)
+    }
+
+    fn draw_wide(&mut self, x: u8, y: u8, data: &[u16]) -> Result<bool, E> {
+        Screen::draw_wide(self, x, y, data).map_err(Into::into)
+    }
+
+    fn clear(&mut self) -> Result<(), E> {
+        Screen::clear(self).map_err(Into::into)
+    }
+
+    fn set_resolution(&mut self, resolution: Resolution) -> Result<(), E> {
+        Screen::set_resolution(self, resolution).map_err(Into::into)
+    }
+
+    fn present(&mut self) -> Result<(), E> {
+        Screen::present(self).map_err(Into::into)
+    }
+
+    #[cfg(feature = "video")]
+    fn presents_frame(&self) -> bool {
+        Screen::presents_frame(self)
+    }
+
+    #[cfg(feature = "video")]
+    fn present_frame(&mut self, frame: &FrameBuffer, regions: &[Region]) -> Result<(), E> {
+        Screen::present_frame(self, frame, regions).map_err(Into::into)
+    }
+}
+
+impl<T: KeyState, E> DynKeypad<E> for T
+where
+    T::Error: Into<E>,
+{
+    fn keys(&mut self, mut delay: &mut dyn DynDelay<E>, held: Keys) -> Result<Keys, E> {
+        KeyState::keys(self, &mut delay, held).map_err(Into::into)
+    }
+}
+
+impl<T: Buzzer, E> DynBuzzer<E> for T
+where
+    T::Error: Into<E>,
+{
+    fn on(&mut self) -> Result<(), E> {
+        Buzzer::on(self).map_err(Into::into)
+    }
+
+    fn off(&mut self) -> Result<(), E> {
+        Buzzer::off(self).map_err(Into::into)
+    }
+
+    fn frame(&mut self) -> Result<(), E> {
+        Buzzer::frame(self).map_err(Into::into)
+    }
+}
+
+impl<T: Rng, E> DynRng<E> for T
+where
+    T::Error: Into<E>,
+{
+    fn random(&mut self) -> Result<u8, E> {
+        Rng::random(self).map_err(Into::into)
+    }
+}
+
+impl<T: Delay, E> DynDelay<E> for T
+where
+    T::Error: Into<E>,
+{
+    fn delay_us(&mut self, us: u32) -> Result<(), E> {
+        Delay::delay_us(self, us).map_err(Into::into)
+    }
+}
+
+/// A [`Delay`] of any type passed to a [`DynKeypad`]. Its errors are
+/// reported as [`Error::Delay`], as the original type cannot be named.
+struct Erased<'a, D>(&'a mut D);
+
+impl<D: Delay, E: From<Error>> DynDelay<E> for Erased<'_, D> {
+    fn delay_us(&mut self, us: u32) -> Result<(), E> {
+        self.0.delay_us(us).map_err(|_| Error::Delay.into())
+    }
+}
+
+/// Implement the HAL traits for a pointer to the `Dyn` traits.
+macro_rules! forward_screen {
+    ($($ptr: ty),+) => {$(
+        impl<E> Screen for $ptr {
+            type Error = E;
+
+            fn draw(&mut self, x: u8, y: u8, data: &[u8]) -> Result<bool, E> {
+                (**self).draw(x, y, data)
+            }
+
+            fn draw_wide(&mut self, x: u8, y: u8, data: &[u16]) -> Result<bool, E> {
+                (**self).draw_wide(x, y, data)
+            }
+
+            fn clear(&mut self) -> Result<(), E> {
+                (**self).clear()
+            }
+
+            fn set_resolution(&mut self, resolution: Resolution) -> Result<(), E> {
+                (**self).set_resolution(resolution)
+            }
+
+            fn present(&mut self) -> Result<(), E> {
+                (**self).present()
+            }
+
+            #[cfg(feature = "video")]
+            fn presents_frame(&self) -> bool {
+                (**self).presents_frame()
+            }
+
+            #[cfg(feature = "video")]
+            fn present_frame(&mut self, frame: &FrameBuffer, regions: &[Region]) -> Result<(), E> {
+                (**self).present_frame(frame, regions)
+            }
+        }
+    )+};
+}
+
+forward_screen!(&mut (dyn DynScreen<E> + '_));
+#[cfg(feature = "alloc")]
+forward_screen!(Box<dyn DynScreen<E> + '_>);
+
+macro_rules! forward_keypad {
+    ($($ptr: ty),+) => {$(
+        impl<E: From<Error>> KeyState for $ptr {
+            type Error = E;
+
+            fn keys<D: Delay>(&mut self, delay: &mut D, held: Keys) -> Result<Keys, E> {
+                (**self).keys(&mut Erased(delay), held)
+            }
+        }
+    )+};
+}
+
+forward_keypad!(&mut (dyn DynKeypad<E> + '_));
+#[cfg(feature = "alloc")]
+forward_keypad!(Box<dyn DynKeypad<E> + '_>);
+
+macro_rules! forward_buzzer {
+    ($($ptr: ty),+) => {$(
+        impl<E> Buzzer for $ptr {
+            type Error = E;
+
+            fn on(&mut self) -> Result<(), E> {
+                (**self).on()
+            }
+
+            fn off(&mut self) -> Result<(), E> {
+                (**self).off()
+            }
+
+            fn frame(&mut self) -> Result<(), E> {
+                (**self).frame()
+            }
+        }
+    )+};
+}
+
+forward_buzzer!(&mut (dyn DynBuzzer<E> + '_));
+#[cfg(feature = "alloc")]
+forward_buzzer!(Box<dyn DynBuzzer<E> + '_>);
+
+macro_rules! forward_rng {
+    ($($ptr: ty),+) => {$(
+        impl<E> Rng for $ptr {
+            type Error = E;
+
+            fn random(&mut self) -> Result<u8, E> {
+                (**self).random()
+            }
+        }
+    )+};
+}
+
+forward_rng!(&mut (dyn DynRng<E> + '_));
+#[cfg(feature = "alloc")]
+forward_rng!(Box<dyn DynRng<E> + '_>);
+
+macro_rules! forward_delay {
+    ($($ptr: ty),+) => {$(
+        impl<E> Delay for $ptr {
+            type Error = E;
+
+            fn delay_us(&mut self, us: u32) -> Result<(), E> {
+                (**self).delay_us(us)
+            }
+        }
+    )+};
+}
+
+forward_delay!(&mut (dyn DynDelay<E> + '_));
+#[cfg(feature = "alloc")]
+forward_delay!(Box<dyn DynDelay<E> + '_>);
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use alloc::boxed::Box;
+
+    use super::*;
+    use crate::hal::framebuffer::FrameBuffer;
+    use crate::hal::headless::{HeldKeys, NoDelay, NullBuzzer, NullScreen, XorShift};
+    use crate::hal::{Fault, Keypad};
+    use crate::vm::mem::Load;
+    use crate::vm::{Chip8, Error as VmError};
+
+    /// Keypad which waits before reading a key, and fails if the delay
+    /// does. Key 5 is pressed on one read and released on the next.
+    #[derive(Default)]
+    struct Slow(bool);
+
+    impl Keypad for Slow {
+        type Error = Error;
+
+        fn key_is_pressed(&self) -> Result<bool, Error> {
+            Ok(false)
+        }
+
+        fn read_key<D: Delay>(&mut self, delay: &mut D) -> Result<Option<u8>, Error> {
+            delay.delay_us(100).map_err(|_| Error::Keypad)?;
+            self.0 = !self.0;
+            Ok(self.0.then_some(5))
+        }
+    }
+
+    struct Broken;
+
+    impl Delay for Broken {
+        type Error = Error;
+
+        fn delay_us(&mut self, _us: u32) -> Result<(), Error> {
+            Err(Error::Delay)
+        }
+    }
+
+    fn machine(
+        fb: bool,
+        keypad: Box<dyn DynKeypad>,
+        delay: Box<dyn DynDelay>,
+    ) -> BoxedChip8<'static> {
+        let screen: Box<dyn DynScreen> = match fb {
+            true => Box::new(FrameBuffer::new()),
+            false => Box::new(NullScreen),
+        };
+
+        let mut chip = BoxedChip8::boxed(
+            screen,
+            keypad,
+            Box::new(NullBuzzer::default()),
+            Box::new(XorShift::default()),
+            delay,
+        );
+        chip.state_mut()
+            .ram
+            .load(
+                0x200,
+                &crate::chip8_asm! {
+                    ldi 0x208;
+                    drw 0, 0, 1;
+                    drw 0, 0, 1;
+                    ldkey 1;
+                    jp 0xFF0;
+                },
+            )
+            .unwrap();
+        chip.init().unwrap();
+        chip
+    }
+
+    #[test]
+    fn boxed() {
+        for (fb, collision) in [(true, 1), (false, 0)] {
+            let mut chip = machine(fb, Box::new(Slow::default()), Box::new(NoDelay));
+            for _ in 0..5 {
+                chip.step().unwrap();
+            }
+
+            assert_eq!(chip.state().reg.get(0xF).unwrap(), collision);
+            assert_eq!(chip.state().reg.get(1).unwrap(), 5);
+        }
+
+        let mut chip = machine(false, Box::new(HeldKeys::default()), Box::new(Broken));
+        for _ in 0..3 {
+            chip.step().unwrap();
+        }
+        assert_eq!(
+            chip.step().unwrap_err(),
+            VmError::Peripheral(Fault::Delay(Error::Delay))
+        );
+
+        let mut chip = machine(false, Box::new(Slow::default()), Box::new(Broken));
+        for _ in 0..3 {
+            chip.step().unwrap();
+        }
+        assert_eq!(
+            chip.step().unwrap_err(),
+            VmError::Peripheral(Fault::Keypad(Error::Keypad))
+        );
+    }
+
+    #[test]
+    fn borrowed() {
+        let mut fb = FrameBuffer::new();
+        let mut rng = XorShift::new(7);
+        let screen: &mut dyn DynScreen = &mut fb;
+        let random: &mut dyn DynRng = &mut rng;
+
+        let mut chip = Chip8::new(
+            screen,
+            HeldKeys::default(),
+            NullBuzzer::default(),
+            random,
+            NoDelay,
+        );
+        chip.state_mut()
+            .ram
+            .load(
+                0x200,
+                &crate::chip8_asm! { ldi 0x200; rnd 0, 0x3F; drw 0, 1, 1; },
+            )
+            .unwrap();
+        chip.init().unwrap();
+        for _ in 0..3 {
+            chip.step().unwrap();
+        }
+
+        let x = chip.state().reg.get(0).unwrap() as usize;
+        let _ = chip.free();
+        assert!(fb.pixel(x, 0));
+    }
+}
@@ -0,0 +1,534 @@
+use core::fmt;
+
+use super::{Error, Resolution, Screen};
+
+/// What happens to sprite pixels which fall past the edge of the display.
+/// The starting position always wraps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Edge {
+    /// Pixels past the edge are not drawn.
+    #[default]
+    Clip,
+    /// Pixels past the edge continue on the opposite side.
+    Wrap,
+}
+
+/// Rectangle of pixels which changed between two frames.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Region {
+    pub x: u8,
+    pub y: u8,
+    pub width: u8,
+    pub height: u8,
+}
+
+/// Screen which keeps its pixels in memory, XORs sprites exactly and
+/// reports real collisions. Every [`Resolution`] is supported, starting at
+/// 64x32.
+///
+/// Pixels are available as rows of bits, and as one byte per pixel (`0x00`
+/// off, `0xFF` on, row by row) for hosts to blit. The rows last marked as
+/// presented are kept as well, so that only the [`dirty`](Self::dirty) parts
+/// of a frame need to be redrawn.
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(into = "RawFrame", try_from = "RawFrame"))]
+pub struct FrameBuffer {
+    rows: [u128; Resolution::MAX_HEIGHT],
+    pixels: [u8; Resolution::MAX_WIDTH * Resolution::MAX_HEIGHT],
+    presented: [u128; Resolution::MAX_HEIGHT],
+    resolution: Resolution,
+    edge: Edge,
+}
+
+impl FrameBuffer {
+    /// Value of a lit pixel in [`pixels`](Self::pixels).
+    pub const ON: u8 = 0xFF;
+
+    pub fn new() -> Self {
+        Self::with_edge(Edge::Clip)
+    }
+
+    pub fn with_edge(edge: Edge) -> Self {
+        Self {
+            rows: [0; Resolution::MAX_HEIGHT],
+            pixels: [0; Resolution::MAX_WIDTH * Resolution::MAX_HEIGHT],
+            presented: [0; Resolution::MAX_HEIGHT],
+            resolution: Resolution::Lores,
+            edge,
+        }
+    }
+
+    pub fn edge(&self) -> Edge {
+        self.edge
+    }
+
+    pub fn set_edge(&mut self, edge: Edge) {
+        self.edge = edge;
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    /// Switch to `resolution`, turning every pixel off. The whole display is
+    /// dirty afterwards, so hosts can resize before drawing.
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        self.resolution = resolution;
+        self.reset();
+        let (height, mask) = (self.height(), self.mask());
+        self.presented = [0; Resolution::MAX_HEIGHT];
+        self.presented[..height].fill(mask);
+    }
+
+    pub fn width(&self) -> usize {
+        self.resolution.width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.resolution.height()
+    }
+
+    /// Rows of pixels, the most significant bit is the leftmost pixel.
+    pub fn rows(&self) -> &[u128] {
+        &self.rows[..self.height()]
+    }
+
+    /// One byte per pixel, row by row.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels[..self.width() * self.height()]
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        x < self.width() && y < self.height() && self.rows[y] & (1 << (127 - x)) != 0
+    }
+
+    /// XOR a sprite into the display, returning whether any pixel was erased.
+    pub fn draw_sprite(&mut self, x: u8, y: u8, data: &[u8]) -> bool {
+        let lines = data.iter().map(|&byte| (byte as u128) << 120);
+        self.draw_lines(x, y, lines)
+    }
+
+    /// XOR a sprite 16 pixels wide into the display, returning whether any
+    /// pixel was erased.
+    pub fn draw_wide_sprite(&mut self, x: u8, y: u8, data: &[u16]) -> bool {
+        let lines = data.iter().map(|&row| (row as u128) << 112);
+        self.draw_lines(x, y, lines)
+    }
+
+    /// Turn every pixel off.
+    pub fn reset(&mut self) {
+        self.rows = [0; Resolution::MAX_HEIGHT];
+        self.pixels = [0; Resolution::MAX_WIDTH * Resolution::MAX_HEIGHT];
+    }
+
+    /// Whether any pixel differs from the last presented frame.
+    pub fn is_dirty(&self) -> bool {
+        self.rows != self.presented
+    }
+
+    /// Regions which differ from the last presented frame, top to bottom.
+    /// Changed rows are merged with the rows below them while their changed
+    /// columns overlap, so a sprite moving across the display is one region.
+    pub fn dirty(&self) -> Dirty<'_> {
+        Dirty { fb: self, y: 0 }
+    }
+
+    /// Record the current pixels as shown, so they are no longer dirty.
+    pub fn mark_presented(&mut self) {
+        self.presented = self.rows;
+    }
+
+    /// Columns `[start, end)` of row `y` which changed since the last
+    /// presented frame.
+    fn changed(&self, y: usize) -> Option<(u32, u32)> {
+        let diff = self.rows[y] ^ self.presented[y];
+        (diff != 0).then(|| (diff.leading_zeros(), 128 - diff.trailing_zeros()))
+    }
+
+    /// Bits of a row which are on the display.
+    fn mask(&self) -> u128 {
+        !u128::MAX.checked_shr(self.width() as u32).unwrap_or(0)
+    }
+
+    /// XOR left aligned lines of a sprite into the display.
+    fn draw_lines(&mut self, x: u8, y: u8, lines: impl Iterator<Item = u128>) -> bool {
+        let (width, height) = (self.width(), self.height());
+        let (x, y) = (x as usize % width, y as usize % height);
+        let mut erased = false;
+
+        for (dy, line) in lines.enumerate() {
+            let (row, sprite) = match self.edge {
+                Edge::Clip if y + dy >= height => break,
+                Edge::Clip => (y + dy, line >> x),
+                Edge::Wrap => (
+                    (y + dy) % height,
+                    line >> x | line.checked_shl((width - x) as u32).unwrap_or(0),
+                ),
+            };
+
+            erased |= self.xor(row, sprite & self.mask());
+        }
+
+        erased
+    }
+
+    /// XOR `sprite` into row `y`, returning whether any pixel was erased.
+    fn xor(&mut self, y: usize, sprite: u128) -> bool {
+        let width = self.width();
+        let row = &mut self.rows[y];
+        let erased = *row & sprite != 0;
+        *row ^= sprite;
+
+        let pixels = &mut self.pixels[y * width..][..width];
+        let mut bits = sprite;
+        while bits != 0 {
+            let x = bits.leading_zeros() as usize;
+            pixels[x] ^= Self::ON;
+            bits &= !(1 << (127 - x));
+        }
+
+        erased
+    }
+}
+
+impl Default for FrameBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PartialEq for FrameBuffer {
+    fn eq(&self, other: &Self) -> bool {
+        self.rows == other.rows && self.resolution == other.resolution && self.edge == other.edge
+    }
+}
+
+impl Eq for FrameBuffer {}
+
+/// Serialized form of a [`FrameBuffer`]. Only the pixels are kept, the whole
+/// frame is dirty once it is deserialized.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RawFrame {
+    resolution: Resolution,
+    edge: Edge,
+    rows: Rows,
+}
+
+/// Every row of pixels, as serde has no impls for arrays this long.
+#[cfg(feature = "serde")]
+struct Rows([u128; Resolution::MAX_HEIGHT]);
+
+#[cfg(feature = "serde")]
+impl From<FrameBuffer> for RawFrame {
+    fn from(fb: FrameBuffer) -> Self {
+        Self {
+            resolution: fb.resolution,
+            edge: fb.edge,
+            rows: Rows(fb.rows),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawFrame> for FrameBuffer {
+    type Error = &'static str;
+
+    fn try_from(raw: RawFrame) -> Result<Self, Self::Error> {
+        let mut fb = FrameBuffer::with_edge(raw.edge);
+        fb.set_resolution(raw.resolution);
+
+        let (height, mask) = (fb.height(), fb.mask());
+        let (rows, below) = raw.rows.0.split_at(height);
+        if rows.iter().any(|row| row & !mask != 0) || below.iter().any(|&row| row != 0) {
+            return Err("pixels outside the display");
+        }
+
+        for (y, &row) in rows.iter().enumerate() {
+            fb.xor(y, row);
+        }
+
+        Ok(fb)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Rows {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeTuple;
+
+        let mut tuple = serializer.serialize_tuple(self.0.len())?;
+        for row in &self.0 {
+            tuple.serialize_element(row)?;
+        }
+        tuple.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Rows {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::{Error, SeqAccess, Visitor};
+
+        struct RowsVisitor;
+
+        impl<'de> Visitor<'de> for RowsVisitor {
+            type Value = Rows;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{} rows of pixels", Resolution::MAX_HEIGHT)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Rows, A::Error> {
+                let mut rows = Rows([0; Resolution::MAX_HEIGHT]);
+                for (len, row) in rows.0.iter_mut().enumerate() {
+                    *row = seq
+                        .next_element()?
+                        .ok_or_else(|| A::Error::invalid_length(len, &self))?;
+                }
+                Ok(rows)
+            }
+        }
+
+        deserializer.deserialize_tuple(Resolution::MAX_HEIGHT, RowsVisitor)
+    }
+}
+
+impl fmt::Debug for FrameBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrameBuffer")
+            .field("resolution", &self.resolution)
+            .field("edge", &self.edge)
+            .field("rows", &self.rows())
+            .finish()
+    }
+}
+
+/// Iterator over the changed [`Region`]s of a [`FrameBuffer`].
+#[derive(Debug, Clone)]
+pub struct Dirty<'a> {
+    fb: &'a FrameBuffer,
+    y: usize,
+}
+
+impl Iterator for Dirty<'_> {
+    type Item = Region;
+
+    fn next(&mut self) -> Option<Region> {
+        let height = self.fb.height();
+        let top = (self.y..height).find(|&y| self.fb.changed(y).is_some())?;
+        let (mut start, mut end) = self.fb.changed(top).unwrap();
+
+        self.y = top + 1;
+        while self.y < height {
+            match self.fb.changed(self.y) {
+                Some((s, e)) if s <= end && e >= start => {
+                    (start, end) = (start.min(s), end.max(e));
+                    self.y += 1;
+                }
+                _ => break,
+            }
+        }
+
+        Some(Region {
+            x: start as u8,
+            y: top as u8,
+            width: (end - start) as u8,
+            height: (self.y - top) as u8,
+        })
+    }
+}
+
+impl Screen for FrameBuffer {
+    type Error = Error;
+
+    fn draw(&mut self, x: u8, y: u8, data: &[u8]) -> Result<bool, Self::Error> {
+        Ok(self.draw_sprite(x, y, data))
+    }
+
+    fn draw_wide(&mut self, x: u8, y: u8, data: &[u16]) -> Result<bool, Self::Error> {
+        Ok(self.draw_wide_sprite(x, y, data))
+    }
+
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        self.reset();
+        Ok(())
+    }
+
+    fn set_resolution(&mut self, resolution: Resolution) -> Result<(), Self::Error> {
+        FrameBuffer::set_resolution(self, resolution);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit(fb: &FrameBuffer) -> usize {
+        fb.rows().iter().map(|row| row.count_ones() as usize).sum()
+    }
+
+    #[test]
+    fn xor() {
+        let mut fb = FrameBuffer::new();
+
+        assert!(!fb.draw(2, 1, &[0b1100_0000, 0b1000_0001]).unwrap());
+        assert_eq!(fb.rows()[1], 0b11 << 124);
+        assert_eq!(fb.rows()[2], 0b1000_0001 << 118);
+        assert!(fb.pixel(2, 1) && fb.pixel(9, 2) && !fb.pixel(4, 1));
+
+        // Only touching a lit pixel counts as a collision.
+        assert!(!fb.draw(4, 1, &[0b1000_0000]).unwrap());
+        assert!(fb.draw(3, 1, &[0b1000_0000]).unwrap());
+        assert!(!fb.pixel(3, 1));
+        assert_eq!(lit(&fb), 4);
+
+        fb.clear().unwrap();
+        assert_eq!(lit(&fb), 0);
+        assert!(fb.pixels().iter().all(|&px| px == 0));
+    }
+
+    #[test]
+    fn edges() {
+        let sprite = [0xFF; 4];
+
+        let mut clip = FrameBuffer::new();
+        assert!(!clip.draw(60 + 64, 30 + 32, &sprite).unwrap());
+        assert_eq!(clip.rows()[30], 0xF << 64);
+        assert_eq!(clip.rows()[0], 0);
+        assert_eq!(lit(&clip), 8);
+
+        let mut wrap = FrameBuffer::with_edge(Edge::Wrap);
+        assert!(!wrap.draw(60, 30, &sprite).unwrap());
+        assert_eq!(wrap.rows()[31], 0xF000_0000_0000_000F << 64);
+        assert_eq!(wrap.rows()[0], 0xF000_0000_0000_000F << 64);
+        assert_eq!(lit(&wrap), 32);
+
+        // Wrapped pixels collide like any other.
+        assert!(wrap.draw(0, 0, &[0x80]).unwrap());
+
+        wrap.clear().unwrap();
+        assert_eq!(wrap.edge(), Edge::Wrap);
+    }
+
+    #[test]
+    fn pixels() {
+        let mut fb = FrameBuffer::with_edge(Edge::Wrap);
+        fb.draw(62, 31, &[0b1010_0000, 0b0110_0000]).unwrap();
+        fb.draw(63, 0, &[0b1100_0000]).unwrap();
+
+        for y in 0..fb.height() {
+            for x in 0..fb.width() {
+                let byte = fb.pixels()[y * fb.width() + x];
+                assert_eq!(byte == FrameBuffer::ON, fb.pixel(x, y), "({x}, {y})");
+                assert!(byte == 0 || byte == FrameBuffer::ON);
+            }
+        }
+        assert_eq!(fb.pixels()[62 + 31 * 64], FrameBuffer::ON);
+        assert_eq!(fb.pixels()[31 * 64], FrameBuffer::ON);
+        assert_eq!(fb.pixels()[..64], [0; 64]);
+    }
+
+    #[test]
+    fn dirty() {
+        let region = |x, y, width, height| Region {
+            x,
+            y,
+            width,
+            height,
+        };
+
+        let mut fb = FrameBuffer::new();
+        assert!(!fb.is_dirty());
+
+        fb.draw(2, 1, &[0x80, 0xC0]).unwrap();
+        fb.draw(40, 2, &[0xFF]).unwrap();
+        fb.draw(8, 10, &[0x01]).unwrap();
+        assert!(fb.is_dirty());
+        assert!(fb.dirty().eq([region(2, 1, 46, 2), region(15, 10, 1, 1)]));
+
+        fb.mark_presented();
+        assert!(!fb.is_dirty());
+        assert_eq!(fb.dirty().count(), 0);
+
+        // Drawing a sprite twice leaves nothing to present.
+        fb.draw(30, 20, &[0xF0; 3]).unwrap();
+        fb.draw(30, 20, &[0xF0; 3]).unwrap();
+        assert!(!fb.is_dirty());
+
+        // A sprite moved down overlaps its old position and is one region.
+        fb.draw(8, 10, &[0x01]).unwrap();
+        fb.draw(0, 4, &[0xFF; 4]).unwrap();
+        fb.mark_presented();
+        fb.draw(0, 4, &[0xFF; 4]).unwrap();
+        fb.draw(1, 6, &[0xFF; 4]).unwrap();
+        assert!(fb.dirty().eq([region(0, 4, 9, 6)]));
+
+        fb.clear().unwrap();
+        assert!(fb.dirty().eq([region(2, 1, 46, 2), region(0, 4, 8, 4)]));
+        assert_eq!(fb, FrameBuffer::new());
+    }
+
+    #[test]
+    fn resolutions() {
+        let mut fb = FrameBuffer::with_edge(Edge::Wrap);
+        fb.draw(0, 0, &[0x80]).unwrap();
+        fb.mark_presented();
+
+        fb.set_resolution(Resolution::Extended);
+        assert_eq!((fb.width(), fb.height()), (128, 64));
+        assert_eq!((fb.rows().len(), fb.pixels().len()), (64, 128 * 64));
+        assert!(!fb.pixel(0, 0));
+        assert!(fb.dirty().eq([Region {
+            x: 0,
+            y: 0,
+            width: 128,
+            height: 64,
+        }]));
+        fb.mark_presented();
+
+        // Coordinates wrap at the new size, and wide sprites wrap too.
+        assert!(!fb.draw_wide(120 + 128, 63, &[0xFFFF, 0x8001]).unwrap());
+        assert_eq!(fb.rows()[63], 0xFF << 120 | 0xFF);
+        assert_eq!(fb.rows()[0], 1 << 120 | 1 << 7);
+        assert!(fb.pixel(127, 63) && fb.pixel(7, 0) && fb.pixel(120, 0));
+        assert_eq!(fb.pixels()[127 + 63 * 128], FrameBuffer::ON);
+
+        fb.set_resolution(Resolution::Eti660);
+        fb.set_edge(Edge::Clip);
+        assert!(!fb.draw(60, 47, &[0xFF, 0xFF]).unwrap());
+        assert_eq!(fb.rows()[47], 0xF << 64);
+        assert_eq!(lit(&fb), 4);
+        assert!(fb.draw_wide(56, 47, &[0x0F00]).unwrap());
+        assert!(fb.pixels().iter().all(|&px| px == 0));
+
+        // The default splits wide sprites in two.
+        let mut screen = crate::hal::headless::NullScreen;
+        assert!(!screen.draw_wide(0, 0, &[0xFFFF; 20]).unwrap());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let mut fb = FrameBuffer::with_edge(Edge::Wrap);
+        fb.set_resolution(Resolution::Extended);
+        fb.draw_wide_sprite(120, 62, &[0xFFFF, 0x8001, 0x8001]);
+
+        // The pixel plane is rebuilt, and the whole frame is dirty.
+        let json = serde_json::to_string(&fb).unwrap();
+        let copy = serde_json::from_str::<FrameBuffer>(&json).unwrap();
+        assert_eq!(copy, fb);
+        assert_eq!(copy.pixels(), fb.pixels());
+        assert_eq!(copy.dirty().count(), 1);
+
+        let bin = bincode::serialize(&fb).unwrap();
+        assert_eq!(bincode::deserialize::<FrameBuffer>(&bin).unwrap(), fb);
+
+        // Pixels past the edge of a smaller display are rejected.
+        let lores = json.replace("Extended", "Lores");
+        assert!(serde_json::from_str::<FrameBuffer>(&lores).is_err());
+    }
+}
@@ -1,5 +1,9 @@
-/// General peripheral error, indicates only which component failed.
+#[cfg(feature = "video")]
+use super::framebuffer::{FrameBuffer, Region};
+
+/// Which peripheral failed, without the details.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Error {
     Screen,
     Keypad,
@@ -8,37 +12,220 @@ pub enum Error {
     Rng,
 }
 
-/// Delay handler
-pub trait Delay
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Error::Screen => "screen failed",
+            Error::Keypad => "keypad failed",
+            Error::Buzzer => "buzzer failed",
+            Error::Delay => "delay failed",
+            Error::Rng => "rng failed",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+/// Error from one of the peripherals, keeping the value returned by its
+/// driver.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Fault<S, K, B, R, D> {
+    Screen(S),
+    Keypad(K),
+    Buzzer(B),
+    Rng(R),
+    Delay(D),
+}
+
+impl<S, K, B, R, D> Fault<S, K, B, R, D> {
+    pub fn kind(&self) -> Error {
+        match self {
+            Fault::Screen(_) => Error::Screen,
+            Fault::Keypad(_) => Error::Keypad,
+            Fault::Buzzer(_) => Error::Buzzer,
+            Fault::Rng(_) => Error::Rng,
+            Fault::Delay(_) => Error::Delay,
+        }
+    }
+}
+
+impl<S, K, B, R, D> From<Fault<S, K, B, R, D>> for Error {
+    fn from(fault: Fault<S, K, B, R, D>) -> Self {
+        fault.kind()
+    }
+}
+
+impl<S, K, B, R, D> core::fmt::Display for Fault<S, K, B, R, D> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.kind().fmt(f)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S, K, B, R, D> std::error::Error for Fault<S, K, B, R, D>
 where
-    Self::Error: Into<Error>,
+    S: std::error::Error + 'static,
+    K: std::error::Error + 'static,
+    B: std::error::Error + 'static,
+    R: std::error::Error + 'static,
+    D: std::error::Error + 'static,
 {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(match self {
+            Fault::Screen(err) => err,
+            Fault::Keypad(err) => err,
+            Fault::Buzzer(err) => err,
+            Fault::Rng(err) => err,
+            Fault::Delay(err) => err,
+        })
+    }
+}
+
+/// Delay handler
+pub trait Delay {
     type Error;
 
     fn delay_us(&mut self, us: u32) -> Result<(), Self::Error>;
 }
 
+/// Display geometry. The machine starts in [`Lores`](Resolution::Lores) and
+/// tells the [`Screen`] whenever it changes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Resolution {
+    /// 64x32, the original CHIP-8 display.
+    #[default]
+    Lores,
+    /// 64x48, the ETI-660 display.
+    Eti660,
+    /// 64x64, Hires CHIP-8.
+    Hires,
+    /// 128x64, the SUPER-CHIP high resolution mode.
+    Extended,
+}
+
+impl Resolution {
+    /// Size of the largest display, in pixels.
+    pub const MAX_WIDTH: usize = 128;
+    pub const MAX_HEIGHT: usize = 64;
+
+    pub const fn width(self) -> usize {
+        match self {
+            Resolution::Extended => 128,
+            _ => 64,
+        }
+    }
+
+    pub const fn height(self) -> usize {
+        match self {
+            Resolution::Lores => 32,
+            Resolution::Eti660 => 48,
+            Resolution::Hires | Resolution::Extended => 64,
+        }
+    }
+}
+
+impl core::fmt::Display for Resolution {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}x{}", self.width(), self.height())
+    }
+}
+
 /// Screen
-pub trait Screen
-where
-    Self::Error: Into<Error>,
-{
+pub trait Screen {
     type Error;
 
     /// XOR the [&\[u8\]](`u8`) into the current display starting at position
     /// `(x,y)`, then update the display. Returns a boolean indicating whether
     /// pixels were erased by this operation.
+    ///
+    /// Positions are those given to the `DRW` instruction and wrap at the
+    /// edges of the current [`Resolution`].
     fn draw(&mut self, x: u8, y: u8, data: &[u8]) -> Result<bool, Self::Error>;
 
+    /// As [`draw`](Self::draw), for a sprite 16 pixels wide.
+    ///
+    /// By default the sprite is drawn as two 8 pixel halves. The right half
+    /// starts at `x + 8`, which wraps even where sprites are clipped, so
+    /// screens which clip should draw the sprite at once.
+    fn draw_wide(&mut self, x: u8, y: u8, data: &[u16]) -> Result<bool, Self::Error> {
+        let mut left = [0; 16];
+        let mut right = [0; 16];
+        for ((l, r), row) in left.iter_mut().zip(&mut right).zip(data) {
+            [*l, *r] = row.to_be_bytes();
+        }
+
+        let len = data.len().min(16);
+        let erased = self.draw(x, y, &left[..len])?;
+        Ok(self.draw(x.wrapping_add(8), y, &right[..len])? | erased)
+    }
+
     /// Clear the entire display
     fn clear(&mut self) -> Result<(), Self::Error>;
+
+    /// Switch to `resolution` and clear the display. Screens with a single
+    /// size may ignore the change and keep drawing at 64x32.
+    fn set_resolution(&mut self, resolution: Resolution) -> Result<(), Self::Error> {
+        let _ = resolution;
+        self.clear()
+    }
+
+    /// Show what was drawn since the last call. Called once per frame by
+    /// [`Chip8::run`](crate::vm::Chip8::run), screens which update on every
+    /// `draw` need not implement it.
+    fn present(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Whether the VM should draw into the [`FrameBuffer`] in its state and
+    /// work out collisions itself. The screen is then only handed the frame
+    /// through [`present_frame`](Self::present_frame), and none of the other
+    /// methods are called.
+    #[cfg(feature = "video")]
+    fn presents_frame(&self) -> bool {
+        false
+    }
+
+    /// Show the VM's `frame`, of which only the pixels inside `regions` have
+    /// changed since the last call. Called once per frame in place of
+    /// [`present`](Self::present) if [`presents_frame`](Self::presents_frame)
+    /// is true.
+    #[cfg(feature = "video")]
+    fn present_frame(
+        &mut self,
+        frame: &FrameBuffer,
+        regions: &[Region],
+    ) -> Result<(), Self::Error> {
+        let _ = (frame, regions);
+        Ok(())
+    }
+}
+
+/// Presentation-only display, for use with [`Video`](crate::vm::display::Video)
+/// which has the VM keep the pixels and work out collisions.
+#[cfg(feature = "video")]
+pub trait Present {
+    type Error;
+
+    /// Show `frame`. Only the pixels inside `regions` have changed since
+    /// the last call.
+    fn present(&mut self, frame: &FrameBuffer, regions: &[Region]) -> Result<(), Self::Error>;
+}
+
+#[cfg(feature = "video")]
+impl<F: FnMut(&FrameBuffer, &[Region])> Present for F {
+    type Error = core::convert::Infallible;
+
+    fn present(&mut self, frame: &FrameBuffer, regions: &[Region]) -> Result<(), Self::Error> {
+        self(frame, regions);
+        Ok(())
+    }
 }
 
 /// Keypad
-pub trait Keypad
-where
-    Self::Error: Into<Error>,
-{
+pub trait Keypad {
     type Error;
 
     /// Returns true if any key is pressed, false otherwise.
@@ -48,23 +235,196 @@ where
     fn read_key<D: Delay>(&mut self, delay: &mut D) -> Result<Option<u8>, Self::Error>;
 }
 
+/// Snapshot of the 16 keys, bit `n` is set while key `n` is down.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Keys(pub u16);
+
+impl Keys {
+    pub const NONE: Keys = Keys(0);
+
+    /// Only `key` down. Keys above `0xF` are ignored.
+    pub fn key(key: u8) -> Self {
+        Self::NONE.with(key)
+    }
+
+    pub fn mask(self) -> u16 {
+        self.0
+    }
+
+    /// Whether `key` is down, never true for keys above `0xF`.
+    pub fn is_down(self, key: u8) -> bool {
+        key < 16 && self.0 & (1 << key) != 0
+    }
+
+    pub fn any(self) -> bool {
+        self.0 != 0
+    }
+
+    /// The lowest key which is down.
+    pub fn lowest(self) -> Option<u8> {
+        self.any().then(|| self.0.trailing_zeros() as u8)
+    }
+
+    pub fn with(self, key: u8) -> Self {
+        match key < 16 {
+            true => Self(self.0 | 1 << key),
+            false => self,
+        }
+    }
+
+    pub fn without(self, key: u8) -> Self {
+        match key < 16 {
+            true => Self(self.0 & !(1 << key)),
+            false => self,
+        }
+    }
+
+    /// Keys which are down now but were up in `before`.
+    pub fn pressed(self, before: Keys) -> Self {
+        Self(self.0 & !before.0)
+    }
+
+    /// Keys which were down in `before` but are up now.
+    pub fn released(self, before: Keys) -> Self {
+        Self(before.0 & !self.0)
+    }
+
+    /// The keys which are down, lowest first.
+    pub fn iter(self) -> impl Iterator<Item = u8> {
+        (0..16).filter(move |&key| self.is_down(key))
+    }
+}
+
+impl From<u16> for Keys {
+    fn from(mask: u16) -> Self {
+        Self(mask)
+    }
+}
+
+impl From<Keys> for u16 {
+    fn from(keys: Keys) -> Self {
+        keys.0
+    }
+}
+
+/// Keypad which reports every key at once, as sampled by the VM. Every
+/// [`Keypad`] is one, see the blanket impl below.
+pub trait KeyState {
+    type Error;
+
+    /// Sample the state of every key. `held` is the result of the previous
+    /// sample, for drivers which only see changes or one key at a time.
+    ///
+    /// Sampling must not consume input: the VM samples on every `SKP`,
+    /// `SKNP` and `LD Vx, K` and expects the same answer until a key
+    /// actually changes. The delay is available to drivers which scan the
+    /// keys.
+    fn keys<D: Delay>(&mut self, delay: &mut D, held: Keys) -> Result<Keys, Self::Error>;
+}
+
+/// Adapter for keypads which report a single key. The key read last is
+/// latched while [`key_is_pressed`](Keypad::key_is_pressed) holds, and a
+/// new key is only read once it is released. Only one key is ever down,
+/// and moving straight from one key to another is not seen until the
+/// first is released.
+impl<K: Keypad> KeyState for K {
+    type Error = K::Error;
+
+    fn keys<D: Delay>(&mut self, delay: &mut D, held: Keys) -> Result<Keys, Self::Error> {
+        if held.any() && self.key_is_pressed()? {
+            return Ok(held);
+        }
+
+        Ok(self.read_key(delay)?.map_or(Keys::NONE, Keys::key))
+    }
+}
+
 /// Buzzer
-pub trait Buzzer
-where
-    Self::Error: Into<Error>,
-{
+pub trait Buzzer {
     type Error;
 
     fn on(&mut self) -> Result<(), Self::Error>;
     fn off(&mut self) -> Result<(), Self::Error>;
+
+    /// Called once per 60 Hz frame by [`Chip8::run`](crate::vm::Chip8::run),
+    /// e.g. to generate the next frame of audio.
+    fn frame(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 /// Rng
-pub trait Rng
-where
-    Self::Error: Into<Error>,
-{
+pub trait Rng {
     type Error;
 
     fn random(&mut self) -> Result<u8, Self::Error>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::mocks::MockDelay;
+
+    /// Keypad with a single switch, which counts the keys read.
+    #[derive(Default)]
+    struct Switch {
+        key: Option<u8>,
+        reads: usize,
+    }
+
+    impl Keypad for Switch {
+        type Error = Error;
+
+        fn key_is_pressed(&self) -> Result<bool, Self::Error> {
+            Ok(self.key.is_some())
+        }
+
+        fn read_key<D: Delay>(&mut self, _delay: &mut D) -> Result<Option<u8>, Self::Error> {
+            self.reads += 1;
+            Ok(self.key)
+        }
+    }
+
+    #[test]
+    fn keys() {
+        let before = Keys(0b0110);
+        let now = Keys(0b1100);
+
+        assert_eq!(now.pressed(before), Keys::key(3));
+        assert_eq!(now.released(before), Keys::key(1));
+        assert_eq!(now.lowest(), Some(2));
+        assert_eq!(Keys::NONE.lowest(), None);
+        assert!(now.iter().eq([2, 3]));
+
+        assert!(!now.is_down(0x12));
+        assert_eq!(now.with(0x12), now);
+        assert_eq!(now.with(0).without(2).mask(), 0b1001);
+    }
+
+    #[test]
+    fn latched() {
+        let mut keypad = Switch::default();
+        let mut delay = MockDelay;
+
+        assert_eq!(keypad.keys(&mut delay, Keys::NONE).unwrap(), Keys::NONE);
+
+        keypad.key = Some(3);
+        let held = keypad.keys(&mut delay, Keys::NONE).unwrap();
+        assert_eq!(held, Keys::key(3));
+
+        // The key is not read again while it is held.
+        for _ in 0..3 {
+            assert_eq!(keypad.keys(&mut delay, held).unwrap(), held);
+        }
+        assert_eq!(keypad.reads, 2);
+
+        // Sliding to another key is only seen after a release.
+        keypad.key = Some(7);
+        assert_eq!(keypad.keys(&mut delay, held).unwrap(), held);
+        keypad.key = None;
+        assert_eq!(keypad.keys(&mut delay, held).unwrap(), Keys::NONE);
+        keypad.key = Some(7);
+        assert_eq!(keypad.keys(&mut delay, Keys::NONE).unwrap(), Keys::key(7));
+    }
+}
@@ -0,0 +1,111 @@
+use super::{Buzzer, Delay, Error, KeyState, Keys, Rng, Screen};
+
+/// Screen which discards everything drawn to it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullScreen;
+
+impl Screen for NullScreen {
+    type Error = Error;
+
+    fn draw(&mut self, _x: u8, _y: u8, _data: &[u8]) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Keypad whose keys are pressed and released by the host.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeldKeys {
+    keys: u16,
+}
+
+impl HeldKeys {
+    pub fn press(&mut self, key: u8) {
+        self.keys |= 1 << (key & 0xF);
+    }
+
+    pub fn release(&mut self, key: u8) {
+        self.keys &= !(1 << (key & 0xF));
+    }
+
+    pub fn release_all(&mut self) {
+        self.keys = 0;
+    }
+
+    /// Bitmask of the held keys, bit `n` is key `n`.
+    pub fn held(&self) -> u16 {
+        self.keys
+    }
+}
+
+impl KeyState for HeldKeys {
+    type Error = Error;
+
+    fn keys<D: Delay>(&mut self, _delay: &mut D, _held: Keys) -> Result<Keys, Self::Error> {
+        Ok(Keys(self.keys))
+    }
+}
+
+/// Buzzer which remembers whether it is on.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullBuzzer {
+    pub on: bool,
+}
+
+impl Buzzer for NullBuzzer {
+    type Error = Error;
+
+    fn on(&mut self) -> Result<(), Self::Error> {
+        self.on = true;
+        Ok(())
+    }
+
+    fn off(&mut self) -> Result<(), Self::Error> {
+        self.on = false;
+        Ok(())
+    }
+}
+
+/// Deterministic xorshift generator.
+#[derive(Debug, Clone, Copy)]
+pub struct XorShift {
+    state: u32,
+}
+
+impl XorShift {
+    pub fn new(seed: u32) -> Self {
+        Self { state: seed.max(1) }
+    }
+}
+
+impl Default for XorShift {
+    fn default() -> Self {
+        Self::new(0x2545_F491)
+    }
+}
+
+impl Rng for XorShift {
+    type Error = Error;
+
+    fn random(&mut self) -> Result<u8, Self::Error> {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        Ok(self.state as u8)
+    }
+}
+
+/// Delay which returns immediately.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoDelay;
+
+impl Delay for NoDelay {
+    type Error = Error;
+
+    fn delay_us(&mut self, _us: u32) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,419 @@
+//! Driver for a 4x4 key matrix.
+//!
+//! [`Matrix`] drives each row low in turn, waits for the lines to settle and
+//! reads the columns, which are pulled up and read low for every closed
+//! switch in that row. All 16 keys are scanned on every sample, so any
+//! number of keys can be held at once.
+//!
+//! Without a diode in series with each switch, current also flows back
+//! through other closed switches: holding three keys on the corners of a
+//! rectangle closes the fourth corner as well. Such scans are ambiguous, and
+//! the rows involved keep their previous keys until it clears, see
+//! [`Matrix::with_diodes`].
+
+use super::{Delay, KeyState, Keys};
+
+/// Pin driving a row of the matrix.
+pub trait OutputPin {
+    type Error;
+
+    fn set_low(&mut self) -> Result<(), Self::Error>;
+    fn set_high(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Pin reading a column of the matrix, pulled up.
+pub trait InputPin {
+    type Error;
+
+    fn is_low(&mut self) -> Result<bool, Self::Error>;
+}
+
+/// Key at each row and column of the COSMAC VIP keypad.
+pub const VIP: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+/// Error from a pin or the delay while scanning.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MatrixError<O, I> {
+    Row(O),
+    Column(I),
+    Delay,
+}
+
+impl<O, I> core::fmt::Display for MatrixError<O, I> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            MatrixError::Row(_) => "matrix row pin failed",
+            MatrixError::Column(_) => "matrix column pin failed",
+            MatrixError::Delay => "matrix settle delay failed",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl<O: core::fmt::Debug, I: core::fmt::Debug> std::error::Error for MatrixError<O, I> {}
+
+/// Scanner for a 4x4 key matrix, which is a [`KeyState`].
+#[derive(Debug, Clone)]
+pub struct Matrix<R, C> {
+    rows: [R; 4],
+    cols: [C; 4],
+    keymap: [[u8; 4]; 4],
+    settle_us: u32,
+    diodes: bool,
+    /// Columns closed in each row, bit `n` is column `n`.
+    closed: [u8; 4],
+    ghosted: bool,
+}
+
+impl<R: OutputPin, C: InputPin> Matrix<R, C> {
+    /// Scan the [`VIP`] layout, settling for 10 us after driving each row.
+    pub fn new(rows: [R; 4], cols: [C; 4]) -> Self {
+        Self {
+            rows,
+            cols,
+            keymap: VIP,
+            settle_us: 10,
+            diodes: false,
+            closed: [0; 4],
+            ghosted: false,
+        }
+    }
+
+    /// Key reported for the switch at `keymap[row][column]`.
+    pub fn with_keymap(mut self, keymap: [[u8; 4]; 4]) -> Self {
+        self.keymap = keymap;
+        self
+    }
+
+    /// Time to wait between driving a row and reading the columns.
+    pub fn with_settle_us(mut self, us: u32) -> Self {
+        self.settle_us = us;
+        self
+    }
+
+    /// Whether every switch has a diode, which prevents ghosting so that
+    /// every combination of keys can be read.
+    pub fn with_diodes(mut self, diodes: bool) -> Self {
+        self.diodes = diodes;
+        self
+    }
+
+    /// Columns closed in each row by the last scan, bit `n` is column `n`.
+    pub fn closed(&self) -> [u8; 4] {
+        self.closed
+    }
+
+    /// Whether the last scan was ambiguous. Rows on the corners of a
+    /// rectangle of closed switches kept their previous keys.
+    pub fn ghosted(&self) -> bool {
+        self.ghosted
+    }
+
+    /// Keys held at the last scan.
+    pub fn held(&self) -> Keys {
+        let mut keys = Keys::NONE;
+        for (row, closed) in self.keymap.iter().zip(self.closed) {
+            for (col, &key) in row.iter().enumerate() {
+                if closed & (1 << col) != 0 {
+                    keys = keys.with(key);
+                }
+            }
+        }
+        keys
+    }
+
+    /// Read every switch in the matrix, returning the keys held.
+    pub fn scan<D: Delay>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<Keys, MatrixError<R::Error, C::Error>> {
+        // Release every row first, in case the pins started low.
+        for row in &mut self.rows {
+            row.set_high().map_err(MatrixError::Row)?;
+        }
+
+        let mut raw = [0; 4];
+        for (row, closed) in self.rows.iter_mut().zip(&mut raw) {
+            row.set_low().map_err(MatrixError::Row)?;
+            delay
+                .delay_us(self.settle_us)
+                .map_err(|_| MatrixError::Delay)?;
+
+            for (n, col) in self.cols.iter_mut().enumerate() {
+                if col.is_low().map_err(MatrixError::Column)? {
+                    *closed |= 1 << n;
+                }
+            }
+            row.set_high().map_err(MatrixError::Row)?;
+        }
+
+        let ambiguous = match self.diodes {
+            true => 0,
+            false => ghosts(&raw),
+        };
+
+        for (n, (closed, raw)) in self.closed.iter_mut().zip(raw).enumerate() {
+            *closed = match ambiguous & (1 << n) != 0 {
+                true => *closed & raw,
+                false => raw,
+            };
+        }
+        self.ghosted = ambiguous != 0;
+
+        Ok(self.held())
+    }
+
+    pub fn into_inner(self) -> ([R; 4], [C; 4]) {
+        (self.rows, self.cols)
+    }
+}
+
+/// Rows sharing two or more closed columns with another row, bit `n` is
+/// row `n`. Any one of the four switches might be a ghost.
+fn ghosts(raw: &[u8; 4]) -> u8 {
+    let mut rows = 0;
+    for a in 0..4 {
+        for b in a + 1..4 {
+            if (raw[a] & raw[b]).count_ones() >= 2 {
+                rows |= 1 << a | 1 << b;
+            }
+        }
+    }
+    rows
+}
+
+impl<R: OutputPin, C: InputPin> KeyState for Matrix<R, C> {
+    type Error = MatrixError<R::Error, C::Error>;
+
+    fn keys<D: Delay>(&mut self, delay: &mut D, _held: Keys) -> Result<Keys, Self::Error> {
+        self.scan(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::cell::Cell;
+    use core::convert::Infallible;
+
+    use super::*;
+    use std::vec::Vec;
+
+    /// Switches wired between rows and columns, without diodes unless
+    /// `diodes` is set.
+    #[derive(Default)]
+    struct Board {
+        /// Bit `row * 4 + col` is set while that switch is closed.
+        pressed: Cell<u16>,
+        /// Rows driven low, bit `n` is row `n`.
+        driven: Cell<u8>,
+        diodes: bool,
+        settled_us: Cell<u32>,
+    }
+
+    impl Board {
+        fn press(&self, row: usize, col: usize) {
+            self.pressed.set(self.pressed.get() | 1 << (row * 4 + col));
+        }
+
+        fn release(&self, row: usize, col: usize) {
+            self.pressed
+                .set(self.pressed.get() & !(1 << (row * 4 + col)));
+        }
+
+        fn columns(&self, rows: u8) -> u8 {
+            let pressed = self.pressed.get();
+            (0..4)
+                .filter(|row| rows & (1 << row) != 0)
+                .fold(0, |cols, row| cols | (pressed >> (row * 4)) as u8 & 0xF)
+        }
+
+        /// Columns pulled low, following closed switches back through
+        /// other rows when there are no diodes.
+        fn low(&self) -> u8 {
+            let mut rows = self.driven.get();
+            loop {
+                let cols = self.columns(rows);
+                let reached = (0..4)
+                    .filter(|&row| self.columns(1 << row) & cols != 0)
+                    .fold(rows, |rows, row| rows | 1 << row);
+
+                if self.diodes || reached == rows {
+                    return cols;
+                }
+                rows = reached;
+            }
+        }
+    }
+
+    struct Row<'a>(&'a Board, u8);
+
+    impl OutputPin for Row<'_> {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.0.driven.set(self.0.driven.get() | 1 << self.1);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.0.driven.set(self.0.driven.get() & !(1 << self.1));
+            Ok(())
+        }
+    }
+
+    struct Col<'a>(&'a Board, u8);
+
+    impl InputPin for Col<'_> {
+        type Error = Infallible;
+
+        fn is_low(&mut self) -> Result<bool, Self::Error> {
+            assert_eq!(self.0.driven.get().count_ones(), 1, "one row at a time");
+            Ok(self.0.low() & (1 << self.1) != 0)
+        }
+    }
+
+    impl Delay for &Board {
+        type Error = Infallible;
+
+        fn delay_us(&mut self, us: u32) -> Result<(), Self::Error> {
+            self.settled_us.set(self.settled_us.get() + us);
+            Ok(())
+        }
+    }
+
+    fn matrix(board: &Board) -> Matrix<Row<'_>, Col<'_>> {
+        Matrix::new(
+            core::array::from_fn(|n| Row(board, n as u8)),
+            core::array::from_fn(|n| Col(board, n as u8)),
+        )
+    }
+
+    #[test]
+    fn multiple_keys() {
+        let board = Board::default();
+        // Rows 0 and 2 start driven low.
+        board.driven.set(0b101);
+        let mut keypad = matrix(&board).with_settle_us(25);
+
+        assert_eq!(keypad.scan(&mut &board).unwrap(), Keys::NONE);
+        assert_eq!(board.driven.get(), 0);
+        assert_eq!(board.settled_us.get(), 4 * 25);
+
+        board.press(0, 0);
+        board.press(0, 1);
+        board.press(0, 2);
+        board.press(3, 3);
+        let keys = keypad.scan(&mut &board).unwrap();
+        assert_eq!(keys.iter().collect::<Vec<_>>(), [0x1, 0x2, 0x3, 0xF]);
+        assert_eq!(keypad.closed(), [0b0111, 0, 0, 0b1000]);
+        assert!(!keypad.ghosted());
+
+        // Sampling again reports the same keys.
+        assert_eq!(keypad.scan(&mut &board).unwrap(), keys);
+
+        board.release(0, 1);
+        assert_eq!(keypad.scan(&mut &board).unwrap(), keys.without(2));
+    }
+
+    #[test]
+    fn ghosting() {
+        let board = Board::default();
+        let mut keypad = matrix(&board);
+
+        board.press(0, 0);
+        board.press(0, 1);
+        assert_eq!(keypad.scan(&mut &board).unwrap(), Keys(1 << 1 | 1 << 2));
+
+        // Pressing 4 also closes the path through 5.
+        board.press(1, 0);
+        assert_eq!(keypad.scan(&mut &board).unwrap(), Keys(1 << 1 | 1 << 2));
+        assert!(keypad.ghosted());
+
+        // Releases are still seen while ambiguous.
+        board.release(0, 1);
+        board.press(1, 1);
+        board.release(1, 0);
+        assert_eq!(keypad.scan(&mut &board).unwrap(), Keys(1 << 1 | 1 << 5));
+        assert!(!keypad.ghosted());
+
+        let board = Board {
+            diodes: true,
+            ..Board::default()
+        };
+        let mut keypad = matrix(&board).with_diodes(true);
+        board.press(0, 0);
+        board.press(0, 1);
+        board.press(1, 0);
+        assert_eq!(
+            keypad.scan(&mut &board).unwrap(),
+            Keys(1 << 1 | 1 << 2 | 1 << 4)
+        );
+        assert!(!keypad.ghosted());
+    }
+
+    #[test]
+    fn chip8() {
+        use crate::hal::headless::{NullBuzzer, NullScreen, XorShift};
+        use crate::vm::mem::Load;
+        use crate::vm::Chip8;
+
+        let board = Board::default();
+        let keymap = [
+            [0x0, 0x1, 0x2, 0x3],
+            [4, 5, 6, 7],
+            [8, 9, 0xA, 0xB],
+            [0xC, 0xD, 0xE, 0xF],
+        ];
+        let keypad = matrix(&board).with_keymap(keymap);
+        let mut chip = Chip8::new(
+            NullScreen,
+            keypad,
+            NullBuzzer::default(),
+            XorShift::default(),
+            &board,
+        );
+        chip.state_mut()
+            .ram
+            .load(
+                0x200,
+                &crate::chip8_asm! {
+                    ld 1, 0xF;    // 0x200
+                    skp 1;        // 0x202
+                    jp 0x202;     // 0x204
+                    ldkey 0;      // 0x206
+                    jp 0x208;     // 0x208
+                },
+            )
+            .unwrap();
+        chip.init().unwrap();
+
+        for _ in 0..3 {
+            chip.step().unwrap();
+        }
+        assert_eq!(chip.state().pc, 0x202);
+
+        // F is seen while another key is held too.
+        board.press(0, 0);
+        board.press(3, 3);
+        chip.step().unwrap();
+        assert_eq!(chip.state().pc, 0x206);
+
+        // Already held keys do not complete LD V0, K, a new press and
+        // release does.
+        board.press(1, 1);
+        chip.step().unwrap();
+        chip.step().unwrap();
+        assert_eq!(chip.state().pc, 0x206);
+        board.release(1, 1);
+        chip.step().unwrap();
+        assert_eq!(chip.state().pc, 0x208);
+        assert_eq!(chip.state().reg.get(0).unwrap(), 5);
+    }
+}
@@ -1,5 +1,5 @@
 extern crate std;
-use crate::hal::{Buzzer, Delay, Error, Keypad, Rng, Screen};
+use crate::hal::{Buzzer, Delay, Error, Keypad, Resolution, Rng, Screen};
 use std::{vec, vec::Vec};
 
 macro_rules! chip {
@@ -43,6 +43,7 @@ pub struct Peripherals {
 pub enum ScreenCommand {
     Draw { x: u8, y: u8, data: Vec<u8> },
     Clear,
+    Resolution(Resolution),
 }
 
 impl ScreenCommand {
@@ -84,6 +85,11 @@ impl Screen for MockScreen {
 
         Ok(self.collision)
     }
+
+    fn set_resolution(&mut self, resolution: Resolution) -> Result<(), Self::Error> {
+        self.commands.push(ScreenCommand::Resolution(resolution));
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Default)]
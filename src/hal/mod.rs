@@ -1,8 +1,27 @@
+#[allow(clippy::module_inception)]
 mod hal;
 pub use hal::*;
 
+pub mod audio;
+#[cfg(feature = "std")]
+pub mod capture;
+pub mod dynamic;
+pub mod framebuffer;
+pub mod headless;
+pub mod matrix;
+#[cfg(feature = "std")]
+pub mod terminal;
+
 #[cfg(test)]
 #[macro_use]
+#[allow(
+    clippy::bool_assert_comparison,
+    clippy::default_constructed_unit_structs,
+    clippy::len_zero,
+    clippy::option_map_or_none,
+    clippy::unit_arg,
+    clippy::unnecessary_map_or
+)]
 pub mod mocks;
 
 #[cfg(test)]
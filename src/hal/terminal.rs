@@ -0,0 +1,644 @@
+//! Peripherals for hosts with only a terminal.
+//!
+//! [`Terminal`] draws with ANSI escape codes. The VM keeps the pixels in its
+//! [`FrameBuffer`], and [`TerminalPresenter`] writes the character cells in
+//! the regions which changed since the last frame, moving the cursor only
+//! where unchanged cells are skipped.
+//!
+//! [`TerminalKeys`] reads key presses from an [`Input`] such as [`RawStdin`].
+//! Terminals do not report releases, so a key is held until a timeout, and
+//! kept held while the terminal repeats it.
+
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::string::String;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+use std::{thread, vec};
+
+use super::framebuffer::{FrameBuffer, Region};
+use super::{Delay, KeyState, Keys, Present, Resolution};
+use crate::vm::display::Video;
+
+/// How pixels are packed into character cells.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Glyphs {
+    /// `▀`, `▄` and `█`, 1x2 pixels per cell.
+    #[default]
+    HalfBlock,
+    /// Braille patterns, 2x4 pixels per cell.
+    Braille,
+}
+
+impl Glyphs {
+    /// Pixels covered by each cell, as `(width, height)`.
+    pub fn cell(self) -> (usize, usize) {
+        match self {
+            Glyphs::HalfBlock => (1, 2),
+            Glyphs::Braille => (2, 4),
+        }
+    }
+
+    /// The character showing the cell whose top left pixel is `(x, y)`.
+    fn glyph(self, frame: &FrameBuffer, x: usize, y: usize) -> char {
+        match self {
+            Glyphs::HalfBlock => match (frame.pixel(x, y), frame.pixel(x, y + 1)) {
+                (false, false) => ' ',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (true, true) => '█',
+            },
+            Glyphs::Braille => {
+                // Dots 1-3 and 4-6 are the first three rows of each column,
+                // dots 7 and 8 the bottom row.
+                const DOTS: [[u32; 2]; 4] =
+                    [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+                let mut bits = 0;
+                for (dy, row) in DOTS.iter().enumerate() {
+                    for (dx, dot) in row.iter().enumerate() {
+                        if frame.pixel(x + dx, y + dy) {
+                            bits |= dot;
+                        }
+                    }
+                }
+                char::from_u32(0x2800 + bits).unwrap()
+            }
+        }
+    }
+}
+
+/// Terminal color.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Color {
+    /// The terminal's own foreground or background.
+    #[default]
+    Default,
+    /// One of the 256 indexed colors.
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// Write the SGR parameters selecting this color, `base` is 30 for the
+    /// foreground and 40 for the background.
+    fn write_sgr<W: Write>(self, out: &mut W, base: u8) -> io::Result<()> {
+        match self {
+            Color::Default => write!(out, "{}", base + 9),
+            Color::Indexed(n) => write!(out, "{};5;{n}", base + 8),
+            Color::Rgb(r, g, b) => write!(out, "{};2;{r};{g};{b}", base + 8),
+        }
+    }
+}
+
+/// [`Present`] which writes to a terminal, or anything else implementing
+/// [`Write`].
+///
+/// The display is drawn from the top left corner of the terminal, each frame
+/// after the first only updates the cells which changed.
+#[derive(Debug, Clone)]
+pub struct TerminalPresenter<W> {
+    out: W,
+    glyphs: Glyphs,
+    fg: Color,
+    bg: Color,
+    /// Characters on the terminal, empty when it must be cleared and redrawn.
+    cells: Vec<char>,
+    /// Size of the display in `cells`, as `(columns, rows)`.
+    size: (usize, usize),
+}
+
+/// [`Screen`](super::Screen) drawing on a terminal, see [`TerminalPresenter`].
+pub type Terminal<W> = Video<TerminalPresenter<W>>;
+
+impl<W: Write> TerminalPresenter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            glyphs: Glyphs::default(),
+            fg: Color::Default,
+            bg: Color::Default,
+            cells: Vec::new(),
+            size: (0, 0),
+        }
+    }
+
+    pub fn with_glyphs(mut self, glyphs: Glyphs) -> Self {
+        self.glyphs = glyphs;
+        self.cells.clear();
+        self
+    }
+
+    /// Show lit pixels in `fg` and unlit pixels in `bg`.
+    pub fn with_colors(mut self, fg: Color, bg: Color) -> Self {
+        (self.fg, self.bg) = (fg, bg);
+        self.cells.clear();
+        self
+    }
+
+    /// Size of the display at `resolution` in character cells, as
+    /// `(columns, rows)`.
+    pub fn size(&self, resolution: Resolution) -> (usize, usize) {
+        let (width, height) = self.glyphs.cell();
+        (
+            resolution.width().div_ceil(width),
+            resolution.height().div_ceil(height),
+        )
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    /// Clear the terminal and draw every cell on the next
+    /// [`present`](Present::present), e.g. after it was resized. Use
+    /// [`Chip8::refresh`](crate::vm::Chip8::refresh) to present it at once.
+    pub fn invalidate(&mut self) {
+        self.cells.clear();
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Write the cells of `frame` covering `regions`, or every cell if the
+    /// terminal must be redrawn.
+    fn render(&mut self, frame: &FrameBuffer, regions: &[Region]) -> io::Result<()> {
+        let (columns, rows) = self.size(frame.resolution());
+        let (width, height) = self.glyphs.cell();
+        let redraw = self.cells.is_empty() || self.size != (columns, rows);
+        if redraw {
+            self.cells = vec!['\0'; columns * rows];
+            self.size = (columns, rows);
+        }
+
+        let mut out = Vec::new();
+        // Where the terminal's cursor is, if known.
+        let mut cursor = None;
+
+        let all = [Region {
+            x: 0,
+            y: 0,
+            width: frame.width() as u8,
+            height: frame.height() as u8,
+        }];
+        for region in if redraw { &all[..] } else { regions } {
+            let (x, y) = (region.x as usize, region.y as usize);
+            let cols = x / width..(x + region.width as usize).div_ceil(width);
+            for row in y / height..(y + region.height as usize).div_ceil(height) {
+                for col in cols.clone() {
+                    let glyph = self.glyphs.glyph(frame, col * width, row * height);
+                    let cell = &mut self.cells[row * columns + col];
+                    if *cell == glyph {
+                        continue;
+                    }
+
+                    if cursor != Some((row, col)) {
+                        write!(out, "\x1b[{};{}H", row + 1, col + 1)?;
+                    }
+                    write!(out, "{glyph}")?;
+                    *cell = glyph;
+                    cursor = Some((row, col + 1));
+                }
+            }
+        }
+
+        if out.is_empty() {
+            return Ok(());
+        }
+
+        out.write_all(b"\x1b[0m")?;
+        write!(self.out, "\x1b[")?;
+        self.fg.write_sgr(&mut self.out, 30)?;
+        write!(self.out, ";")?;
+        self.bg.write_sgr(&mut self.out, 40)?;
+        write!(self.out, "m")?;
+        if redraw {
+            self.out.write_all(b"\x1b[2J")?;
+        }
+        self.out.write_all(&out)?;
+        self.out.flush()
+    }
+}
+
+impl<W: Write> Present for TerminalPresenter<W> {
+    type Error = io::Error;
+
+    fn present(&mut self, frame: &FrameBuffer, regions: &[Region]) -> Result<(), Self::Error> {
+        self.render(frame, regions)
+    }
+}
+
+/// Characters typed for keys `0` to `F`, on the left of a QWERTY keyboard:
+///
+/// ```text
+/// 1 2 3 4      1 2 3 C
+/// Q W E R  ->  4 5 6 D
+/// A S D F      7 8 9 E
+/// Z X C V      A 0 B F
+/// ```
+pub const QWERTY: [u8; 16] = *b"x123qweasdzc4rfv";
+
+/// Byte sent by Ctrl-C when the terminal is in raw mode.
+const INTERRUPT: u8 = 0x03;
+
+/// Source of the bytes typed at a terminal.
+pub trait Input {
+    /// The next byte typed, or `None` if there is none waiting.
+    fn read_byte(&mut self) -> io::Result<Option<u8>>;
+
+    /// Time elapsed since some fixed point.
+    fn now(&self) -> Duration;
+}
+
+/// [`Input`] reading from stdin, which is put in raw mode with `stty` until
+/// this is dropped.
+///
+/// A thread reads stdin, locking it for each read only. After this is
+/// dropped the thread stops at the next byte typed, which is discarded.
+#[derive(Debug)]
+pub struct RawStdin {
+    bytes: Receiver<u8>,
+    stop: Arc<AtomicBool>,
+    start: Instant,
+    saved: String,
+}
+
+impl RawStdin {
+    pub fn new() -> io::Result<Self> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+
+        let (send, bytes) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        thread::spawn(move || {
+            let mut byte = [0];
+            while !stopped.load(Ordering::Relaxed) {
+                match io::stdin().read(&mut byte) {
+                    Ok(1) if !stopped.load(Ordering::Relaxed) && send.send(byte[0]).is_ok() => {}
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    _ => break,
+                }
+            }
+        });
+
+        Ok(Self {
+            bytes,
+            stop,
+            start: Instant::now(),
+            saved,
+        })
+    }
+}
+
+impl Input for RawStdin {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        match self.bytes.try_recv() {
+            Ok(byte) => Ok(Some(byte)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+impl Drop for RawStdin {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = stty(&[self.saved.as_str()]);
+    }
+}
+
+/// Run `stty` on the terminal attached to stdin, returning what it printed.
+fn stty(args: &[&str]) -> io::Result<String> {
+    let out = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()?;
+
+    if !out.status.success() {
+        return Err(io::Error::other("stty failed"));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().into())
+}
+
+/// [`KeyState`] driven by key presses read from a terminal.
+///
+/// A key is held for the press timeout after it is typed. Terminals repeat
+/// held keys after a delay, each repeat keeps the key held for the shorter
+/// repeat timeout, so the key is released soon after the last one. The
+/// press timeout should be longer than the terminal's repeat delay.
+///
+/// Ctrl-C is reported as an [`Interrupted`](io::ErrorKind::Interrupted)
+/// error, as raw mode stops it from raising a signal.
+#[derive(Debug, Clone)]
+pub struct TerminalKeys<I> {
+    input: I,
+    keymap: [u8; 16],
+    press: Duration,
+    repeat: Duration,
+    /// When each key is released.
+    until: [Option<Duration>; 16],
+}
+
+impl<I: Input> TerminalKeys<I> {
+    /// Read keys from `input` with the [`QWERTY`] layout, holding keys for
+    /// 500ms after a press and 100ms after a repeat.
+    pub fn new(input: I) -> Self {
+        Self {
+            input,
+            keymap: QWERTY,
+            press: Duration::from_millis(500),
+            repeat: Duration::from_millis(100),
+            until: [None; 16],
+        }
+    }
+
+    /// Use `keymap[n]` as the character for key `n`. Letters match either
+    /// case.
+    pub fn with_keymap(mut self, keymap: [u8; 16]) -> Self {
+        self.keymap = keymap;
+        self
+    }
+
+    pub fn with_timeouts(mut self, press: Duration, repeat: Duration) -> Self {
+        (self.press, self.repeat) = (press, repeat);
+        self
+    }
+
+    pub fn input(&self) -> &I {
+        &self.input
+    }
+
+    pub fn input_mut(&mut self) -> &mut I {
+        &mut self.input
+    }
+
+    /// Bitmask of the held keys, bit `n` is key `n`.
+    pub fn held(&self) -> u16 {
+        let now = self.input.now();
+        (0..16)
+            .filter(|&key| self.until[key].is_some_and(|until| until > now))
+            .fold(0, |mask, key| mask | 1 << key)
+    }
+
+    /// Read everything typed so far.
+    pub fn update(&mut self) -> io::Result<()> {
+        while let Some(byte) = self.input.read_byte()? {
+            if byte == INTERRUPT {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+
+            let Some(key) = self
+                .keymap
+                .iter()
+                .position(|c| c.eq_ignore_ascii_case(&byte))
+            else {
+                continue;
+            };
+
+            let now = self.input.now();
+            let hold = match self.until[key] {
+                Some(until) if until > now => self.repeat,
+                _ => self.press,
+            };
+            self.until[key] = Some(now + hold);
+        }
+
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> I {
+        self.input
+    }
+}
+
+impl<I: Input> KeyState for TerminalKeys<I> {
+    type Error = io::Error;
+
+    fn keys<D: Delay>(&mut self, _delay: &mut D, _held: Keys) -> Result<Keys, Self::Error> {
+        self.update()?;
+        Ok(Keys(self.held()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::string::String;
+
+    use super::*;
+
+    /// Present the changes to `frame`, returning what was written.
+    fn present(terminal: &mut TerminalPresenter<Vec<u8>>, frame: &mut FrameBuffer) -> String {
+        let regions: Vec<Region> = frame.dirty().collect();
+        terminal.present(frame, &regions).unwrap();
+        frame.mark_presented();
+        String::from_utf8(core::mem::take(terminal.get_mut())).unwrap()
+    }
+
+    #[test]
+    fn half_block() {
+        let mut terminal = TerminalPresenter::new(Vec::new())
+            .with_colors(Color::Rgb(0, 255, 0), Color::Indexed(0));
+        let mut frame = FrameBuffer::new();
+        assert_eq!(terminal.size(frame.resolution()), (64, 16));
+
+        frame.draw_sprite(0, 0, &[0xC0, 0x80]);
+        frame.draw_sprite(62, 31, &[0x40]);
+
+        let first = present(&mut terminal, &mut frame);
+        assert!(first.starts_with("\x1b[38;2;0;255;0;48;5;0m\x1b[2J\x1b[1;1H█▀ "));
+        assert!(first.ends_with(" ▄\x1b[0m"));
+        assert_eq!(first.matches("\x1b[").count(), 3 + 16);
+
+        // Nothing changed, nothing is written.
+        assert_eq!(present(&mut terminal, &mut frame), "");
+
+        // Only changed cells are written, the cursor moves past the others.
+        frame.draw_sprite(1, 1, &[0xC0]);
+        frame.draw_sprite(10, 4, &[0x80]);
+        assert_eq!(
+            present(&mut terminal, &mut frame),
+            "\x1b[38;2;0;255;0;48;5;0m\x1b[1;2H█▄\x1b[3;11H▀\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn braille() {
+        let mut terminal = TerminalPresenter::new(Vec::new()).with_glyphs(Glyphs::Braille);
+        let mut frame = FrameBuffer::new();
+        frame.set_resolution(Resolution::Extended);
+        assert_eq!(terminal.size(frame.resolution()), (64, 16));
+
+        frame.draw_sprite(0, 0, &[0x80, 0x40, 0x80, 0xC0]);
+
+        let out = present(&mut terminal, &mut frame);
+        assert!(out.starts_with("\x1b[39;49m\x1b[2J\x1b[1;1H\u{28D5}\u{2800}"));
+        assert_eq!(out.matches('\u{2800}').count(), 64 * 16 - 1);
+
+        frame.reset();
+        assert_eq!(
+            present(&mut terminal, &mut frame),
+            "\x1b[39;49m\x1b[1;1H\u{2800}\x1b[0m"
+        );
+
+        // A new size clears the terminal.
+        frame.set_resolution(Resolution::Lores);
+        assert!(present(&mut terminal, &mut frame).contains("\x1b[2J"));
+    }
+
+    #[test]
+    fn vm() {
+        use crate::hal::headless::{HeldKeys, NoDelay, NullBuzzer, XorShift};
+        use crate::vm::mem::Load;
+        use crate::vm::Chip8;
+
+        let terminal: Terminal<Vec<u8>> = Video::new(TerminalPresenter::new(Vec::new()));
+        let mut chip = Chip8::new(
+            terminal,
+            HeldKeys::default(),
+            NullBuzzer::default(),
+            XorShift::default(),
+            NoDelay,
+        );
+        chip.state_mut()
+            .ram
+            .load(
+                0x200,
+                &crate::chip8_asm! {
+                    ldi 0x300;
+                    drw 0, 0, 2;
+                    drw 0, 0, 1;
+                },
+            )
+            .unwrap();
+        chip.state_mut().ram.load(0x300, &[0xC0u8, 0x80]).unwrap();
+        chip.init().unwrap();
+
+        // Sprites are drawn by the VM, and presented once per frame.
+        chip.step().unwrap();
+        chip.step().unwrap();
+        chip.step().unwrap();
+        assert!(chip.screen().presenter().get_ref().is_empty());
+        chip.present().unwrap();
+        assert_eq!(chip.state().reg.get(0xF).unwrap(), 1);
+
+        let out = core::mem::take(chip.screen_mut().presenter_mut().get_mut());
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\x1b[2J\x1b[1;1H▄ "));
+
+        // Invalidating redraws everything on the next refresh.
+        chip.screen_mut().presenter_mut().invalidate();
+        chip.refresh().unwrap();
+        let out = core::mem::take(chip.screen_mut().presenter_mut().get_mut());
+        assert_eq!(
+            String::from_utf8(out).unwrap().matches("\x1b[2J").count(),
+            1
+        );
+    }
+
+    /// Bytes typed at given times, the clock is moved by the test.
+    #[derive(Default)]
+    struct Typed {
+        bytes: std::collections::VecDeque<(u64, u8)>,
+        now: u64,
+    }
+
+    impl Typed {
+        fn at(&mut self, ms: u64, text: &[u8]) {
+            self.bytes.extend(text.iter().map(|&byte| (ms, byte)));
+        }
+    }
+
+    impl Input for Typed {
+        fn read_byte(&mut self) -> io::Result<Option<u8>> {
+            match self.bytes.front() {
+                Some(&(ms, byte)) if ms <= self.now => {
+                    self.bytes.pop_front();
+                    Ok(Some(byte))
+                }
+                _ => Ok(None),
+            }
+        }
+
+        fn now(&self) -> Duration {
+            Duration::from_millis(self.now)
+        }
+    }
+
+    fn held_at(keys: &mut TerminalKeys<Typed>, ms: u64) -> u16 {
+        keys.input_mut().now = ms;
+        keys.update().unwrap();
+        keys.held()
+    }
+
+    #[test]
+    fn keymap() {
+        let mut keys = TerminalKeys::new(Typed::default());
+        keys.input_mut().at(0, b"1Qx4v-");
+        assert_eq!(
+            held_at(&mut keys, 0),
+            1 << 1 | 1 << 4 | 1 << 0 | 1 << 0xC | 1 << 0xF
+        );
+        assert_eq!(
+            keys.keys(&mut crate::hal::headless::NoDelay, Keys::NONE)
+                .unwrap(),
+            Keys(1 << 1 | 1 << 4 | 1 << 0 | 1 << 0xC | 1 << 0xF)
+        );
+
+        let mut keymap = QWERTY;
+        keymap[0] = b' ';
+        let mut keys = TerminalKeys::new(Typed::default()).with_keymap(keymap);
+        keys.input_mut().at(0, b"x ");
+        assert_eq!(held_at(&mut keys, 0), 1);
+
+        keys.input_mut().at(0, b"\x03w");
+        assert_eq!(
+            keys.update().unwrap_err().kind(),
+            io::ErrorKind::Interrupted
+        );
+    }
+
+    #[test]
+    fn hold() {
+        let mut keys = TerminalKeys::new(Typed::default())
+            .with_timeouts(Duration::from_millis(500), Duration::from_millis(100));
+
+        // A tap is held for the press timeout.
+        keys.input_mut().at(0, b"w");
+        assert_eq!(held_at(&mut keys, 0), 1 << 5);
+        assert!(keys
+            .keys(&mut crate::hal::headless::NoDelay, Keys::NONE)
+            .unwrap()
+            .any());
+        assert_eq!(held_at(&mut keys, 499), 1 << 5);
+        assert_eq!(held_at(&mut keys, 500), 0);
+        assert!(!keys
+            .keys(&mut crate::hal::headless::NoDelay, Keys::NONE)
+            .unwrap()
+            .any());
+
+        // Repeats keep it held until shortly after the last one.
+        keys.input_mut().at(1000, b"w");
+        keys.input_mut().at(1400, b"w");
+        keys.input_mut().at(1450, b"w");
+        keys.input_mut().at(1500, b"s");
+        for ms in [1000, 1400, 1450, 1500] {
+            assert_eq!(held_at(&mut keys, ms) & 1 << 5, 1 << 5);
+        }
+        assert_eq!(held_at(&mut keys, 1549), 1 << 5 | 1 << 8);
+        assert_eq!(held_at(&mut keys, 1550), 1 << 8);
+        assert_eq!(held_at(&mut keys, 2000), 0);
+    }
+}
@@ -73,6 +73,10 @@ instruction_set! {
         cls -> 0x00E0;
     "Return from a subroutine.";
         ret -> 0x00EE;
+    "Switch to the 64x32 display.";
+        low -> 0x00FE;
+    "Switch to the 128x64 display.";
+        high -> 0x00FF;
     "Jump to location `addr`.";
         jp addr -> 0x1000;
     "Call subroutine at `addr`.";
@@ -139,6 +143,274 @@ instruction_set! {
         ldiv vx -> 0xF065;
 }
 
+/// A decoded instruction, named after the function which encodes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Cls,
+    Ret,
+    Low,
+    High,
+    Jp(u16),
+    Call(u16),
+    Se(u8, u8),
+    Sne(u8, u8),
+    Sev(u8, u8),
+    Ld(u8, u8),
+    Add(u8, u8),
+    Ldv(u8, u8),
+    Or(u8, u8),
+    And(u8, u8),
+    Xor(u8, u8),
+    Addv(u8, u8),
+    Sub(u8, u8),
+    Shr(u8),
+    Subn(u8, u8),
+    Shl(u8),
+    Snev(u8, u8),
+    Ldi(u16),
+    Jp0(u16),
+    Rnd(u8, u8),
+    Drw(u8, u8, u8),
+    Skp(u8),
+    Sknp(u8),
+    Lddtv(u8),
+    Ldkey(u8),
+    Lddt(u8),
+    Ldst(u8),
+    Addi(u8),
+    Sprite(u8),
+    Bcd(u8),
+    Sviv(u8),
+    Ldiv(u8),
+}
+
+impl Instruction {
+    /// Decode an opcode, returns `None` if the VM would not execute it.
+    pub fn decode(opcode: u16) -> Option<Self> {
+        use Instruction::*;
+
+        let addr = opcode & 0x0FFF;
+        let byte = opcode as u8;
+        let nibble = byte & 0xF;
+        let vx = (addr >> 8) as u8;
+        let vy = byte >> 4;
+
+        Some(match (opcode >> 12, nibble) {
+            (0, _) if addr == 0x0E0 => Cls,
+            (0, _) if addr == 0x0EE => Ret,
+            (0, _) if addr == 0x0FE => Low,
+            (0, _) if addr == 0x0FF => High,
+            (1, _) => Jp(addr),
+            (2, _) => Call(addr),
+            (3, _) => Se(vx, byte),
+            (4, _) => Sne(vx, byte),
+            (5, 0) => Sev(vx, vy),
+            (6, _) => Ld(vx, byte),
+            (7, _) => Add(vx, byte),
+            (8, 0) => Ldv(vx, vy),
+            (8, 1) => Or(vx, vy),
+            (8, 2) => And(vx, vy),
+            (8, 3) => Xor(vx, vy),
+            (8, 4) => Addv(vx, vy),
+            (8, 5) => Sub(vx, vy),
+            (8, 6) => Shr(vx),
+            (8, 7) => Subn(vx, vy),
+            (8, 0xE) => Shl(vx),
+            (9, 0) => Snev(vx, vy),
+            (0xA, _) => Ldi(addr),
+            (0xB, _) => Jp0(addr),
+            (0xC, _) => Rnd(vx, byte),
+            (0xD, _) => Drw(vx, vy, nibble),
+            (0xE, _) if byte == 0x9E => Skp(vx),
+            (0xE, _) if byte == 0xA1 => Sknp(vx),
+            (0xF, _) => match byte {
+                0x07 => Lddtv(vx),
+                0x0A => Ldkey(vx),
+                0x15 => Lddt(vx),
+                0x18 => Ldst(vx),
+                0x1E => Addi(vx),
+                0x29 => Sprite(vx),
+                0x33 => Bcd(vx),
+                0x55 => Sviv(vx),
+                0x65 => Ldiv(vx),
+                _ => return None,
+            },
+            _ => return None,
+        })
+    }
+
+    /// Encode the instruction as an opcode.
+    pub fn encode(self) -> u16 {
+        use Instruction::*;
+
+        match self {
+            Cls => cls(),
+            Ret => ret(),
+            Low => low(),
+            High => high(),
+            Jp(addr) => jp(addr),
+            Call(addr) => call(addr),
+            Se(vx, byte) => se(vx, byte),
+            Sne(vx, byte) => sne(vx, byte),
+            Sev(vx, vy) => sev(vx, vy),
+            Ld(vx, byte) => ld(vx, byte),
+            Add(vx, byte) => add(vx, byte),
+            Ldv(vx, vy) => ldv(vx, vy),
+            Or(vx, vy) => or(vx, vy),
+            And(vx, vy) => and(vx, vy),
+            Xor(vx, vy) => xor(vx, vy),
+            Addv(vx, vy) => addv(vx, vy),
+            Sub(vx, vy) => sub(vx, vy),
+            Shr(vx) => shr(vx),
+            Subn(vx, vy) => subn(vx, vy),
+            Shl(vx) => shl(vx),
+            Snev(vx, vy) => snev(vx, vy),
+            Ldi(addr) => ldi(addr),
+            Jp0(addr) => jp0(addr),
+            Rnd(vx, byte) => rnd(vx, byte),
+            Drw(vx, vy, nibble) => drw(vx, vy, nibble),
+            Skp(vx) => skp(vx),
+            Sknp(vx) => sknp(vx),
+            Lddtv(vx) => lddtv(vx),
+            Ldkey(vx) => ldkey(vx),
+            Lddt(vx) => lddt(vx),
+            Ldst(vx) => ldst(vx),
+            Addi(vx) => addi(vx),
+            Sprite(vx) => sprite(vx),
+            Bcd(vx) => bcd(vx),
+            Sviv(vx) => sviv(vx),
+            Ldiv(vx) => ldiv(vx),
+        }
+    }
+
+    /// The bits of the opcode which identify this kind of instruction,
+    /// excluding its operands.
+    pub fn mask(&self) -> u16 {
+        use Instruction::*;
+
+        match self {
+            Cls | Ret | Low | High => 0xFFFF,
+            Jp(_) | Call(_) | Se(..) | Sne(..) | Ld(..) | Add(..) | Ldi(_) | Jp0(_) | Rnd(..)
+            | Drw(..) => 0xF000,
+            Sev(..) | Ldv(..) | Or(..) | And(..) | Xor(..) | Addv(..) | Sub(..) | Shr(_)
+            | Subn(..) | Shl(_) | Snev(..) => 0xF00F,
+            Skp(_) | Sknp(_) | Lddtv(_) | Ldkey(_) | Lddt(_) | Ldst(_) | Addi(_) | Sprite(_)
+            | Bcd(_) | Sviv(_) | Ldiv(_) => 0xF0FF,
+        }
+    }
+
+    /// The instruction with placeholder operands, e.g. `"DRW Vx, Vy, n"`.
+    pub fn syntax(&self) -> &'static str {
+        use Instruction::*;
+
+        match self {
+            Cls => "CLS",
+            Ret => "RET",
+            Low => "LOW",
+            High => "HIGH",
+            Jp(_) => "JP addr",
+            Call(_) => "CALL addr",
+            Se(..) => "SE Vx, byte",
+            Sne(..) => "SNE Vx, byte",
+            Sev(..) => "SE Vx, Vy",
+            Ld(..) => "LD Vx, byte",
+            Add(..) => "ADD Vx, byte",
+            Ldv(..) => "LD Vx, Vy",
+            Or(..) => "OR Vx, Vy",
+            And(..) => "AND Vx, Vy",
+            Xor(..) => "XOR Vx, Vy",
+            Addv(..) => "ADD Vx, Vy",
+            Sub(..) => "SUB Vx, Vy",
+            Shr(_) => "SHR Vx",
+            Subn(..) => "SUBN Vx, Vy",
+            Shl(_) => "SHL Vx",
+            Snev(..) => "SNE Vx, Vy",
+            Ldi(_) => "LD I, addr",
+            Jp0(_) => "JP V0, addr",
+            Rnd(..) => "RND Vx, byte",
+            Drw(..) => "DRW Vx, Vy, n",
+            Skp(_) => "SKP Vx",
+            Sknp(_) => "SKNP Vx",
+            Lddtv(_) => "LD Vx, DT",
+            Ldkey(_) => "LD Vx, K",
+            Lddt(_) => "LD DT, Vx",
+            Ldst(_) => "LD ST, Vx",
+            Addi(_) => "ADD I, Vx",
+            Sprite(_) => "LD F, Vx",
+            Bcd(_) => "LD B, Vx",
+            Sviv(_) => "LD [I], Vx",
+            Ldiv(_) => "LD Vx, [I]",
+        }
+    }
+
+    /// The assembler mnemonic, e.g. `"DRW"`.
+    pub fn mnemonic(&self) -> &'static str {
+        use Instruction::*;
+
+        match self {
+            Cls => "CLS",
+            Ret => "RET",
+            Low => "LOW",
+            High => "HIGH",
+            Jp(_) | Jp0(_) => "JP",
+            Call(_) => "CALL",
+            Se(..) | Sev(..) => "SE",
+            Sne(..) | Snev(..) => "SNE",
+            Add(..) | Addv(..) | Addi(_) => "ADD",
+            Or(..) => "OR",
+            And(..) => "AND",
+            Xor(..) => "XOR",
+            Sub(..) => "SUB",
+            Shr(_) => "SHR",
+            Subn(..) => "SUBN",
+            Shl(_) => "SHL",
+            Rnd(..) => "RND",
+            Drw(..) => "DRW",
+            Skp(_) => "SKP",
+            Sknp(_) => "SKNP",
+            Ld(..) | Ldv(..) | Ldi(_) | Lddtv(_) | Ldkey(_) | Lddt(_) | Ldst(_) | Sprite(_)
+            | Bcd(_) | Sviv(_) | Ldiv(_) => "LD",
+        }
+    }
+}
+
+impl core::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use Instruction::*;
+
+        let op = self.mnemonic();
+        match *self {
+            Cls | Ret | Low | High => write!(f, "{op}"),
+            Jp(addr) | Call(addr) => write!(f, "{op} 0x{addr:03X}"),
+            Se(vx, byte) | Sne(vx, byte) | Ld(vx, byte) | Add(vx, byte) | Rnd(vx, byte) => {
+                write!(f, "{op} V{vx:X}, 0x{byte:02X}")
+            }
+            Sev(vx, vy)
+            | Snev(vx, vy)
+            | Ldv(vx, vy)
+            | Or(vx, vy)
+            | And(vx, vy)
+            | Xor(vx, vy)
+            | Addv(vx, vy)
+            | Sub(vx, vy)
+            | Subn(vx, vy) => write!(f, "{op} V{vx:X}, V{vy:X}"),
+            Shr(vx) | Shl(vx) | Skp(vx) | Sknp(vx) => write!(f, "{op} V{vx:X}"),
+            Ldi(addr) => write!(f, "{op} I, 0x{addr:03X}"),
+            Jp0(addr) => write!(f, "{op} V0, 0x{addr:03X}"),
+            Drw(vx, vy, nibble) => write!(f, "{op} V{vx:X}, V{vy:X}, {nibble}"),
+            Lddtv(vx) => write!(f, "{op} V{vx:X}, DT"),
+            Ldkey(vx) => write!(f, "{op} V{vx:X}, K"),
+            Lddt(vx) => write!(f, "{op} DT, V{vx:X}"),
+            Ldst(vx) => write!(f, "{op} ST, V{vx:X}"),
+            Addi(vx) => write!(f, "{op} I, V{vx:X}"),
+            Sprite(vx) => write!(f, "{op} F, V{vx:X}"),
+            Bcd(vx) => write!(f, "{op} B, V{vx:X}"),
+            Sviv(vx) => write!(f, "{op} [I], V{vx:X}"),
+            Ldiv(vx) => write!(f, "{op} V{vx:X}, [I]"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -179,6 +451,8 @@ mod tests {
         assert_eq!(bcd(1), 0xF133);
         assert_eq!(sviv(1), 0xF155);
         assert_eq!(ldiv(1), 0xF165);
+        assert_eq!(low(), 0x00FE);
+        assert_eq!(high(), 0x00FF);
     }
 
     #[test]
@@ -192,4 +466,41 @@ mod tests {
 
         assert_eq!(prog, [0x00E0, 0x1123, 0xD123, 0x00EE]);
     }
+
+    #[test]
+    fn decode() {
+        for opcode in 0..=u16::MAX {
+            if let Some(inst) = Instruction::decode(opcode) {
+                assert_eq!(Instruction::decode(inst.encode()), Some(inst));
+            }
+        }
+
+        assert_eq!(Instruction::decode(0xD123), Some(Instruction::Drw(1, 2, 3)));
+        assert_eq!(Instruction::decode(0x8126), Some(Instruction::Shr(1)));
+        assert_eq!(Instruction::decode(0x0123), None);
+        assert_eq!(Instruction::decode(0x5121), None);
+        assert_eq!(Instruction::decode(0xF1FF), None);
+
+        assert_eq!(Instruction::Drw(1, 2, 3).mask(), 0xF000);
+        assert_eq!(Instruction::Shr(1).mask() & 0x8136, 0x8006);
+    }
+
+    #[test]
+    fn display() {
+        extern crate std;
+        use std::string::ToString;
+
+        let text = |opcode| Instruction::decode(opcode).unwrap().to_string();
+
+        assert_eq!(text(0x00E0), "CLS");
+        assert_eq!(text(0x00FF), "HIGH");
+        assert_eq!(text(0x2456), "CALL 0x456");
+        assert_eq!(text(0x3A23), "SE VA, 0x23");
+        assert_eq!(text(0x8124), "ADD V1, V2");
+        assert_eq!(text(0xA123), "LD I, 0x123");
+        assert_eq!(text(0xB123), "JP V0, 0x123");
+        assert_eq!(text(0xD125), "DRW V1, V2, 5");
+        assert_eq!(text(0xF10A), "LD V1, K");
+        assert_eq!(text(0xF165), "LD V1, [I]");
+    }
 }
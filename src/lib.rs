@@ -1,6 +1,16 @@
 #![no_std]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "std")]
+extern crate std;
+
+#[cfg(feature = "std")]
+pub mod asm;
+pub mod debug;
 pub mod hal;
+#[allow(dead_code, unused_imports)]
 mod program;
 
 pub mod instruction;
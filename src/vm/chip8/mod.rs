@@ -1,41 +1,52 @@
+#[cfg_attr(test, allow(clippy::bool_assert_comparison))]
 mod timer;
 use timer::Timer;
 
-use crate::vm::mem::{Load, Mem, Ram, SPRITES};
+use crate::instruction::Instruction;
+#[cfg(feature = "video")]
+use crate::vm::display;
+use crate::vm::mem::Mem;
+use crate::vm::observer::{Access, AccessKind, Cpu, Exec, Observer};
 
-use super::error::{Error, Result};
-use crate::hal::{Buzzer, Delay, Keypad, Rng, Screen};
+use super::error::{ChipError, Error};
+use crate::hal::{Buzzer, Delay, Fault, KeyState, Keys, Resolution, Rng, Screen};
 
 #[cfg(test)]
+#[allow(unused_imports)]
 mod tests;
 
 const POLL_FREQ: u32 = 1000;
 const INST_STEP: u16 = 2;
 const REG_FLAG: u8 = 0x0F;
 
-pub struct Chip8<S, K, B, R, D>
+type Result<T, S, K, B, R, D> = core::result::Result<T, ChipError<S, K, B, R, D>>;
+
+pub struct Chip8<S, K, B, R, D, O = ()>
 where
     S: Screen,
-    K: Keypad,
+    K: KeyState,
     B: Buzzer,
     R: Rng,
     D: Delay,
+    O: Observer,
 {
     screen: S,
     keypad: K,
     buzzer: B,
     rng: R,
     delay: D,
+    observer: O,
     mem: Mem,
 }
 
-impl<S, K, B, R, D> Chip8<S, K, B, R, D>
+impl<S, K, B, R, D, O> Chip8<S, K, B, R, D, O>
 where
     S: Screen,
-    K: Keypad,
+    K: KeyState,
     B: Buzzer,
     R: Rng,
     D: Delay,
+    O: Observer,
 {
     // pub fn main<'a, P: Into<Program<'a>>>(&mut self, program: P) -> Result<u16> {
     //     self.sub(0x200, program)
@@ -50,26 +61,92 @@ where
     //     Ok(addr)
     // }
 
-    pub fn init(&mut self) -> Result {
+    pub fn init(&mut self) -> Result<(), S, K, B, R, D> {
         self.mem.pc = 0x200;
         Ok(())
     }
 
-    pub fn step(&mut self) -> Result {
-        self.read_inst(self.mem.pc).and_then(|inst| self.exec(inst))
+    /// Execute the instruction at pc. While `LD Vx, K` waits for a key it
+    /// polls once, then delays before returning without advancing.
+    pub fn step(&mut self) -> Result<(), S, K, B, R, D> {
+        if !self.execute()? {
+            if O::ENABLED {
+                self.observer.key_wait(self.mem.pc, POLL_FREQ);
+            }
+            self.delay
+                .delay_us(POLL_FREQ)
+                .map_err(|e| Error::Peripheral(Fault::Delay(e)))?;
+        }
+        Ok(())
+    }
+
+    /// Fetch and execute the instruction at pc, returning `false` if it was
+    /// `LD Vx, K` still waiting for a key. Polls are not reported to the
+    /// observer, the caller reports them with the time spent waiting.
+    fn execute(&mut self) -> Result<bool, S, K, B, R, D> {
+        let pc = self.mem.pc;
+        let opcode = self.read_inst(pc)?;
+        let waits = opcode & 0xF0FF == 0xF00A;
+
+        if !O::ENABLED {
+            self.exec(opcode)?;
+            return Ok(!waits || self.mem.pc != pc);
+        }
+
+        let exec = Exec {
+            pc,
+            opcode,
+            inst: Instruction::decode(opcode),
+            before: Cpu::from(&self.mem),
+        };
+
+        // LD Vx, K polls before the observer hears about it, so that it only
+        // sees the instruction once a key is released
+        if waits {
+            self.exec(opcode)?;
+            if self.mem.pc == pc {
+                return Ok(false);
+            }
+        }
+
+        self.fetched(pc, opcode);
+        self.observer.before(&exec);
+        if !waits {
+            self.exec(opcode)?;
+        }
+        self.observer.after(&exec, &Cpu::from(&self.mem));
+        Ok(true)
     }
 
-    pub fn run(&mut self, hz: u32) -> Result {
+    /// Execute instructions at `hz`, presenting the screen 60 times a second.
+    pub fn run(&mut self, hz: u32) -> Result<(), S, K, B, R, D> {
+        self.run_at(hz, 60)
+    }
+
+    /// Execute instructions at `hz`, presenting the screen `fps` times a
+    /// second. The timers always count down at 60 Hz, and the buzzer is on
+    /// while the sound timer is.
+    pub fn run_at(&mut self, hz: u32, fps: u32) -> Result<(), S, K, B, R, D> {
         let tick = if hz >= 60 {
             Timer::hertz_to_us(hz).ok_or(Error::ClockSpeed(hz))
         } else {
             Err(Error::ClockSpeed(hz))
         }?;
 
+        let mut frame = match Timer::new(fps) {
+            Some(frame) if fps <= hz => frame,
+            _ => return Err(Error::FrameRate(fps)),
+        };
         let mut dt = Timer::new(60).unwrap();
         let mut st = Timer::new(60).unwrap();
+        let mut audio = Timer::new(60).unwrap();
+        let mut buzzing = None;
 
         loop {
+            if frame.update(true, tick) {
+                self.present()?;
+            }
+
             if dt.update(self.mem.dt > 0, tick) {
                 self.mem.dt -= 1;
             }
@@ -78,12 +155,80 @@ where
                 self.mem.st -= 1;
             }
 
-            self.step()?;
-            self.delay.delay_us(tick).map_err(|e| e.into())?;
+            let sound = self.mem.st > 0;
+            if buzzing != Some(sound) {
+                match sound {
+                    true => self.buzzer.on(),
+                    false => self.buzzer.off(),
+                }
+                .map_err(|e| Error::Peripheral(Fault::Buzzer(e)))?;
+                buzzing = Some(sound);
+            }
+
+            if audio.update(true, tick) {
+                self.buzzer
+                    .frame()
+                    .map_err(|e| Error::Peripheral(Fault::Buzzer(e)))?;
+            }
+
+            // A poll by LD Vx, K takes the place of an instruction, so the
+            // timers keep their rate while it waits
+            if !self.execute()? && O::ENABLED {
+                self.observer.key_wait(self.mem.pc, tick);
+            }
+            self.delay
+                .delay_us(tick)
+                .map_err(|e| Error::Peripheral(Fault::Delay(e)))?;
+        }
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        self.mem.resolution
+    }
+
+    /// Switch the display to `resolution`, e.g. to run ETI-660 or Hires
+    /// CHIP-8 programs. `LOW` and `HIGH` switch between 64x32 and 128x64.
+    pub fn set_resolution(&mut self, resolution: Resolution) -> Result<(), S, K, B, R, D> {
+        #[cfg(feature = "video")]
+        if self.screen.presents_frame() {
+            self.mem.frame.set_resolution(resolution);
+            self.mem.resolution = resolution;
+            return Ok(());
+        }
+
+        self.screen
+            .set_resolution(resolution)
+            .map_err(|e| Error::Peripheral(Fault::Screen(e)))?;
+        self.mem.resolution = resolution;
+        Ok(())
+    }
+
+    /// Show what was drawn since the last frame, see [`Screen::present`].
+    /// Screens which only present the VM's frame are handed its changes.
+    pub fn present(&mut self) -> Result<(), S, K, B, R, D> {
+        #[cfg(feature = "video")]
+        if self.screen.presents_frame() {
+            return display::present(&mut self.screen, &mut self.mem.frame)
+                .map_err(|e| Error::Peripheral(Fault::Screen(e)));
+        }
+
+        self.screen
+            .present()
+            .map_err(|e| Error::Peripheral(Fault::Screen(e)))
+    }
+
+    /// Present the whole of the VM's frame again, e.g. after the host window
+    /// was exposed. Does nothing unless the screen only presents the frame.
+    #[cfg(feature = "video")]
+    pub fn refresh(&mut self) -> Result<(), S, K, B, R, D> {
+        if self.screen.presents_frame() {
+            display::refresh(&mut self.screen, &mut self.mem.frame)
+                .map_err(|e| Error::Peripheral(Fault::Screen(e)))?;
         }
+        Ok(())
     }
 
-    fn read_inst(&mut self, addr: u16) -> Result<u16> {
+    fn read_inst(&self, addr: u16) -> Result<u16, S, K, B, R, D> {
         if self.mem.ram.to_read_addr(addr)? % INST_STEP == 0 {
             Ok(u16::from_be_bytes([
                 self.mem.ram.read_byte(addr)?,
@@ -94,11 +239,27 @@ where
         }
     }
 
-    fn read_key(keypad: &mut K, delay: &mut D) -> Result<Option<u8>> {
-        keypad.read_key(delay).map_err(|e| e.into().into())
+    /// Report the fetch of `opcode` from `addr` to the observer.
+    fn fetched(&mut self, addr: u16, opcode: u16) {
+        for (loc, value) in (addr..).zip(opcode.to_be_bytes()) {
+            self.observer.access(&Access {
+                pc: addr,
+                addr: loc,
+                value,
+                kind: AccessKind::Fetch,
+            });
+        }
+    }
+
+    /// Sample the keypad into `keys`, returning the previous sample.
+    fn sample(keypad: &mut K, delay: &mut D, keys: &mut Keys) -> Result<Keys, S, K, B, R, D> {
+        let now = keypad
+            .keys(delay, *keys)
+            .map_err(|e| Error::Peripheral(Fault::Keypad(e)))?;
+        Ok(core::mem::replace(keys, now))
     }
 
-    fn exec(&mut self, instruction: u16) -> Result {
+    fn exec(&mut self, instruction: u16) -> Result<(), S, K, B, R, D> {
         let cmd = instruction >> 12;
         let addr = instruction & 0x0FFF;
         let byte = addr as u8;
@@ -113,6 +274,11 @@ where
             reg,
             stack,
             ram,
+            resolution,
+            keys,
+            key_down,
+            #[cfg(feature = "video")]
+            frame,
         } = &mut self.mem;
 
         let vx = reg.get(vx_addr)?;
@@ -134,6 +300,39 @@ where
             };
         }
 
+        /// Report a memory access to the observer
+        macro_rules! access {
+            ($kind: ident, $addr: expr, $value: expr) => {
+                if O::ENABLED {
+                    self.observer.access(&Access {
+                        pc: *pc,
+                        addr: $addr,
+                        value: $value,
+                        kind: AccessKind::$kind,
+                    });
+                }
+            };
+        }
+
+        /// Use the VM's frame if the screen only presents it, otherwise the
+        /// screen
+        macro_rules! display {
+            ($frame: ident => $video: expr, $screen: ident => $call: expr) => {{
+                #[cfg(feature = "video")]
+                let $frame = self.screen.presents_frame().then_some(&mut *frame);
+                #[cfg(not(feature = "video"))]
+                let $frame: Option<&mut crate::hal::framebuffer::FrameBuffer> = None;
+
+                match $frame {
+                    Some($frame) => $video,
+                    None => {
+                        let $screen = &mut self.screen;
+                        $call.map_err(|e| Error::Peripheral(Fault::Screen(e)))?
+                    }
+                }
+            }};
+        }
+
         /// Set the `vx` and flag registers
         macro_rules! set {
             (vf = $flag: expr) => {{
@@ -148,17 +347,30 @@ where
 
         match cmd {
             // CLS
-            0 if addr == 0x0E0 => self.screen.clear().map_err(|e| e.into())?,
+            0 if addr == 0x0E0 => display!(frame => frame.reset(), screen => screen.clear()),
 
             // RET
             0 if addr == 0x0EE => jump!(stack.pop()?),
 
+            // LOW, HIGH
+            0 if addr == 0x0FE || addr == 0x0FF => {
+                let mode = match addr {
+                    0x0FE => Resolution::Lores,
+                    _ => Resolution::Extended,
+                };
+                display!(
+                    frame => frame.set_resolution(mode),
+                    screen => screen.set_resolution(mode)
+                );
+                *resolution = mode;
+            }
+
             // JP addr
             1 => jump!(addr),
 
             // CALL addr
             2 => {
-                stack.push(*pc)?;
+                stack.push(*pc + INST_STEP)?;
                 jump!(addr);
             }
 
@@ -222,40 +434,75 @@ where
             0xB => jump!(addr + reg.get(0)? as u16),
 
             // // RND Vx, byte
-            0xC => set!(byte & self.rng.random().map_err(|e| e.into())?),
+            0xC => set!(
+                byte & self
+                    .rng
+                    .random()
+                    .map_err(|e| Error::Peripheral(Fault::Rng(e)))?
+            ),
+
+            // // DRW Vx, Vy, 0 draws a 16x16 sprite at 128x64
+            0xD if nibble == 0 && *resolution == Resolution::Extended => {
+                let data = ram.read_bytes(*i, 32)?;
+                let mut rows = [0; 16];
+                for ((loc, pair), row) in (*i..).step_by(2).zip(data.chunks(2)).zip(&mut rows) {
+                    access!(Sprite, loc, pair[0]);
+                    access!(Sprite, loc + 1, pair[1]);
+                    *row = u16::from_be_bytes([pair[0], pair[1]]);
+                }
+
+                let erased = display!(
+                    frame => frame.draw_wide_sprite(vx, vy, &rows),
+                    screen => screen.draw_wide(vx, vy, &rows)
+                );
+                set!(vf = erased as u8);
+            }
 
             // // DRW Vx, Vy, len
             0xD => {
                 let data = ram.read_bytes(*i, nibble)?;
-                let erased = self.screen.draw(vx, vy, data).map_err(|e| e.into())?;
+                for (loc, &value) in (*i..).zip(data) {
+                    access!(Sprite, loc, value);
+                }
+
+                let erased = display!(
+                    frame => frame.draw_sprite(vx, vy, data),
+                    screen => screen.draw(vx, vy, data)
+                );
                 set!(vf = erased as u8);
             }
 
             // // SKP Vx
-            0xE if byte == 0x9E => match Self::read_key(&mut self.keypad, &mut self.delay)? {
-                Some(key) => skip!(key == vx),
-                _ => (),
-            },
+            0xE if byte == 0x9E => {
+                Self::sample(&mut self.keypad, &mut self.delay, keys)?;
+                skip!(keys.is_down(vx));
+            }
 
             // // SKNP Vx
-            0xE if byte == 0xA1 => match Self::read_key(&mut self.keypad, &mut &mut self.delay)? {
-                Some(key) => skip!(key != vx),
-                _ => (),
-            },
+            0xE if byte == 0xA1 => {
+                Self::sample(&mut self.keypad, &mut self.delay, keys)?;
+                skip!(!keys.is_down(vx));
+            }
 
             0xF if byte == 0x07 => set!(*dt),
 
             // LD Vx, K
+            // A key must be pressed and released. Until then the
+            // instruction polls once and runs again on the next step, so
+            // the timers keep counting down. `execute` reports the polls.
             0xF if byte == 0x0A => {
-                let key = loop {
-                    if let Some(key) = Self::read_key(&mut self.keypad, &mut self.delay)? {
-                        break key;
-                    }
-
-                    self.delay.delay_us(POLL_FREQ).map_err(|e| e.into())?;
-                };
+                let before = Self::sample(&mut self.keypad, &mut self.delay, keys)?;
+                if key_down.is_none() {
+                    *key_down = keys.pressed(before).lowest();
+                }
 
-                set!(key);
+                match *key_down {
+                    Some(key) if !keys.is_down(key) => {
+                        *key_down = None;
+                        set!(key);
+                    }
+                    _ => return Ok(()),
+                }
             }
 
             // LD DT, Vx
@@ -272,22 +519,27 @@ where
 
             // LD B, Vx
             0xF if byte == 0x33 => {
-                ram.write_byte(*i, vx / 100)?;
-                ram.write_byte(i.saturating_add(1), (vx / 10) % 10)?;
-                ram.write_byte(i.saturating_add(2), vx % 10)?;
+                for (offset, digit) in [vx / 100, (vx / 10) % 10, vx % 10].into_iter().enumerate() {
+                    let loc = i.saturating_add(offset as u16);
+                    ram.write_byte(loc, digit)?;
+                    access!(Bcd, loc, digit);
+                }
             }
 
             // LD [I], Vx
             0xF if byte == 0x55 => {
                 for loc in 0..=vx_addr {
                     let val = reg.get(loc)?;
-                    ram.write_byte(i.saturating_add(loc.into()), val)?;
+                    let addr = i.saturating_add(loc.into());
+                    ram.write_byte(addr, val)?;
+                    access!(Store, addr, val);
                 }
             }
 
             // Ld Vx, [I]
             0xF if byte == 0x65 => {
                 for (&val, loc) in ram.read_bytes(*i, vx_addr + 1)?.iter().zip(0..=vx_addr) {
+                    access!(Load, i.saturating_add(loc.into()), val);
                     reg.set(loc, val)?;
                 }
             }
@@ -303,7 +555,7 @@ where
 impl<S, K, B, R, D> Chip8<S, K, B, R, D>
 where
     S: Screen,
-    K: Keypad,
+    K: KeyState,
     B: Buzzer,
     R: Rng,
     D: Delay,
@@ -320,13 +572,100 @@ where
             buzzer,
             rng,
             delay,
+            observer: (),
         }
     }
+}
+
+impl<S, K, B, R, D, O> Chip8<S, K, B, R, D, O>
+where
+    S: Screen,
+    K: KeyState,
+    B: Buzzer,
+    R: Rng,
+    D: Delay,
+    O: Observer,
+{
+    /// Replace the observer, which is called around every instruction.
+    pub fn with_observer<T: Observer>(self, observer: T) -> Chip8<S, K, B, R, D, T> {
+        self.map_observer(|_| observer)
+    }
+
+    /// Replace the observer with one built from the current observer.
+    pub fn map_observer<T, F>(self, f: F) -> Chip8<S, K, B, R, D, T>
+    where
+        T: Observer,
+        F: FnOnce(O) -> T,
+    {
+        let Chip8 {
+            screen,
+            keypad,
+            buzzer,
+            rng,
+            delay,
+            observer,
+            mem,
+        } = self;
+
+        Chip8 {
+            screen,
+            keypad,
+            buzzer,
+            rng,
+            delay,
+            observer: f(observer),
+            mem,
+        }
+    }
+
+    pub fn screen(&self) -> &S {
+        &self.screen
+    }
+
+    pub fn screen_mut(&mut self) -> &mut S {
+        &mut self.screen
+    }
+
+    pub fn keypad(&self) -> &K {
+        &self.keypad
+    }
+
+    pub fn keypad_mut(&mut self) -> &mut K {
+        &mut self.keypad
+    }
+
+    pub fn buzzer(&self) -> &B {
+        &self.buzzer
+    }
+
+    pub fn buzzer_mut(&mut self) -> &mut B {
+        &mut self.buzzer
+    }
+
+    pub fn rng(&self) -> &R {
+        &self.rng
+    }
+
+    pub fn rng_mut(&mut self) -> &mut R {
+        &mut self.rng
+    }
+
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
 
     pub fn state(&self) -> &Mem {
         &self.mem
     }
 
+    pub fn state_mut(&mut self) -> &mut Mem {
+        &mut self.mem
+    }
+
     pub fn free(self) -> (S, K, B, R, D, Mem) {
         let Chip8 {
             screen,
@@ -335,6 +674,7 @@ where
             rng,
             delay,
             mem,
+            ..
         } = self;
 
         (screen, keypad, buzzer, rng, delay, mem)
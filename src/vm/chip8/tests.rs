@@ -1,9 +1,12 @@
 extern crate std;
 use super::Error;
 use super::{INST_STEP, REG_FLAG};
-use crate::hal::{chip, ScreenCommand};
+use crate::hal::{chip, Keys, Resolution, ScreenCommand};
+use crate::instruction::Instruction;
 use crate::vm::mem::{self, Load};
-use std::vec;
+use crate::vm::observer::{Access, AccessKind, Cpu, Exec, Observer};
+use crate::vm::trace::TraceWriter;
+use std::{string::String, vec, vec::Vec};
 
 /// Set multiple registers of a new chip8 mock, or read a single register.
 macro_rules! reg {
@@ -78,7 +81,7 @@ fn call_nnn() {
     chip.exec(0x2456).unwrap();
 
     assert_eq!(chip.mem.pc, 0x0456);
-    assert_eq!(chip.mem.stack.pop().unwrap(), 0x0123);
+    assert_eq!(chip.mem.stack.pop().unwrap(), 0x0125);
 }
 
 // 3xkk
@@ -365,6 +368,57 @@ fn drw_x_y_n() {
     assert_eq!(reg!(chip REG_FLAG), 0);
 }
 
+// Dxy0 - DRW Vx, Vy, 0
+// At 128x64 a 16x16 sprite is drawn from 32 bytes at I, two bytes per row.
+#[test]
+fn drw_x_y_0() {
+    let mut chip = chip!();
+    let data: Vec<u8> = (0..32).collect();
+
+    chip.mem.ram.load(0x300, &data[..]).unwrap();
+    chip.mem.i = 0x300;
+    chip.exec(0xD010).unwrap();
+    assert_eq!(chip.screen.commands, vec![ScreenCommand::xor(0, 0, &[])]);
+
+    chip.screen.commands.clear();
+    chip.set_resolution(Resolution::Extended).unwrap();
+    chip.exec(0xD010).unwrap();
+
+    let (left, right): (Vec<u8>, Vec<u8>) = data.chunks(2).map(|b| (b[0], b[1])).unzip();
+    assert_eq!(
+        chip.screen.commands,
+        vec![
+            ScreenCommand::Resolution(Resolution::Extended),
+            ScreenCommand::xor(0, 0, &left),
+            ScreenCommand::xor(8, 0, &right),
+        ]
+    );
+}
+
+// 00FE - LOW, 00FF - HIGH
+// Switch between the 64x32 and 128x64 displays.
+#[test]
+fn low_high() {
+    let mut chip = chip!();
+    assert_eq!(chip.resolution(), Resolution::Lores);
+
+    chip.exec(0x00FF).unwrap();
+    assert_eq!(chip.resolution(), Resolution::Extended);
+    chip.exec(0x00FE).unwrap();
+    assert_eq!(chip.resolution(), Resolution::Lores);
+    chip.set_resolution(Resolution::Eti660).unwrap();
+    assert_eq!(chip.state().resolution, Resolution::Eti660);
+
+    assert_eq!(
+        chip.screen.commands,
+        vec![
+            ScreenCommand::Resolution(Resolution::Extended),
+            ScreenCommand::Resolution(Resolution::Lores),
+            ScreenCommand::Resolution(Resolution::Eti660),
+        ]
+    );
+}
+
 // Ex9E - SKP Vx
 // Skip next instruction if key with the value of Vx is pressed.
 // Checks the keyboard, and if the key corresponding to the value of Vx is currently in the down position, PC is increased by 2.
@@ -377,9 +431,44 @@ fn skp_x() {
 
     assert_eq!(chip.mem.pc, 2 * INST_STEP);
 
+    // Sampling does not consume the key, 1 is still down.
     chip.mem.pc = 0;
+    chip.mem.reg.set(0, 2).unwrap();
     chip.exec(0xE09E).unwrap();
     assert_eq!(chip.mem.pc, INST_STEP);
+
+    chip.mem.pc = 0;
+    chip.mem.reg.set(0, 1).unwrap();
+    chip.exec(0xE09E).unwrap();
+    assert_eq!(chip.mem.pc, 2 * INST_STEP);
+}
+
+#[test]
+fn skp_x_multiple_keys() {
+    use crate::hal::headless::{HeldKeys, NoDelay, NullBuzzer, NullScreen, XorShift};
+
+    let mut keypad = HeldKeys::default();
+    keypad.press(3);
+    keypad.press(5);
+    let mut chip = super::Chip8::new(
+        NullScreen,
+        keypad,
+        NullBuzzer::default(),
+        XorShift::default(),
+        NoDelay,
+    );
+
+    for (key, down) in [(5, true), (3, true), (4, false), (5, true)] {
+        chip.mem.reg.set(0, key).unwrap();
+
+        chip.mem.pc = 0;
+        chip.exec(0xE09E).unwrap();
+        assert_eq!(chip.mem.pc == 2 * INST_STEP, down, "SKP {key}");
+
+        chip.mem.pc = 0;
+        chip.exec(0xE0A1).unwrap();
+        assert_eq!(chip.mem.pc == 2 * INST_STEP, !down, "SKNP {key}");
+    }
 }
 
 // ExA1 - SKNP Vx
@@ -395,6 +484,7 @@ fn sknp_x() {
     assert_eq!(chip.mem.pc, INST_STEP);
 
     chip.mem.pc = 0;
+    chip.mem.reg.set(0, 2).unwrap();
     chip.exec(0xE0A1).unwrap();
     assert_eq!(chip.mem.pc, 2 * INST_STEP);
 }
@@ -416,12 +506,104 @@ fn ld_x_dt() {
 // All execution stops until a key is pressed, then the value of that key is stored in Vx.
 #[test]
 fn ld_x_key() {
-    let mut chip = chip!(keys = [None, None, Some(1)]);
+    let mut chip = chip!(keys = [None, Some(1), None]);
+
+    // Polls without advancing until the key is pressed and released.
+    for _ in 0..2 {
+        chip.exec(0xF00A).unwrap();
+        assert_eq!(chip.mem.pc, 0);
+    }
+    assert_eq!(chip.mem.key_down, Some(1));
+
+    chip.exec(0xF00A).unwrap();
+    assert_eq!(chip.mem.pc, INST_STEP);
+    assert_eq!(chip.mem.key_down, None);
+    assert_eq!(reg!(chip 0), 1);
+}
+
+#[test]
+fn ld_x_key_edges() {
+    use crate::hal::headless::{HeldKeys, NoDelay, NullBuzzer, NullScreen, XorShift};
 
+    let mut keypad = HeldKeys::default();
+    keypad.press(2);
+    let mut chip = super::Chip8::new(
+        NullScreen,
+        keypad,
+        NullBuzzer::default(),
+        XorShift::default(),
+        NoDelay,
+    );
+
+    // Key 2 was already down when last sampled, so it does not count.
+    chip.mem.keys = Keys::key(2);
+    chip.exec(0xF00A).unwrap();
+    chip.keypad.press(1);
+    chip.exec(0xF00A).unwrap();
+    chip.keypad.release(2);
     chip.exec(0xF00A).unwrap();
+    assert_eq!(chip.mem.pc, 0);
+
+    chip.keypad.release(1);
+    chip.exec(0xF00A).unwrap();
+    assert_eq!(chip.mem.pc, INST_STEP);
     assert_eq!(reg!(chip 0), 1);
 }
 
+#[test]
+fn ld_x_key_timers() {
+    use crate::hal::mocks::Peripherals;
+    use crate::hal::Delay;
+
+    /// Delay which only adds up the time asked for.
+    #[derive(Default)]
+    struct Clock(u64);
+
+    impl Delay for Clock {
+        type Error = crate::hal::Error;
+
+        fn delay_us(&mut self, us: u32) -> core::result::Result<(), Self::Error> {
+            self.0 += us as u64;
+            Ok(())
+        }
+    }
+
+    // Key 1 is pressed after 600 polls, and released on the next.
+    let mut peri = Peripherals::default();
+    let mut keys = vec![None; 600];
+    keys.extend([Some(1), None]);
+    peri.keypad.set_sequence(keys);
+
+    let mut chip = super::Chip8::new(
+        peri.screen,
+        peri.keypad,
+        peri.buzzer,
+        peri.rng,
+        Clock::default(),
+    );
+    chip.mem
+        .ram
+        .load(
+            0x200,
+            &crate::chip8_asm! {
+                ld 0, 0xFF;
+                lddt 0;
+                ldkey 1;
+                lddtv 2;
+            },
+        )
+        .unwrap();
+    chip.init().unwrap();
+    assert!(matches!(chip.run_at(600, 60), Err(Error::Instruction(_))));
+
+    // The polls only take the time of the instructions they replace, so DT
+    // counts down at 60 Hz of the time spent.
+    let frames = 0xFF - reg!(chip 2) as u64;
+    let (_, _, _, _, clock, _) = chip.free();
+    assert!(frames > 50);
+    assert_eq!(clock.0 / 16_666, frames);
+}
+
 // Fx15 - LD DT, Vx
 // Set delay timer = Vx.
 // DT is set equal to the value of Vx.
@@ -533,3 +715,161 @@ fn ld_x_i() {
         assert_eq!(reg!(chip vx), (vx % 8) + 9);
     }
 }
+
+#[derive(Default)]
+struct Recorder {
+    events: Vec<(Exec, Option<Cpu>)>,
+    accesses: Vec<Access>,
+}
+
+impl Observer for Recorder {
+    fn before(&mut self, exec: &Exec) {
+        self.events.push((*exec, None));
+    }
+
+    fn after(&mut self, exec: &Exec, after: &Cpu) {
+        let last = self.events.last_mut().unwrap();
+        assert_eq!(&last.0, exec);
+        last.1 = Some(*after);
+    }
+
+    fn access(&mut self, access: &Access) {
+        self.accesses.push(*access);
+    }
+}
+
+#[test]
+fn observer() {
+    let mut chip = chip!().with_observer(Recorder::default());
+
+    chip.mem
+        .ram
+        .load(0x200, &[0x6A02u16, 0xA300, 0xFFFF])
+        .unwrap();
+    chip.init().unwrap();
+    chip.step().unwrap();
+    chip.step().unwrap();
+    chip.step().unwrap_err();
+
+    let events = &chip.observer().events;
+    assert_eq!(events.len(), 3);
+
+    let (exec, after) = events[0];
+    assert_eq!((exec.pc, exec.opcode), (0x200, 0x6A02));
+    assert_eq!(exec.inst, Some(Instruction::Ld(0xA, 0x02)));
+    assert_eq!(exec.before.v[0xA], 0);
+    assert_eq!(after.unwrap().v[0xA], 2);
+    assert_eq!(after.unwrap().pc, 0x202);
+
+    let (exec, after) = events[1];
+    assert_eq!(exec.before.i, 0);
+    assert_eq!(after.unwrap().i, 0x300);
+
+    let (exec, after) = events[2];
+    assert_eq!(exec.inst, None);
+    assert_eq!(after, None);
+}
+
+#[test]
+fn trace_writer() {
+    let mut chip = chip!().with_observer(TraceWriter::new(String::new()));
+
+    chip.mem.ram.load(0x200, &[0x6A02u16, 0xA123]).unwrap();
+    chip.mem.dt = 0x3C;
+    chip.init().unwrap();
+    chip.step().unwrap();
+    chip.step().unwrap();
+
+    assert_eq!(chip.observer().error(), None);
+    assert_eq!(
+        chip.observer().get_ref(),
+        "PC=0200 OP=6A02 I=0000 DT=3C ST=00 SP=00 V=00000000000000000000000000000000 ; LD VA, 0x02\n\
+         PC=0202 OP=A123 I=0000 DT=3C ST=00 SP=00 V=00000000000000000000020000000000 ; LD I, 0x123\n"
+    );
+}
+
+#[test]
+fn memory_access() {
+    let mut chip = chip!().with_observer(Recorder::default());
+    let access = |pc, addr, value, kind| Access {
+        pc,
+        addr,
+        value,
+        kind,
+    };
+
+    let program = [0x60FEu16, 0xA300, 0xF033, 0xD011, 0xF155, 0xF165];
+    chip.mem.ram.load(0x200, &program).unwrap();
+    chip.init().unwrap();
+
+    for _ in 0..program.len() {
+        chip.step().unwrap();
+    }
+
+    let accesses = &chip.observer().accesses;
+    let others: Vec<_> = accesses
+        .iter()
+        .filter(|access| access.kind != AccessKind::Fetch)
+        .copied()
+        .collect();
+
+    assert_eq!(accesses[0], access(0x200, 0x200, 0x60, AccessKind::Fetch));
+    assert_eq!(accesses[1], access(0x200, 0x201, 0xFE, AccessKind::Fetch));
+    assert_eq!(
+        others,
+        vec![
+            access(0x204, 0x300, 2, AccessKind::Bcd),
+            access(0x204, 0x301, 5, AccessKind::Bcd),
+            access(0x204, 0x302, 4, AccessKind::Bcd),
+            access(0x206, 0x300, 2, AccessKind::Sprite),
+            access(0x208, 0x300, 0xFE, AccessKind::Store),
+            access(0x208, 0x301, 0, AccessKind::Store),
+            access(0x20A, 0x300, 0xFE, AccessKind::Load),
+            access(0x20A, 0x301, 0, AccessKind::Load),
+        ]
+    );
+
+    assert!(AccessKind::Bcd.is_write());
+    assert!(AccessKind::Sprite.is_read());
+}
+
+#[test]
+fn peripheral_errors() {
+    use crate::hal::{headless, Fault, Screen};
+    use crate::vm::{Chip8, Error};
+
+    #[derive(Debug, PartialEq)]
+    struct Nack(u8);
+
+    struct Display;
+
+    impl Screen for Display {
+        type Error = Nack;
+
+        fn draw(&mut self, _x: u8, _y: u8, _data: &[u8]) -> Result<bool, Self::Error> {
+            Err(Nack(0x3C))
+        }
+
+        fn clear(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    let mut chip = Chip8::new(
+        Display,
+        headless::HeldKeys::default(),
+        headless::NullBuzzer::default(),
+        headless::XorShift::default(),
+        headless::NoDelay,
+    );
+    chip.mem
+        .ram
+        .load(0x200, &crate::chip8_asm! { cls; drw 0, 0, 1; })
+        .unwrap();
+    chip.init().unwrap();
+    chip.step().unwrap();
+
+    let err = chip.step().unwrap_err();
+    assert_eq!(err, Error::Peripheral(Fault::Screen(Nack(0x3C))));
+    assert_eq!(err.classify(), Error::Peripheral(crate::hal::Error::Screen));
+}
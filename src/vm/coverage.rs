@@ -0,0 +1,336 @@
+use core::fmt::{self, Write};
+use core::ops::Range;
+
+use super::mem::Ram;
+use super::observer::{Cpu, Exec, Observer};
+use crate::instruction::Instruction;
+
+const RAM_SIZE: usize = 0x1000;
+const INST_STEP: u16 = 2;
+
+/// Distinct skip instructions tracked, later ones are counted as executed
+/// but their branches are not recorded.
+pub const MAX_BRANCHES: usize = 256;
+
+/// Outcomes of one skip instruction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Branch {
+    pub addr: u16,
+    /// Times the next instruction was skipped.
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Observer which records which instructions ran and which way each skip
+/// instruction (`SE`, `SNE`, `SKP`, `SKNP`) went.
+///
+/// Reports are written in the lcov tracefile format. Instruction addresses
+/// are mapped to lines by the caller, either from an assembler source map
+/// or with [`Coverage::listing`] for a [`disassembly`](Coverage::write_disassembly).
+#[derive(Clone)]
+pub struct Coverage {
+    hits: [u64; RAM_SIZE],
+    branches: [Branch; MAX_BRANCHES],
+    nbranches: usize,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self {
+            hits: [0; RAM_SIZE],
+            branches: [Branch::default(); MAX_BRANCHES],
+            nbranches: 0,
+        }
+    }
+
+    /// How many times the instruction at `addr` was executed.
+    pub fn hits(&self, addr: u16) -> u64 {
+        self.hits.get(addr as usize).copied().unwrap_or(0)
+    }
+
+    pub fn executed(&self, addr: u16) -> bool {
+        self.hits(addr) > 0
+    }
+
+    /// Recorded skip instructions in the order they were first executed.
+    pub fn branches(&self) -> &[Branch] {
+        &self.branches[..self.nbranches]
+    }
+
+    pub fn branch(&self, addr: u16) -> Option<&Branch> {
+        self.branches().iter().find(|branch| branch.addr == addr)
+    }
+
+    /// Add the results of another run, e.g. to combine several test scripts.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (hits, more) in self.hits.iter_mut().zip(other.hits) {
+            *hits += more;
+        }
+
+        for branch in other.branches() {
+            if let Some(slot) = self.slot(branch.addr) {
+                slot.taken += branch.taken;
+                slot.not_taken += branch.not_taken;
+            }
+        }
+    }
+
+    /// `(addr, line)` pairs for the instructions in `range`, numbered the way
+    /// [`write_disassembly`](Self::write_disassembly) lays them out.
+    pub fn listing(range: Range<u16>) -> impl Iterator<Item = (u16, usize)> {
+        let start = range.start;
+        range
+            .step_by(INST_STEP as usize)
+            .map(move |addr| (addr, ((addr - start) / INST_STEP) as usize + 1))
+    }
+
+    /// Write one instruction per line for `range`, to act as the source file
+    /// when no assembler source is available.
+    pub fn write_disassembly<W: Write>(ram: &Ram, range: Range<u16>, out: &mut W) -> fmt::Result {
+        for (addr, _) in Self::listing(range) {
+            match ram.read_bytes(addr, 2) {
+                Ok(&[msb, lsb]) => {
+                    let opcode = u16::from_be_bytes([msb, lsb]);
+                    write!(out, "0x{addr:03X}: {opcode:04X}")?;
+                    match Instruction::decode(opcode) {
+                        Some(inst) => writeln!(out, "  {inst}")?,
+                        None => writeln!(out)?,
+                    }
+                }
+                _ => writeln!(out, "0x{addr:03X}: ????")?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write an lcov record for `path`, reporting every instruction given in
+    /// `lines` as `(addr, line)`.
+    pub fn write_lcov<W, I>(&self, out: &mut W, path: &str, lines: I) -> fmt::Result
+    where
+        W: Write,
+        I: IntoIterator<Item = (u16, usize)>,
+    {
+        let (mut found, mut hit) = (0, 0);
+        let (mut branches, mut taken) = (0, 0);
+
+        writeln!(out, "TN:")?;
+        writeln!(out, "SF:{path}")?;
+
+        for (addr, line) in lines {
+            let hits = self.hits(addr);
+            writeln!(out, "DA:{line},{hits}")?;
+
+            found += 1;
+            hit += (hits > 0) as u32;
+
+            if let Some(branch) = self.branch(addr) {
+                for (id, count) in [branch.taken, branch.not_taken].into_iter().enumerate() {
+                    writeln!(out, "BRDA:{line},0,{id},{count}")?;
+                    branches += 1;
+                    taken += (count > 0) as u32;
+                }
+            }
+        }
+
+        writeln!(out, "BRF:{branches}")?;
+        writeln!(out, "BRH:{taken}")?;
+        writeln!(out, "LF:{found}")?;
+        writeln!(out, "LH:{hit}")?;
+        writeln!(out, "end_of_record")
+    }
+
+    fn slot(&mut self, addr: u16) -> Option<&mut Branch> {
+        let idx = match self
+            .branches()
+            .iter()
+            .position(|branch| branch.addr == addr)
+        {
+            Some(idx) => idx,
+            None if self.nbranches < MAX_BRANCHES => {
+                self.branches[self.nbranches].addr = addr;
+                self.nbranches += 1;
+                self.nbranches - 1
+            }
+            None => return None,
+        };
+
+        Some(&mut self.branches[idx])
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let executed = self.hits.iter().filter(|hits| **hits > 0).count();
+        f.debug_struct("Coverage")
+            .field("executed", &executed)
+            .field("branches", &self.branches())
+            .finish()
+    }
+}
+
+impl Observer for Coverage {
+    fn after(&mut self, exec: &Exec, after: &Cpu) {
+        if let Some(hits) = self.hits.get_mut(exec.pc as usize) {
+            *hits += 1;
+        }
+
+        use Instruction::*;
+        if let Some(Se(..) | Sne(..) | Sev(..) | Snev(..) | Skp(_) | Sknp(_)) = exec.inst {
+            let skipped = after.pc == exec.pc.wrapping_add(2 * INST_STEP);
+            if let Some(branch) = self.slot(exec.pc) {
+                match skipped {
+                    true => branch.taken += 1,
+                    false => branch.not_taken += 1,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+
+    use super::*;
+    use crate::hal::chip;
+    use crate::vm::mem::Load;
+
+    #[test]
+    fn lcov() {
+        let mut chip = chip!().with_observer(Coverage::new());
+        chip.state_mut()
+            .ram
+            .load(
+                0x200,
+                &crate::chip8_asm! {
+                    add 0, 1;     // 0x200
+                    se 0, 3;      // 0x202
+                    jp 0x200;     // 0x204
+                    se 0, 3;      // 0x206
+                    cls;          // 0x208
+                    jp 0x20A;     // 0x20A
+                },
+            )
+            .unwrap();
+        chip.init().unwrap();
+
+        for _ in 0..12 {
+            chip.step().unwrap();
+        }
+
+        let cov = chip.observer();
+        assert_eq!(cov.hits(0x200), 3);
+        assert!(!cov.executed(0x208));
+        assert_eq!(
+            cov.branches(),
+            &[
+                Branch {
+                    addr: 0x202,
+                    taken: 1,
+                    not_taken: 2
+                },
+                Branch {
+                    addr: 0x206,
+                    taken: 1,
+                    not_taken: 0
+                }
+            ]
+        );
+
+        let mut merged = cov.clone();
+        merged.merge(cov);
+        assert_eq!(merged.hits(0x20A), 6);
+        assert_eq!(merged.branch(0x202).unwrap().not_taken, 4);
+
+        let mut listing = String::new();
+        Coverage::write_disassembly(&chip.state().ram, 0x200..0x20C, &mut listing).unwrap();
+        assert_eq!(listing.lines().nth(3), Some("0x206: 3003  SE V0, 0x03"));
+
+        let mut lcov = String::new();
+        cov.write_lcov(&mut lcov, "rom.dis", Coverage::listing(0x200..0x20C))
+            .unwrap();
+        assert_eq!(
+            lcov,
+            "TN:\n\
+             SF:rom.dis\n\
+             DA:1,3\n\
+             DA:2,3\n\
+             BRDA:2,0,0,1\n\
+             BRDA:2,0,1,2\n\
+             DA:3,2\n\
+             DA:4,1\n\
+             BRDA:4,0,0,1\n\
+             BRDA:4,0,1,0\n\
+             DA:5,0\n\
+             DA:6,3\n\
+             BRF:4\n\
+             BRH:3\n\
+             LF:6\n\
+             LH:5\n\
+             end_of_record\n"
+        );
+    }
+
+    #[test]
+    fn merge_past_u32() {
+        let mut cov = Coverage::new();
+        cov.hits[0x200] = u32::MAX.into();
+        cov.slot(0x200).unwrap().taken = u32::MAX.into();
+
+        let mut merged = cov.clone();
+        merged.merge(&cov);
+        assert_eq!(merged.hits(0x200), 2 * u32::MAX as u64);
+        assert_eq!(merged.branch(0x200).unwrap().taken, 2 * u32::MAX as u64);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn source_lines() {
+        let asm = crate::asm::assemble(
+            "\
+            loop:   add v0, 1
+                    sne v0, 2
+                    jp done
+                    jp loop
+            data:   db 1, 2
+            done:   jp done",
+        )
+        .unwrap();
+
+        let mut chip = chip!().with_observer(Coverage::new());
+        chip.state_mut().ram.load(0x200, &asm.bytes[..]).unwrap();
+        chip.init().unwrap();
+
+        for _ in 0..8 {
+            chip.step().unwrap();
+        }
+
+        let mut lcov = String::new();
+        chip.observer()
+            .write_lcov(&mut lcov, "game.asm", asm.map.instructions())
+            .unwrap();
+
+        let lines: std::vec::Vec<&str> = lcov.lines().collect();
+        assert_eq!(
+            lines[2..9],
+            [
+                "DA:1,2",
+                "DA:2,2",
+                "BRDA:2,0,0,1",
+                "BRDA:2,0,1,1",
+                "DA:3,1",
+                "DA:4,1",
+                "DA:6,2"
+            ]
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! Display state kept by the VM.
+//!
+//! With [`Video`] as the [`Screen`], the VM draws sprites into the
+//! [`FrameBuffer`] in its state and works out collisions itself, so they are
+//! the same on every backend. The backend only implements [`Present`], and
+//! is handed the frame along with the regions which changed once per frame
+//! rather than after every sprite.
+
+use crate::hal::framebuffer::{FrameBuffer, Region};
+use crate::hal::{Present, Resolution, Screen};
+
+/// [`Screen`] which has the VM draw into its own [`FrameBuffer`], and
+/// presents the changes when [`Chip8::present`](crate::vm::Chip8::present)
+/// is called, normally at the frame rate given to
+/// [`Chip8::run_at`](crate::vm::Chip8::run_at).
+#[derive(Debug, Clone)]
+pub struct Video<P> {
+    presenter: P,
+}
+
+impl<P: Present> Video<P> {
+    pub fn new(presenter: P) -> Self {
+        Self { presenter }
+    }
+
+    pub fn presenter(&self) -> &P {
+        &self.presenter
+    }
+
+    pub fn presenter_mut(&mut self) -> &mut P {
+        &mut self.presenter
+    }
+
+    pub fn into_inner(self) -> P {
+        self.presenter
+    }
+}
+
+/// Only the frame is presented, the VM does the drawing.
+impl<P: Present> Screen for Video<P> {
+    type Error = P::Error;
+
+    fn draw(&mut self, _x: u8, _y: u8, _data: &[u8]) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn presents_frame(&self) -> bool {
+        true
+    }
+
+    fn present_frame(
+        &mut self,
+        frame: &FrameBuffer,
+        regions: &[Region],
+    ) -> Result<(), Self::Error> {
+        self.presenter.present(frame, regions)
+    }
+}
+
+/// Hand the regions of `frame` which changed since the last call to
+/// `screen`.
+pub(super) fn present<S: Screen>(screen: &mut S, frame: &mut FrameBuffer) -> Result<(), S::Error> {
+    if !frame.is_dirty() {
+        return Ok(());
+    }
+
+    // Regions never share a row.
+    let mut regions = [Region::default(); Resolution::MAX_HEIGHT];
+    let mut len = 0;
+    for region in frame.dirty() {
+        regions[len] = region;
+        len += 1;
+    }
+
+    screen.present_frame(frame, &regions[..len])?;
+    frame.mark_presented();
+    Ok(())
+}
+
+/// Hand the whole of `frame` to `screen` again.
+pub(super) fn refresh<S: Screen>(screen: &mut S, frame: &mut FrameBuffer) -> Result<(), S::Error> {
+    let all = Region {
+        x: 0,
+        y: 0,
+        width: frame.width() as u8,
+        height: frame.height() as u8,
+    };
+    screen.present_frame(frame, &[all])?;
+    frame.mark_presented();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::hal::headless::{HeldKeys, NoDelay, NullBuzzer, XorShift};
+    use crate::vm::mem::Load;
+    use crate::vm::{Chip8, Error};
+
+    fn machine<F: FnMut(&FrameBuffer, &[Region])>(
+        presenter: F,
+    ) -> Chip8<Video<F>, HeldKeys, NullBuzzer, XorShift, NoDelay> {
+        let mut chip = Chip8::new(
+            Video::new(presenter),
+            HeldKeys::default(),
+            NullBuzzer::default(),
+            XorShift::default(),
+            NoDelay,
+        );
+        chip.state_mut()
+            .ram
+            .load(
+                0x200,
+                &crate::chip8_asm! {
+                    ldi 0x20A;
+                    drw 0, 0, 1;
+                    drw 0, 0, 1;
+                    drw 0, 0, 1;
+                    cls;
+                },
+            )
+            .unwrap();
+        chip.state_mut().ram.load(0x20A, &[0xC0u8]).unwrap();
+        chip.init().unwrap();
+        chip
+    }
+
+    #[test]
+    fn present() {
+        let mut frames = Vec::new();
+        let mut chip = machine(|frame: &FrameBuffer, regions: &[Region]| {
+            frames.push((frame.rows()[0] >> 64, regions.to_vec()))
+        });
+
+        let mut flags = Vec::new();
+        for _ in 0..5 {
+            chip.step().unwrap();
+            chip.present().unwrap();
+            flags.push(chip.state().reg.get(0xF).unwrap());
+        }
+
+        assert_eq!(flags, [0, 0, 1, 0, 0]);
+        assert!(!chip.state().frame.pixel(0, 0));
+
+        let _ = chip.free();
+        let sprite = Region {
+            x: 0,
+            y: 0,
+            width: 2,
+            height: 1,
+        };
+        assert_eq!(
+            frames,
+            [
+                (0xC0 << 56, [sprite].to_vec()),
+                (0, [sprite].to_vec()),
+                (0xC0 << 56, [sprite].to_vec()),
+                (0, [sprite].to_vec()),
+            ]
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn boxed() {
+        extern crate alloc;
+
+        use alloc::boxed::Box;
+
+        use crate::hal::dynamic::BoxedChip8;
+
+        struct Log<'a>(&'a mut Vec<Vec<Region>>);
+
+        impl Present for Log<'_> {
+            type Error = crate::hal::Error;
+
+            fn present(&mut self, _: &FrameBuffer, regions: &[Region]) -> Result<(), Self::Error> {
+                self.0.push(regions.to_vec());
+                Ok(())
+            }
+        }
+
+        let mut log = Vec::new();
+        let mut chip: BoxedChip8 = BoxedChip8::boxed(
+            Box::new(Video::new(Log(&mut log))),
+            Box::new(HeldKeys::default()),
+            Box::new(NullBuzzer::default()),
+            Box::new(XorShift::default()),
+            Box::new(NoDelay),
+        );
+        chip.state_mut()
+            .ram
+            .load(
+                0x200,
+                &crate::chip8_asm! {
+                    high;
+                    ldi 0x300;
+                    drw 0, 0, 0;
+                    drw 0, 0, 0;
+                },
+            )
+            .unwrap();
+        chip.state_mut().ram.load(0x300, &[0xFFu8; 32]).unwrap();
+        chip.init().unwrap();
+
+        // The VM draws at 128x64 through the boxed screen.
+        for _ in 0..3 {
+            chip.step().unwrap();
+        }
+        chip.present().unwrap();
+        assert_eq!(chip.state().frame.resolution(), Resolution::Extended);
+        assert!(chip.state().frame.pixel(15, 15));
+
+        chip.step().unwrap();
+        chip.present().unwrap();
+        assert_eq!(chip.state().reg.get(0xF).unwrap(), 1);
+        assert!(!chip.state().frame.pixel(15, 15));
+
+        let _ = chip.free();
+        let sprite = Region {
+            x: 0,
+            y: 0,
+            width: 16,
+            height: 16,
+        };
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0][0].width, 128);
+        assert_eq!(log[1], [sprite]);
+    }
+
+    #[test]
+    fn batched() {
+        let mut frames = Vec::new();
+        let mut chip =
+            machine(|frame: &FrameBuffer, _: &[Region]| frames.push(frame.rows()[0] >> 64));
+
+        // Changes between frames are not presented if they cancel out.
+        for _ in 0..3 {
+            chip.step().unwrap();
+        }
+        chip.present().unwrap();
+        chip.step().unwrap();
+        chip.present().unwrap();
+        chip.step().unwrap();
+        chip.present().unwrap();
+        chip.present().unwrap();
+
+        chip.refresh().unwrap();
+        assert_eq!(chip.run_at(600, 0), Err(Error::FrameRate(0)));
+        assert_eq!(chip.run_at(60, 120), Err(Error::FrameRate(120)));
+
+        // The program runs into empty memory within a frame, and leaves the
+        // screen as it found it.
+        chip.init().unwrap();
+        assert!(matches!(chip.run_at(600, 60), Err(Error::Instruction(_))));
+
+        // Presenting after every instruction shows each sprite.
+        chip.init().unwrap();
+        assert!(matches!(chip.run_at(600, 600), Err(Error::Instruction(_))));
+
+        let _ = chip.free();
+        assert_eq!(frames, [0xC0 << 56, 0, 0, 0xC0 << 56, 0, 0xC0 << 56, 0]);
+    }
+}
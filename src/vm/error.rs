@@ -1,23 +1,107 @@
-use crate::{hal, vm::mem};
-pub type Result<T = ()> = core::result::Result<T, Error>;
+use core::fmt;
 
+use crate::hal::{self, Buzzer, Delay, Fault, KeyState, Rng, Screen};
+use crate::vm::mem;
+
+pub type Result<T = (), P = hal::Error> = core::result::Result<T, Error<P>>;
+
+/// Errors returned by the VM.
+///
+/// `P` describes a failed peripheral. [`Chip8`](super::Chip8) returns a
+/// [`ChipError`], where it is a [`Fault`] holding the driver's own error,
+/// and [`classify`](Error::classify) reduces that to the [`hal::Error`] kind.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Error {
-    Peripheral(hal::Error),
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Error<P = hal::Error> {
+    Peripheral(P),
     Memory(mem::Error),
     NotAligned(u16),
     Instruction(u16),
     ClockSpeed(u32),
+    FrameRate(u32),
 }
 
+/// The [`Fault`] raised by a machine built from these peripherals.
+pub type PeripheralFault<S, K, B, R, D> = Fault<
+    <S as Screen>::Error,
+    <K as KeyState>::Error,
+    <B as Buzzer>::Error,
+    <R as Rng>::Error,
+    <D as Delay>::Error,
+>;
+
+/// Error returned by a [`Chip8`](super::Chip8) built from these peripherals.
+pub type ChipError<S, K, B, R, D> = Error<PeripheralFault<S, K, B, R, D>>;
+
 impl From<hal::Error> for Error {
     fn from(err: hal::Error) -> Self {
         Error::Peripheral(err)
     }
 }
 
-impl From<mem::Error> for Error {
+impl<P> From<mem::Error> for Error<P> {
     fn from(err: mem::Error) -> Self {
         Error::Memory(err)
     }
 }
+
+impl<S, K, B, R, D> From<Error<Fault<S, K, B, R, D>>> for Error {
+    fn from(err: Error<Fault<S, K, B, R, D>>) -> Self {
+        err.classify()
+    }
+}
+
+impl<S, K, B, R, D> Error<Fault<S, K, B, R, D>> {
+    /// Drop the driver's error, keeping which peripheral failed.
+    pub fn classify(self) -> Error {
+        self.map_peripheral(|fault| fault.kind())
+    }
+}
+
+impl<P> Error<P> {
+    pub fn map_peripheral<T, F: FnOnce(P) -> T>(self, f: F) -> Error<T> {
+        match self {
+            Error::Peripheral(err) => Error::Peripheral(f(err)),
+            Error::Memory(err) => Error::Memory(err),
+            Error::NotAligned(pc) => Error::NotAligned(pc),
+            Error::Instruction(opcode) => Error::Instruction(opcode),
+            Error::ClockSpeed(hz) => Error::ClockSpeed(hz),
+            Error::FrameRate(fps) => Error::FrameRate(fps),
+        }
+    }
+}
+
+impl<P: fmt::Display> Error<P> {
+    /// The lower level error this one wraps, available without `std`.
+    pub fn cause(&self) -> Option<&dyn fmt::Display> {
+        match self {
+            Error::Peripheral(err) => Some(err),
+            Error::Memory(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl<P> fmt::Display for Error<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Error::Peripheral(_) => f.write_str("peripheral failed"),
+            Error::Memory(_) => f.write_str("memory error"),
+            Error::NotAligned(pc) => write!(f, "instruction at 0x{pc:03X} is not aligned"),
+            Error::Instruction(opcode) => write!(f, "unknown instruction {opcode:04X}"),
+            Error::ClockSpeed(hz) => write!(f, "unsupported clock speed {hz} Hz"),
+            Error::FrameRate(fps) => write!(f, "unsupported frame rate {fps} Hz"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<P: std::error::Error + 'static> std::error::Error for Error<P> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Peripheral(err) => Some(err),
+            Error::Memory(err) => Some(err),
+            _ => None,
+        }
+    }
+}
@@ -1,8 +1,12 @@
 use super::{Ram, Registers, Stack};
+#[cfg(feature = "video")]
+use crate::hal::framebuffer::FrameBuffer;
+use crate::hal::{Keys, Resolution};
 
 pub type Result<T = ()> = core::result::Result<T, Error>;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Error {
     InvalidAddress { addr: u16 },
     InvalidSlice { addr: u16, len: u8 },
@@ -11,11 +15,37 @@ pub enum Error {
     NotWritable { addr: u16 },
     NotAligned { pc: u16 },
     LoadTooLong { addr: u16, len: usize },
-    StackOverflow { frame: u16 },
+    StackOverflow { frame: u16, frames: [u16; 16] },
     StackEmpty,
 }
 
-#[derive(Debug, Copy, Clone, Default)]
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            Error::InvalidAddress { addr } => write!(f, "invalid address 0x{addr:03X}"),
+            Error::InvalidSlice { addr, len } => {
+                write!(f, "invalid {len} byte slice at 0x{addr:03X}")
+            }
+            Error::InvalidSprite { sprite } => write!(f, "invalid sprite 0x{sprite:X}"),
+            Error::InvalidRegister { reg } => write!(f, "invalid register V{reg:X}"),
+            Error::NotWritable { addr } => write!(f, "address 0x{addr:03X} is not writable"),
+            Error::NotAligned { pc } => write!(f, "instruction at 0x{pc:03X} is not aligned"),
+            Error::LoadTooLong { addr, len } => {
+                write!(f, "{len} bytes do not fit at 0x{addr:03X}")
+            }
+            Error::StackOverflow { frame, .. } => {
+                write!(f, "stack overflow pushing 0x{frame:03X}")
+            }
+            Error::StackEmpty => f.write_str("return with an empty stack"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Mem {
     pub i: u16,
     // Counter
@@ -31,4 +61,103 @@ pub struct Mem {
     // Usually mem addr
     pub ram: Ram,
     // Stack
+    // Display geometry
+    pub resolution: Resolution,
+    // Keys down when the keypad was last sampled
+    pub keys: Keys,
+    // Key pressed while waiting in LD Vx, K, stored once it is released
+    pub key_down: Option<u8>,
+    // Display drawn by the VM, for screens which only present it
+    #[cfg(feature = "video")]
+    pub frame: FrameBuffer,
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::{Error, Mem};
+    use crate::vm::mem::Load;
+
+    fn state() -> Mem {
+        let mut mem = Mem {
+            i: 0x300,
+            pc: 0x204,
+            dt: 12,
+            st: 34,
+            ..Mem::default()
+        };
+
+        mem.reg.set(0xA, 0x55).unwrap();
+        mem.stack.push(0x202).unwrap();
+        mem.ram.load(0x200, &[0x22u8, 0x04, 0x00, 0xEE]).unwrap();
+        #[cfg(feature = "video")]
+        mem.frame.draw_sprite(60, 30, &[0xFF, 0x81]);
+        mem
+    }
+
+    #[test]
+    fn serde_json() {
+        let mem = state();
+        let json = serde_json::to_string(&mem).unwrap();
+        assert_eq!(serde_json::from_str::<Mem>(&json).unwrap(), mem);
+    }
+
+    #[test]
+    fn bincode() {
+        let mem = state();
+        let bin = bincode::serialize(&mem).unwrap();
+        assert_eq!(bincode::deserialize::<Mem>(&bin).unwrap(), mem);
+    }
+
+    #[test]
+    fn errors() {
+        let errors = [
+            crate::vm::Error::Memory(Error::StackOverflow {
+                frame: 0x200,
+                frames: [0x202; 16],
+            }),
+            crate::vm::Error::Peripheral(crate::hal::Error::Keypad),
+            crate::vm::Error::Instruction(0xFFFF),
+        ];
+
+        let json = serde_json::to_string(&errors).unwrap();
+        assert_eq!(
+            serde_json::from_str::<[crate::vm::Error; 3]>(&json).unwrap(),
+            errors
+        );
+
+        let bin = bincode::serialize(&errors).unwrap();
+        assert_eq!(
+            bincode::deserialize::<[crate::vm::Error; 3]>(&bin).unwrap(),
+            errors
+        );
+    }
+
+    #[test]
+    fn report() {
+        use crate::vm::observer::{Cpu, Exec, Observer};
+        use crate::vm::report::{History, Report};
+
+        let mem = state();
+        let mut history = History::new();
+        for pc in [0x200, 0x202, 0x204] {
+            history.before(&Exec {
+                pc,
+                opcode: 0,
+                inst: None,
+                before: Cpu::default(),
+            });
+        }
+
+        let report = Report::new(crate::vm::Error::Instruction(0xFFFF), &mem, &history);
+
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(serde_json::from_str::<Report>(&json).unwrap(), report);
+
+        let bin = bincode::serialize(&report).unwrap();
+        assert_eq!(bincode::deserialize::<Report>(&bin).unwrap(), report);
+
+        let json = serde_json::to_string(&history).unwrap();
+        let bad = json.replace(r#""len":3"#, r#""len":17"#);
+        assert!(serde_json::from_str::<History>(&bad).is_err());
+    }
 }
@@ -4,7 +4,7 @@ pub trait Load<T> {
     fn load(&mut self, addr: u16, words: &[T]) -> Result<usize>;
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Ram {
     mem: [u8; 4096],
 }
@@ -31,7 +31,7 @@ impl Load<u8> for Ram {
         }
 
         let target = &mut self.mem[index..index + bytes.len()];
-        target.copy_from_slice(&bytes);
+        target.copy_from_slice(bytes);
 
         Ok(bytes.len())
     }
@@ -107,6 +107,101 @@ impl Ram {
     }
 }
 
+/// Ram is encoded as a single hex string for human readable formats, and as
+/// a raw 4096 byte blob otherwise.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Ram {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        struct Hex<'a>(&'a [u8]);
+
+        impl core::fmt::Display for Hex<'_> {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+            }
+        }
+
+        if serializer.is_human_readable() {
+            serializer.collect_str(&Hex(&self.mem))
+        } else {
+            serializer.serialize_bytes(&self.mem)
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Ram {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> core::result::Result<Self, D::Error> {
+        use serde::de::{Error, SeqAccess, Visitor};
+
+        struct RamVisitor;
+
+        impl<'de> Visitor<'de> for RamVisitor {
+            type Value = Ram;
+
+            fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                f.write_str("4096 bytes of ram, raw or as a hex string")
+            }
+
+            fn visit_str<E: Error>(self, hex: &str) -> core::result::Result<Ram, E> {
+                let hex = hex.as_bytes();
+                if hex.len() != 8192 {
+                    return Err(E::invalid_length(hex.len() / 2, &self));
+                }
+
+                let nibble = |c: u8| match c {
+                    b'0'..=b'9' => Ok(c - b'0'),
+                    b'a'..=b'f' => Ok(c - b'a' + 10),
+                    b'A'..=b'F' => Ok(c - b'A' + 10),
+                    _ => Err(E::custom("invalid hex digit in ram")),
+                };
+
+                let mut ram = Ram { mem: [0; 4096] };
+                for (byte, pair) in ram.mem.iter_mut().zip(hex.chunks_exact(2)) {
+                    *byte = nibble(pair[0])? << 4 | nibble(pair[1])?;
+                }
+
+                Ok(ram)
+            }
+
+            fn visit_bytes<E: Error>(self, bytes: &[u8]) -> core::result::Result<Ram, E> {
+                let mem = bytes
+                    .try_into()
+                    .map_err(|_| E::invalid_length(bytes.len(), &self))?;
+
+                Ok(Ram { mem })
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> core::result::Result<Ram, A::Error> {
+                let mut ram = Ram { mem: [0; 4096] };
+                for (len, byte) in ram.mem.iter_mut().enumerate() {
+                    *byte = seq
+                        .next_element()?
+                        .ok_or_else(|| A::Error::invalid_length(len, &self))?;
+                }
+
+                match seq.next_element::<u8>()? {
+                    Some(_) => Err(A::Error::invalid_length(4097, &self)),
+                    None => Ok(ram),
+                }
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(RamVisitor)
+        } else {
+            deserializer.deserialize_bytes(RamVisitor)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Error;
@@ -147,4 +242,23 @@ mod tests {
         assert_eq!(&ram.mem[0x1B0..0x1B5], [0xF0, 0x90, 0x90, 0x90, 0xF0]);
         assert_eq!(&ram.mem[0x1FB..0x200], [0xF0, 0x80, 0xF0, 0x80, 0x80]);
     }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde() {
+        let mut ram = Ram::new();
+        ram.load(0x200, &[0x12u8, 0xAB, 0xFF]).unwrap();
+
+        let json = serde_json::to_string(&ram).unwrap();
+        assert_eq!(json.len(), 8192 + 2);
+        assert!(json.contains("12abff"));
+        assert_eq!(serde_json::from_str::<Ram>(&json).unwrap(), ram);
+
+        let bin = bincode::serialize(&ram).unwrap();
+        assert_eq!(bin.len(), 4096 + 8);
+        assert_eq!(bincode::deserialize::<Ram>(&bin).unwrap(), ram);
+
+        assert!(serde_json::from_str::<Ram>("\"00ff\"").is_err());
+        assert!(bincode::deserialize::<Ram>(&bincode::serialize(&[0u8; 16][..]).unwrap()).is_err());
+    }
 }
@@ -1,6 +1,7 @@
 use super::{Error, Result};
 
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Registers {
     reg: [u8; 16],
 }
@@ -20,9 +21,42 @@ impl Registers {
 
     pub fn set(&mut self, reg: u8, val: u8) -> Result {
         if reg < 16 {
-            Ok(self.reg[reg as usize] = val)
+            self.reg[reg as usize] = val;
+            Ok(())
         } else {
             Err(Error::InvalidRegister { reg })
         }
     }
+
+    /// All sixteen registers, `v0` through `vf`.
+    pub fn as_array(&self) -> &[u8; 16] {
+        &self.reg
+    }
+
+    pub fn as_array_mut(&mut self) -> &mut [u8; 16] {
+        &mut self.reg
+    }
+}
+
+impl From<[u8; 16]> for Registers {
+    fn from(reg: [u8; 16]) -> Self {
+        Self { reg }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, Registers};
+
+    #[test]
+    fn registers() {
+        let mut reg = Registers::from([0; 16]);
+
+        reg.set(3, 0x10).unwrap();
+        reg.as_array_mut()[0xF] = 1;
+
+        assert_eq!(reg.get(0xF).unwrap(), 1);
+        assert_eq!(reg.as_array()[3], 0x10);
+        assert_eq!(reg.get(16).unwrap_err(), Error::InvalidRegister { reg: 16 });
+    }
 }
@@ -1,11 +1,36 @@
 use super::{Error, Result};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "RawStack"))]
 pub struct Stack {
     sp: u8,
     frames: [u16; 16],
 }
 
+/// Unchecked form of [`Stack`], validated before it becomes one.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct RawStack {
+    sp: u8,
+    frames: [u16; 16],
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawStack> for Stack {
+    type Error = &'static str;
+
+    fn try_from(raw: RawStack) -> core::result::Result<Self, Self::Error> {
+        match raw.sp {
+            0..=15 | 0xFF => Ok(Self {
+                sp: raw.sp,
+                frames: raw.frames,
+            }),
+            _ => Err("stack pointer out of range"),
+        }
+    }
+}
+
 impl Default for Stack {
     fn default() -> Self {
         Self {
@@ -27,7 +52,10 @@ impl Stack {
                 self.frames[self.sp as usize] = frame;
                 Ok(())
             }
-            15 => Err(Error::StackOverflow { frame }),
+            15 => Err(Error::StackOverflow {
+                frame,
+                frames: self.frames,
+            }),
             _ => unreachable!(),
         }
     }
@@ -43,4 +71,124 @@ impl Stack {
             _ => unreachable!(),
         }
     }
+
+    /// Number of frames currently on the stack.
+    pub fn depth(&self) -> usize {
+        self.sp.wrapping_add(1) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.depth() == 0
+    }
+
+    /// The frames currently on the stack, oldest first.
+    pub fn frames(&self) -> &[u16] {
+        &self.frames[..self.depth()]
+    }
+
+    /// Mutable access to the frames currently on the stack, oldest first.
+    pub fn frames_mut(&mut self) -> &mut [u16] {
+        let depth = self.depth();
+        &mut self.frames[..depth]
+    }
+
+    /// The most recently pushed frame, without popping it.
+    pub fn peek(&self) -> Option<u16> {
+        self.frames().last().copied()
+    }
+
+    /// Iterate over the frames currently on the stack, oldest first.
+    pub fn iter(&self) -> core::slice::Iter<'_, u16> {
+        self.frames().iter()
+    }
+
+    /// Discard frames until the stack is at most `depth` frames deep.
+    pub fn truncate(&mut self, depth: usize) {
+        if depth < self.depth() {
+            self.sp = (depth as u8).wrapping_sub(1);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+}
+
+impl<'a> IntoIterator for &'a Stack {
+    type Item = &'a u16;
+    type IntoIter = core::slice::Iter<'a, u16>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, Stack};
+
+    #[test]
+    fn introspection() {
+        let mut stack = Stack::new();
+
+        assert!(stack.is_empty());
+        assert!(stack.frames().is_empty());
+        assert_eq!(stack.peek(), None);
+
+        stack.push(0x200).unwrap();
+        stack.push(0x300).unwrap();
+
+        assert_eq!(stack.depth(), 2);
+        assert_eq!(stack.frames(), &[0x200, 0x300]);
+        assert_eq!(stack.peek(), Some(0x300));
+        assert!(stack.iter().eq(&[0x200, 0x300]));
+
+        stack.frames_mut()[0] = 0x400;
+        stack.truncate(1);
+        assert_eq!(stack.pop().unwrap(), 0x400);
+
+        stack.push(0x200).unwrap();
+        stack.clear();
+        assert_eq!(stack.pop().unwrap_err(), Error::StackEmpty);
+    }
+
+    #[test]
+    fn overflow() {
+        let mut stack = Stack::new();
+
+        for frame in 0..16 {
+            stack.push(frame * 2).unwrap();
+        }
+
+        assert_eq!(stack.depth(), 16);
+        assert_eq!(
+            stack.push(0x200).unwrap_err(),
+            Error::StackOverflow {
+                frame: 0x200,
+                frames: [0, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 22, 24, 26, 28, 30],
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_sp() {
+        macro_rules! json {
+            ($sp:literal) => {
+                concat!(
+                    r#"{"sp":"#,
+                    $sp,
+                    r#","frames":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0]}"#
+                )
+            };
+        }
+
+        assert!(serde_json::from_str::<Stack>(json!(0)).is_ok());
+        assert!(serde_json::from_str::<Stack>(json!(15)).is_ok());
+        assert!(serde_json::from_str::<Stack>(json!(255)).is_ok());
+
+        assert!(serde_json::from_str::<Stack>(json!(16)).is_err());
+        assert!(serde_json::from_str::<Stack>(json!(40)).is_err());
+        assert!(serde_json::from_str::<Stack>(json!(254)).is_err());
+    }
 }
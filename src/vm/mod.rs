@@ -1,6 +1,14 @@
 mod chip8;
-
 mod error;
 
+pub mod coverage;
+#[cfg(feature = "video")]
+pub mod display;
 pub mod mem;
+pub mod observer;
+pub mod profile;
+pub mod report;
+pub mod trace;
+
 pub use self::chip8::Chip8;
+pub use self::error::{ChipError, Error, PeripheralFault, Result};
@@ -0,0 +1,120 @@
+use crate::instruction::Instruction;
+use crate::vm::mem::Mem;
+
+/// Register, I and timer values at a point in execution.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cpu {
+    pub pc: u16,
+    pub i: u16,
+    pub dt: u8,
+    pub st: u8,
+    pub sp: u8,
+    pub v: [u8; 16],
+}
+
+impl From<&Mem> for Cpu {
+    fn from(mem: &Mem) -> Self {
+        Self {
+            pc: mem.pc,
+            i: mem.i,
+            dt: mem.dt,
+            st: mem.st,
+            sp: mem.stack.depth() as u8,
+            v: *mem.reg.as_array(),
+        }
+    }
+}
+
+/// An instruction about to be, or which has just been, executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Exec {
+    pub pc: u16,
+    pub opcode: u16,
+    pub inst: Option<Instruction>,
+    pub before: Cpu,
+}
+
+/// Why the VM touched memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AccessKind {
+    /// Instruction fetch (read).
+    Fetch,
+    /// Sprite data read by `DRW`.
+    Sprite,
+    /// Digit written by `LD B, Vx`.
+    Bcd,
+    /// Register written by `LD [I], Vx`.
+    Store,
+    /// Register read by `LD Vx, [I]`.
+    Load,
+}
+
+impl AccessKind {
+    pub fn is_write(&self) -> bool {
+        matches!(self, AccessKind::Bcd | AccessKind::Store)
+    }
+
+    pub fn is_read(&self) -> bool {
+        !self.is_write()
+    }
+}
+
+/// A single byte of memory read or written by the instruction at `pc`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Access {
+    pub pc: u16,
+    pub addr: u16,
+    pub value: u8,
+    pub kind: AccessKind,
+}
+
+/// Hooks called by [`Chip8`](crate::vm::Chip8) around each instruction and
+/// on every memory access.
+///
+/// `()` is the default observer and compiles down to nothing.
+pub trait Observer {
+    /// When false, the VM skips building events entirely.
+    const ENABLED: bool = true;
+
+    /// Called once the instruction at `exec.pc` has been fetched.
+    fn before(&mut self, _exec: &Exec) {}
+
+    /// Called after the instruction executed successfully.
+    fn after(&mut self, _exec: &Exec, _after: &Cpu) {}
+
+    /// Called for every byte of ram the VM reads or writes.
+    fn access(&mut self, _access: &Access) {}
+
+    /// Called each time `LD Vx, K` at `pc` polls without a key being
+    /// released, which takes `us` microseconds. The instruction runs again
+    /// on the next step, and is only passed to `before` and `after` once it
+    /// completes.
+    fn key_wait(&mut self, _pc: u16, _us: u32) {}
+}
+
+impl Observer for () {
+    const ENABLED: bool = false;
+}
+
+impl<T: Observer> Observer for &mut T {
+    const ENABLED: bool = T::ENABLED;
+
+    fn before(&mut self, exec: &Exec) {
+        (**self).before(exec)
+    }
+
+    fn after(&mut self, exec: &Exec, after: &Cpu) {
+        (**self).after(exec, after)
+    }
+
+    fn access(&mut self, access: &Access) {
+        (**self).access(access)
+    }
+
+    fn key_wait(&mut self, pc: u16, us: u32) {
+        (**self).key_wait(pc, us)
+    }
+}
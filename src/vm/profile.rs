@@ -0,0 +1,552 @@
+use core::cmp::Reverse;
+use core::fmt::{self, Write};
+
+use super::observer::{Cpu, Exec, Observer};
+use crate::instruction::Instruction;
+
+const RAM_SIZE: usize = 0x1000;
+
+/// Distinct subroutines tracked, later ones are not profiled.
+pub const MAX_SUBROUTINES: usize = 64;
+
+/// Distinct call paths tracked for the folded stack output.
+pub const MAX_PATHS: usize = 256;
+
+/// Two reads of DT from the same address at most this many instructions
+/// apart, while DT is running, count as a busy-wait loop.
+pub const DT_LOOP_MAX: u64 = 8;
+
+/// Number of instruction classes, one for each [`Instruction`] variant. See
+/// [`Instruction::mask`].
+const CLASSES: usize = 36;
+
+/// Call and instruction counts for one subroutine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Subroutine {
+    pub addr: u16,
+    pub calls: u64,
+    /// Instructions executed by completed calls, including callees.
+    pub inclusive: u64,
+    /// Instructions executed by completed calls, excluding callees.
+    pub exclusive: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Path {
+    parent: u16,
+    addr: u16,
+    count: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Frame {
+    sub: Option<u16>,
+    entered: u64,
+    children: u64,
+}
+
+/// Observer which counts where a program spends its instructions.
+///
+/// It collects execution counts per address, a histogram per instruction
+/// class, call counts and inclusive/exclusive instruction counts per
+/// subroutine from `CALL`/`RET`, and how long the program waits on the
+/// keypad (`LD Vx, K`) or spins reading DT.
+///
+/// Call frames entered before profiling started are attributed to the
+/// outermost frame. Everything is kept in fixed size tables.
+#[derive(Clone)]
+pub struct Profiler {
+    total: u64,
+    counts: [u64; RAM_SIZE],
+    opcodes: [u16; RAM_SIZE],
+    classes: [(u16, u64); CLASSES],
+    unknown: u64,
+    subs: [Subroutine; MAX_SUBROUTINES],
+    nsubs: usize,
+    frames: [Frame; 17],
+    depth: usize,
+    paths: [Path; MAX_PATHS],
+    npaths: usize,
+    path: u16,
+    /// Calls made while the path table was full, which do not move `path`
+    lost: usize,
+    key_wait_us: u64,
+    key_polls: u64,
+    dt_wait: u64,
+    dt_poll: Option<(u16, u64)>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            total: 0,
+            counts: [0; RAM_SIZE],
+            opcodes: [0; RAM_SIZE],
+            classes: [(0, 0); CLASSES],
+            unknown: 0,
+            subs: [Subroutine::default(); MAX_SUBROUTINES],
+            nsubs: 0,
+            frames: [Frame::default(); 17],
+            depth: 0,
+            paths: [Path::default(); MAX_PATHS],
+            npaths: 1,
+            path: 0,
+            lost: 0,
+            key_wait_us: 0,
+            key_polls: 0,
+            dt_wait: 0,
+            dt_poll: None,
+        }
+    }
+
+    /// Instructions executed.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// How many times the instruction at `addr` was executed.
+    pub fn count(&self, addr: u16) -> u64 {
+        self.counts.get(addr as usize).copied().unwrap_or(0)
+    }
+
+    /// Execution counts for every address in ram.
+    pub fn counts(&self) -> &[u64; RAM_SIZE] {
+        &self.counts
+    }
+
+    /// Executed instructions per class, e.g. every `DRW`. Each class is
+    /// represented by an instruction with zeroed operands, use
+    /// [`Instruction::syntax`] to name it.
+    pub fn classes(&self) -> impl Iterator<Item = (Instruction, u64)> + '_ {
+        self.classes
+            .iter()
+            .filter(|(_, count)| *count > 0)
+            .filter_map(|&(class, count)| Some((Instruction::decode(class)?, count)))
+    }
+
+    /// Executed opcodes which do not decode to an instruction.
+    pub fn unknown(&self) -> u64 {
+        self.unknown
+    }
+
+    pub fn subroutines(&self) -> &[Subroutine] {
+        &self.subs[..self.nsubs]
+    }
+
+    /// Time spent polling the keypad in `LD Vx, K`, in microseconds.
+    pub fn key_wait_us(&self) -> u64 {
+        self.key_wait_us
+    }
+
+    /// Instructions executed in loops waiting for DT to reach zero.
+    pub fn dt_wait(&self) -> u64 {
+        self.dt_wait
+    }
+
+    /// Write the hottest `top` addresses, the class histogram, subroutines
+    /// and wait times as plain text tables, most expensive first.
+    pub fn write_report<W: Write>(&self, out: &mut W, top: usize) -> fmt::Result {
+        let percent = |count: u64| count as f64 * 100.0 / self.total.max(1) as f64;
+
+        writeln!(out, "instructions: {}", self.total)?;
+        writeln!(
+            out,
+            "key wait: {} us in {} polls",
+            self.key_wait_us, self.key_polls
+        )?;
+        writeln!(
+            out,
+            "dt wait: {} instructions ({:.1}%)",
+            self.dt_wait,
+            percent(self.dt_wait)
+        )?;
+
+        let mut addrs = [0u16; RAM_SIZE];
+        for (idx, addr) in addrs.iter_mut().enumerate() {
+            *addr = idx as u16;
+        }
+        addrs.sort_unstable_by_key(|&addr| (Reverse(self.counts[addr as usize]), addr));
+
+        writeln!(out, "\n addr      count      %  instruction")?;
+        for &addr in addrs.iter().take(top) {
+            let count = self.counts[addr as usize];
+            if count == 0 {
+                break;
+            }
+
+            write!(out, "{addr:>5X} {count:>10} {:>6.2}  ", percent(count))?;
+            match Instruction::decode(self.opcodes[addr as usize]) {
+                Some(inst) => writeln!(out, "{inst}")?,
+                None => writeln!(out, "{:04X}", self.opcodes[addr as usize])?,
+            }
+        }
+
+        let mut classes = self.classes;
+        classes.sort_unstable_by_key(|&(class, count)| (Reverse(count), class));
+
+        writeln!(out, "\n     count      %  class")?;
+        for (class, count) in classes.iter().filter(|(_, count)| *count > 0) {
+            let name = Instruction::decode(*class).map_or("???", |inst| inst.syntax());
+            writeln!(out, "{count:>10} {:>6.2}  {name}", percent(*count))?;
+        }
+        if self.unknown > 0 {
+            writeln!(
+                out,
+                "{:>10} {:>6.2}  ???",
+                self.unknown,
+                percent(self.unknown)
+            )?;
+        }
+
+        let mut subs = self.subs;
+        let subs = &mut subs[..self.nsubs];
+        subs.sort_unstable_by_key(|sub| (Reverse(sub.inclusive), sub.addr));
+
+        writeln!(out, "\n addr      calls  inclusive  exclusive")?;
+        for sub in subs.iter() {
+            writeln!(
+                out,
+                "{:>5X} {:>10} {:>10} {:>10}",
+                sub.addr, sub.calls, sub.inclusive, sub.exclusive
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Write instruction counts per call path in the folded stack format
+    /// read by flamegraph tools, e.g. `main;0x20A;0x30C 1234`.
+    pub fn write_folded<W: Write>(&self, out: &mut W) -> fmt::Result {
+        for (idx, path) in self.paths[..self.npaths].iter().enumerate() {
+            if path.count == 0 {
+                continue;
+            }
+
+            let mut chain = [0u16; 17];
+            let mut len = 0;
+            let mut at = idx;
+            while at != 0 && len < chain.len() {
+                chain[len] = self.paths[at].addr;
+                len += 1;
+                at = self.paths[at].parent as usize;
+            }
+
+            out.write_str("main")?;
+            for addr in chain[..len].iter().rev() {
+                write!(out, ";0x{addr:03X}")?;
+            }
+            writeln!(out, " {}", path.count)?;
+        }
+
+        Ok(())
+    }
+
+    fn subroutine(&mut self, addr: u16) -> Option<u16> {
+        if let Some(idx) = self.subroutines().iter().position(|sub| sub.addr == addr) {
+            return Some(idx as u16);
+        }
+
+        let sub = self.subs.get_mut(self.nsubs)?;
+        sub.addr = addr;
+        self.nsubs += 1;
+        Some(self.nsubs as u16 - 1)
+    }
+
+    fn enter(&mut self, addr: u16) {
+        let sub = self.subroutine(addr);
+        if let Some(sub) = sub {
+            self.subs[sub as usize].calls += 1;
+        }
+
+        if let Some(frame) = self.frames.get_mut(self.depth + 1) {
+            *frame = Frame {
+                sub,
+                entered: self.total,
+                children: 0,
+            };
+            self.depth += 1;
+        }
+
+        let parent = self.path;
+        let child = self.paths[1..self.npaths]
+            .iter()
+            .position(|path| path.parent == parent && path.addr == addr)
+            .map(|idx| idx + 1);
+
+        match child {
+            Some(child) => self.path = child as u16,
+            None if self.npaths < MAX_PATHS => {
+                self.paths[self.npaths] = Path {
+                    parent,
+                    addr,
+                    count: 0,
+                };
+                self.path = self.npaths as u16;
+                self.npaths += 1;
+            }
+            None => self.lost += 1,
+        }
+    }
+
+    fn leave(&mut self) {
+        if self.depth > 0 {
+            let frame = self.frames[self.depth];
+            let inclusive = self.total - frame.entered;
+
+            self.depth -= 1;
+            self.frames[self.depth].children += inclusive;
+
+            if let Some(sub) = frame.sub {
+                let sub = &mut self.subs[sub as usize];
+                sub.inclusive += inclusive;
+                sub.exclusive += inclusive - frame.children;
+            }
+        }
+
+        if self.lost > 0 {
+            self.lost -= 1;
+        } else {
+            self.path = self.paths[self.path as usize].parent;
+        }
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Profiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Profiler")
+            .field("total", &self.total)
+            .field("subroutines", &self.subroutines())
+            .field("key_wait_us", &self.key_wait_us)
+            .field("dt_wait", &self.dt_wait)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Observer for Profiler {
+    fn before(&mut self, exec: &Exec) {
+        self.total += 1;
+        self.paths[self.path as usize].count += 1;
+
+        if let Some(count) = self.counts.get_mut(exec.pc as usize) {
+            *count += 1;
+            self.opcodes[exec.pc as usize] = exec.opcode;
+        }
+
+        match exec.inst {
+            Some(inst) => {
+                let class = inst.encode() & inst.mask();
+                let slot = self
+                    .classes
+                    .iter()
+                    .position(|&(at, count)| at == class || count == 0);
+                if let Some(slot) = slot {
+                    self.classes[slot].0 = class;
+                    self.classes[slot].1 += 1;
+                }
+            }
+            None => self.unknown += 1,
+        }
+
+        if let Some(Instruction::Lddtv(_)) = exec.inst {
+            if exec.before.dt == 0 {
+                self.dt_poll = None;
+            } else {
+                if let Some((pc, at)) = self.dt_poll {
+                    if pc == exec.pc && self.total - at <= DT_LOOP_MAX {
+                        self.dt_wait += self.total - at;
+                    }
+                }
+                self.dt_poll = Some((exec.pc, self.total));
+            }
+        }
+    }
+
+    fn after(&mut self, exec: &Exec, after: &Cpu) {
+        match exec.inst {
+            Some(Instruction::Call(addr)) if after.sp > exec.before.sp => self.enter(addr),
+            Some(Instruction::Ret) => self.leave(),
+            _ => (),
+        }
+    }
+
+    fn key_wait(&mut self, _pc: u16, us: u32) {
+        self.key_polls += 1;
+        self.key_wait_us += us as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+
+    use super::*;
+    use crate::hal::chip;
+    use crate::vm::mem::Load;
+
+    #[test]
+    fn subroutines() {
+        let mut chip = chip!().with_observer(Profiler::new());
+        chip.state_mut()
+            .ram
+            .load(
+                0x200,
+                &crate::chip8_asm! {
+                    call 0x206;   // 0x200
+                    call 0x20C;   // 0x202
+                    jp 0x204;     // 0x204
+                    call 0x20C;   // 0x206
+                    add 0, 1;     // 0x208
+                    ret;          // 0x20A
+                    add 1, 1;     // 0x20C
+                    ret;          // 0x20E
+                },
+            )
+            .unwrap();
+        chip.init().unwrap();
+
+        for _ in 0..12 {
+            chip.step().unwrap();
+        }
+
+        let prof = chip.observer();
+        assert_eq!(prof.total(), 12);
+        assert_eq!(prof.count(0x204), 3);
+        assert_eq!(prof.count(0x20C), 2);
+        assert_eq!(
+            prof.subroutines(),
+            &[
+                Subroutine {
+                    addr: 0x206,
+                    calls: 1,
+                    inclusive: 5,
+                    exclusive: 3
+                },
+                Subroutine {
+                    addr: 0x20C,
+                    calls: 2,
+                    inclusive: 4,
+                    exclusive: 4
+                },
+            ]
+        );
+
+        let classes: std::vec::Vec<_> = prof
+            .classes()
+            .map(|(inst, count)| (inst.syntax(), count))
+            .collect();
+        assert_eq!(
+            classes,
+            [
+                ("CALL addr", 3),
+                ("ADD Vx, byte", 3),
+                ("RET", 3),
+                ("JP addr", 3)
+            ]
+        );
+
+        let mut folded = String::new();
+        prof.write_folded(&mut folded).unwrap();
+        assert_eq!(
+            folded,
+            "main 5\nmain;0x206 3\nmain;0x206;0x20C 2\nmain;0x20C 2\n"
+        );
+
+        let mut report = String::new();
+        prof.write_report(&mut report, 2).unwrap();
+        assert_eq!(
+            report,
+            "instructions: 12\n\
+             key wait: 0 us in 0 polls\n\
+             dt wait: 0 instructions (0.0%)\n\
+             \n addr      count      %  instruction\n  \
+             204          3  25.00  JP 0x204\n  \
+             20C          2  16.67  ADD V1, 0x01\n\
+             \n     count      %  class\n         \
+             3  25.00  RET\n         \
+             3  25.00  JP addr\n         \
+             3  25.00  CALL addr\n         \
+             3  25.00  ADD Vx, byte\n\
+             \n addr      calls  inclusive  exclusive\n  \
+             206          1          5          3\n  \
+             20C          2          4          4\n"
+        );
+    }
+
+    #[test]
+    fn waits() {
+        let mut chip = chip!(keys = [None, Some(5), None]).with_observer(Profiler::new());
+        chip.state_mut()
+            .ram
+            .load(
+                0x200,
+                &crate::chip8_asm! {
+                    lddtv 0;      // 0x200
+                    se 0, 0;      // 0x202
+                    jp 0x200;     // 0x204
+                    ldkey 1;      // 0x206
+                },
+            )
+            .unwrap();
+        chip.init().unwrap();
+        chip.state_mut().dt = 3;
+
+        for _ in 0..9 {
+            chip.step().unwrap();
+        }
+        chip.state_mut().dt = 0;
+        // LD V1, K polls three times, until key 5 is pressed and released.
+        for _ in 0..5 {
+            chip.step().unwrap();
+        }
+
+        let prof = chip.observer();
+        // The polls are not instructions.
+        assert_eq!(prof.total(), 12);
+        assert_eq!(prof.dt_wait(), 6);
+        assert_eq!(prof.key_wait_us(), 2000);
+        assert_eq!(chip.state().reg.get(1).unwrap(), 5);
+    }
+
+    #[test]
+    fn every_class() {
+        let mut prof = Profiler::new();
+        let mut known = 0;
+
+        for opcode in 0..=u16::MAX {
+            let inst = Instruction::decode(opcode);
+            known += inst.is_some() as u64;
+            prof.before(&Exec {
+                pc: 0x200,
+                opcode,
+                inst,
+                before: Cpu::default(),
+            });
+        }
+
+        assert_eq!(prof.classes().count(), CLASSES);
+        assert_eq!(prof.classes().map(|(_, count)| count).sum::<u64>(), known);
+        assert_eq!(prof.unknown(), 0x10000 - known);
+    }
+
+    #[test]
+    fn count_past_u32() {
+        let mut prof = Profiler::new();
+        prof.counts[0x200] = u32::MAX.into();
+
+        prof.before(&Exec {
+            pc: 0x200,
+            opcode: 0x00E0,
+            inst: Instruction::decode(0x00E0),
+            before: Cpu::default(),
+        });
+        assert_eq!(prof.count(0x200), 1 << 32);
+    }
+}
@@ -0,0 +1,307 @@
+use core::fmt;
+
+use super::mem::{Mem, Stack};
+use super::observer::{Cpu, Exec, Observer};
+use super::{Chip8, ChipError, Error, PeripheralFault};
+#[cfg(feature = "video")]
+use crate::hal::framebuffer::FrameBuffer;
+use crate::hal::{self, Buzzer, Delay, KeyState, Rng, Screen};
+use crate::instruction::Instruction;
+
+/// Number of executed addresses kept by [`History`].
+pub const HISTORY: usize = 16;
+
+/// Observer which remembers the addresses of the most recently executed
+/// instructions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "RawHistory"))]
+pub struct History {
+    addrs: [u16; HISTORY],
+    len: usize,
+    next: usize,
+}
+
+/// Unchecked form of [`History`], validated before it becomes one.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct RawHistory {
+    addrs: [u16; HISTORY],
+    len: usize,
+    next: usize,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawHistory> for History {
+    type Error = &'static str;
+
+    fn try_from(raw: RawHistory) -> Result<Self, Self::Error> {
+        if raw.len > HISTORY || raw.next >= HISTORY {
+            return Err("history position out of range");
+        }
+
+        Ok(Self {
+            addrs: raw.addrs,
+            len: raw.len,
+            next: raw.next,
+        })
+    }
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Executed addresses, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        let start = (self.next + HISTORY - self.len) % HISTORY;
+        (0..self.len).map(move |n| self.addrs[(start + n) % HISTORY])
+    }
+
+    pub fn last(&self) -> Option<u16> {
+        (self.len > 0).then(|| self.addrs[(self.next + HISTORY - 1) % HISTORY])
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Observer for History {
+    fn before(&mut self, exec: &Exec) {
+        self.addrs[self.next] = exec.pc;
+        self.next = (self.next + 1) % HISTORY;
+        self.len = (self.len + 1).min(HISTORY);
+    }
+}
+
+/// The machine state at the point an instruction failed.
+///
+/// ```text
+/// error: memory error: stack overflow pushing 0x202
+///   at 0x200: 2200  CALL 0x200
+/// registers:
+///   V0=00 V1=00 V2=00 V3=00 V4=00 V5=00 V6=00 V7=00
+///   V8=00 V9=00 VA=00 VB=00 VC=00 VD=00 VE=00 VF=00
+///   I=0000 DT=00 ST=00 SP=10
+/// stack:
+///   #15 0x202
+///   ...
+/// history:
+///   0x200 0x200 0x200
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Report<P = hal::Error> {
+    pub error: Error<P>,
+    /// Registers when the instruction failed. `pc` is that of the failed
+    /// instruction.
+    pub cpu: Cpu,
+    /// `None` if the instruction could not be fetched.
+    pub opcode: Option<u16>,
+    pub stack: Stack,
+    pub history: History,
+    /// The VM's frame, blank unless the screen only presents it.
+    #[cfg(feature = "video")]
+    pub frame: FrameBuffer,
+}
+
+impl<P> Report<P> {
+    /// Describe `error`, returned by the last step of a machine now in
+    /// state `mem`.
+    pub fn new(error: Error<P>, mem: &Mem, history: &History) -> Self {
+        let opcode = match mem.ram.read_bytes(mem.pc, 2) {
+            Ok(&[msb, lsb]) => Some(u16::from_be_bytes([msb, lsb])),
+            _ => None,
+        };
+
+        Self {
+            error,
+            cpu: Cpu::from(mem),
+            opcode,
+            stack: mem.stack,
+            history: *history,
+            #[cfg(feature = "video")]
+            frame: mem.frame,
+        }
+    }
+
+    pub fn instruction(&self) -> Option<Instruction> {
+        self.opcode.and_then(Instruction::decode)
+    }
+}
+
+impl<P: fmt::Display> fmt::Display for Report<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cpu = &self.cpu;
+
+        write!(f, "error: {}", self.error)?;
+        if let Some(cause) = self.error.cause() {
+            write!(f, ": {cause}")?;
+        }
+
+        write!(f, "\n  at 0x{:03X}: ", cpu.pc)?;
+        match (self.opcode, self.instruction()) {
+            (Some(opcode), Some(inst)) => writeln!(f, "{opcode:04X}  {inst}")?,
+            (Some(opcode), None) => writeln!(f, "{opcode:04X}  ???")?,
+            (None, _) => writeln!(f, "????")?,
+        }
+
+        writeln!(f, "registers:")?;
+        for (row, regs) in cpu.v.chunks(8).enumerate() {
+            f.write_str(" ")?;
+            for (col, v) in regs.iter().enumerate() {
+                write!(f, " V{:X}={v:02X}", row * 8 + col)?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(
+            f,
+            "  I={:04X} DT={:02X} ST={:02X} SP={:02X}",
+            cpu.i, cpu.dt, cpu.st, cpu.sp
+        )?;
+
+        writeln!(f, "stack:")?;
+        for (n, frame) in self.stack.iter().enumerate().rev() {
+            writeln!(f, "  #{n} 0x{frame:03X}")?;
+        }
+
+        write!(f, "history:\n ")?;
+        for addr in self.history.iter() {
+            write!(f, " 0x{addr:03X}")?;
+        }
+        writeln!(f)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<P: std::error::Error + 'static> std::error::Error for Report<P> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl<S, K, B, R, D> Chip8<S, K, B, R, D, History>
+where
+    S: Screen,
+    K: KeyState,
+    B: Buzzer,
+    R: Rng,
+    D: Delay,
+{
+    /// Describe `error`, which must have just been returned by
+    /// [`step`](Self::step).
+    pub fn report(
+        &self,
+        error: ChipError<S, K, B, R, D>,
+    ) -> Report<PeripheralFault<S, K, B, R, D>> {
+        Report::new(error, self.state(), self.observer())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::ToString;
+
+    use super::*;
+    use crate::hal::chip;
+    use crate::vm::mem::Load;
+
+    #[test]
+    fn report() {
+        let mut chip = chip!().with_observer(History::new());
+        chip.state_mut()
+            .ram
+            .load(
+                0x200,
+                &crate::chip8_asm! {
+                    ld 0xA, 0x55;  // 0x200
+                    ldi 0x300;     // 0x202
+                    call 0x208;    // 0x204
+                    jp 0x204;      // 0x206
+                    add 1, 1;      // 0x208
+                },
+            )
+            .unwrap();
+        chip.state_mut().ram.load(0x20A, &[0xFFu8, 0xFF]).unwrap();
+        chip.init().unwrap();
+
+        let error = loop {
+            if let Err(error) = chip.step() {
+                break error;
+            }
+        };
+        let report = chip.report(error);
+
+        assert_eq!(report.cpu.pc, 0x20A);
+        assert_eq!(report.instruction(), None);
+        assert_eq!(
+            report.to_string(),
+            "error: unknown instruction FFFF\n  \
+             at 0x20A: FFFF  ???\n\
+             registers:\n  \
+             V0=00 V1=01 V2=00 V3=00 V4=00 V5=00 V6=00 V7=00\n  \
+             V8=00 V9=00 VA=55 VB=00 VC=00 VD=00 VE=00 VF=00\n  \
+             I=0300 DT=00 ST=00 SP=01\n\
+             stack:\n  \
+             #0 0x206\n\
+             history:\n  \
+             0x200 0x202 0x204 0x208 0x20A\n"
+        );
+    }
+
+    #[test]
+    fn history() {
+        let mut history = History::new();
+        for pc in 0..20 {
+            history.before(&Exec {
+                pc,
+                opcode: 0,
+                inst: None,
+                before: Cpu::default(),
+            });
+        }
+
+        assert_eq!(history.len(), HISTORY);
+        assert_eq!(history.iter().next(), Some(4));
+        assert_eq!(history.last(), Some(19));
+    }
+
+    #[test]
+    fn causes() {
+        let mut chip = chip!().with_observer(History::new());
+        chip.state_mut()
+            .ram
+            .load(0x200, &crate::chip8_asm! { ret; })
+            .unwrap();
+        chip.init().unwrap();
+
+        let error = chip.step().unwrap_err();
+        let report = chip.report(error);
+        assert!(report.to_string().starts_with(
+            "error: memory error: return with an empty stack\n  at 0x200: 00EE  RET\n"
+        ));
+
+        #[cfg(feature = "std")]
+        {
+            use std::error::Error as _;
+
+            let source = report.source().unwrap().source().unwrap();
+            assert_eq!(
+                source.downcast_ref::<crate::vm::mem::Error>(),
+                Some(&crate::vm::mem::Error::StackEmpty)
+            );
+            assert!(Error::Peripheral(crate::hal::Error::Screen)
+                .source()
+                .unwrap()
+                .is::<crate::hal::Error>());
+        }
+    }
+}
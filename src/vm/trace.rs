@@ -0,0 +1,62 @@
+use core::fmt::{self, Write};
+
+use super::observer::{Exec, Observer};
+
+/// Writes one line per instruction, with the machine state before it runs:
+///
+/// ```text
+/// PC=0200 OP=6A02 I=0000 DT=00 ST=00 SP=00 V=00000000000000000000000000000000 ; LD VA, 0x02
+/// ```
+///
+/// Everything before the `;` is fixed width so traces can be diffed directly.
+#[derive(Debug, Clone, Default)]
+pub struct TraceWriter<W: Write> {
+    out: W,
+    error: Option<fmt::Error>,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out, error: None }
+    }
+
+    /// The first write error encountered, if any. Tracing stops after an error.
+    pub fn error(&self) -> Option<fmt::Error> {
+        self.error
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn line(&mut self, exec: &Exec) -> fmt::Result {
+        let cpu = &exec.before;
+
+        write!(
+            self.out,
+            "PC={:04X} OP={:04X} I={:04X} DT={:02X} ST={:02X} SP={:02X} V=",
+            exec.pc, exec.opcode, cpu.i, cpu.dt, cpu.st, cpu.sp
+        )?;
+
+        for v in cpu.v {
+            write!(self.out, "{v:02X}")?;
+        }
+
+        match exec.inst {
+            Some(inst) => writeln!(self.out, " ; {inst}"),
+            None => writeln!(self.out, " ; ???"),
+        }
+    }
+}
+
+impl<W: Write> Observer for TraceWriter<W> {
+    fn before(&mut self, exec: &Exec) {
+        if self.error.is_none() {
+            self.error = self.line(exec).err();
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::fs;
+
+use chip8::debug::dap::Session;
+use serde_json::Value;
+
+/// Frame each line of a request transcript as it would arrive over stdio.
+fn frame(requests: &str) -> Vec<u8> {
+    let mut input = Vec::new();
+    for line in requests.lines().filter(|line| !line.is_empty()) {
+        input.extend(format!("Content-Length: {}\r\n\r\n{line}", line.len()).bytes());
+    }
+
+    input
+}
+
+/// Split the adapter output back into messages, checking the framing.
+fn unframe(mut output: &str) -> Vec<Value> {
+    let mut messages = Vec::new();
+    while !output.is_empty() {
+        let (header, rest) = output.split_once("\r\n\r\n").unwrap();
+        let len: usize = header
+            .strip_prefix("Content-Length: ")
+            .unwrap()
+            .parse()
+            .unwrap();
+        messages.push(serde_json::from_str(&rest[..len]).unwrap());
+        output = &rest[len..];
+    }
+
+    messages
+}
+
+fn replay(name: &str) {
+    let requests = fs::read_to_string(format!("tests/data/{name}.in.jsonl")).unwrap();
+    let expected = fs::read_to_string(format!("tests/data/{name}.out.jsonl")).unwrap();
+
+    let input = frame(&requests);
+    let mut session = Session::new(&input[..], Vec::new());
+    session.run().unwrap();
+
+    let output = String::from_utf8(session.into_output()).unwrap();
+    let messages = unframe(&output);
+    let expected: Vec<Value> = expected
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    for (idx, (got, want)) in messages.iter().zip(&expected).enumerate() {
+        assert_eq!(got, want, "message {idx}");
+    }
+    assert_eq!(messages.len(), expected.len());
+}
+
+#[test]
+fn count() {
+    replay("count");
+}
+
+#[test]
+fn data() {
+    replay("data");
+}
@@ -0,0 +1,13 @@
+; Count V0 up through a subroutine, storing it at 0x300
+start:
+    LD V0, 0
+
+loop:
+    CALL incr
+    LD I, 0x300
+    LD [I], V0
+    JP loop
+
+incr:
+    ADD V0, 1
+    RET
@@ -0,0 +1,7 @@
+; A breakpoint on data moves to the next instruction
+    JP start
+    DB 1, 2, 3
+
+start:
+    CLS
+    JP start
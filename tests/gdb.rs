@@ -0,0 +1,247 @@
+use std::io::{self, Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use chip8::debug::{gdb::Server, Debugger};
+use chip8::hal::{self, Buzzer, Delay, Keypad, Rng, Screen};
+use chip8::vm::{mem::Load, Chip8};
+
+struct Null;
+
+impl Screen for Null {
+    type Error = hal::Error;
+
+    fn draw(&mut self, _x: u8, _y: u8, _data: &[u8]) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl Keypad for Null {
+    type Error = hal::Error;
+
+    fn key_is_pressed(&self) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+
+    fn read_key<D: Delay>(&mut self, _delay: &mut D) -> Result<Option<u8>, Self::Error> {
+        Ok(None)
+    }
+}
+
+impl Buzzer for Null {
+    type Error = hal::Error;
+
+    fn on(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn off(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl Rng for Null {
+    type Error = hal::Error;
+
+    fn random(&mut self) -> Result<u8, Self::Error> {
+        Ok(4)
+    }
+}
+
+impl Delay for Null {
+    type Error = hal::Error;
+
+    fn delay_us(&mut self, _us: u32) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// A scripted GDB client.
+struct Client(TcpStream);
+
+impl Client {
+    fn send(&mut self, data: &str) -> String {
+        self.write(data);
+        self.recv()
+    }
+
+    fn write(&mut self, data: &str) {
+        let sum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.0, "${data}#{sum:02x}").unwrap();
+
+        let mut ack = [0];
+        self.0.read_exact(&mut ack).unwrap();
+        assert_eq!(ack[0], b'+');
+    }
+
+    fn recv(&mut self) -> String {
+        let mut reply = Vec::new();
+        let mut byte = [0];
+        loop {
+            self.0.read_exact(&mut byte).unwrap();
+            match byte[0] {
+                b'$' => reply.clear(),
+                b'#' => break,
+                byte => reply.push(byte),
+            }
+        }
+
+        let mut sum = [0; 2];
+        self.0.read_exact(&mut sum).unwrap();
+        self.0.write_all(b"+").unwrap();
+
+        String::from_utf8(reply).unwrap()
+    }
+}
+
+fn connect(program: &'static [u16], limit: Option<usize>) -> (Client, thread::JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let mut chip = Chip8::new(Null, Null, Null, Null, Null);
+        chip.state_mut().ram.load(0x200, program).unwrap();
+        chip.init().unwrap();
+
+        let mut debugger = Debugger::new(chip);
+        debugger.set_limit(limit);
+
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nodelay(true).unwrap();
+        Server::new(stream).serve(&mut debugger).unwrap();
+    });
+
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_nodelay(true).unwrap();
+    (Client(stream), server)
+}
+
+#[test]
+fn registers_and_memory() {
+    let (mut gdb, server) = connect(&[0x6A12, 0xA345], Some(1000));
+
+    assert!(gdb.send("qSupported:multiprocess+").contains("PacketSize"));
+    assert_eq!(gdb.send("?"), "S05");
+
+    let regs = gdb.send("g");
+    assert_eq!(regs.len(), 16 * 2 + 4 + 4 + 2 + 2 + 2);
+    assert_eq!(&regs[32..40], "00000002");
+
+    assert_eq!(gdb.send("s"), "S05");
+    assert_eq!(gdb.send("pa"), "12");
+    assert_eq!(gdb.send("p11"), "0202");
+
+    assert_eq!(gdb.send("P3=7f"), "OK");
+    assert_eq!(gdb.send("p3"), "7f");
+    assert_eq!(gdb.send("P10=3412"), "OK");
+    assert_eq!(gdb.send("p10"), "3412");
+    assert_eq!(gdb.send("P3=7f7f"), "E01");
+
+    assert_eq!(gdb.send("m200,4"), "6a12a345");
+    assert_eq!(gdb.send("M300,3:010203"), "OK");
+    assert_eq!(gdb.send("m300,3"), "010203");
+    assert_eq!(gdb.send("mfff,2"), "E01");
+
+    assert!(gdb
+        .send("qXfer:features:read:target.xml:0,fff")
+        .starts_with("l<?xml"));
+
+    assert_eq!(gdb.send("D"), "OK");
+    server.join().unwrap();
+}
+
+#[test]
+fn breakpoints() {
+    // 0x200: LD V0, 0; 0x202: ADD V0, 1; 0x204: LD I, 0x300; 0x206: LD [I], V0; 0x208: JP 0x202
+    let (mut gdb, server) = connect(&[0x6000, 0x7001, 0xA300, 0xF055, 0x1202], Some(1000));
+
+    assert_eq!(gdb.send("Z0,204,2"), "OK");
+    assert_eq!(gdb.send("c"), "S05");
+    assert_eq!(gdb.send("p11"), "0402");
+    assert_eq!(gdb.send("p0"), "01");
+
+    assert_eq!(gdb.send("c"), "S05");
+    assert_eq!(gdb.send("p0"), "02");
+
+    assert_eq!(gdb.send("z0,204,2"), "OK");
+    assert_eq!(gdb.send("z0,204,2"), "E01");
+
+    assert_eq!(gdb.send("Z2,300,1"), "OK");
+    assert_eq!(gdb.send("c"), "T05watch:300;");
+    assert_eq!(gdb.send("p11"), "0802");
+
+    assert_eq!(gdb.send("z2,300,1"), "OK");
+    assert_eq!(gdb.send("P11=0100"), "OK");
+    assert_eq!(gdb.send("s"), "S04");
+    assert_eq!(gdb.send("?"), "S04");
+
+    assert_eq!(gdb.send("vMustReplyEmpty"), "");
+    gdb.0.write_all(b"$k#6b").unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn interrupt() {
+    // 0x200: ADD V0, 1; 0x202: JP 0x200
+    let (mut gdb, server) = connect(&[0x7001, 0x1200], None);
+
+    gdb.write("c");
+    thread::sleep(Duration::from_millis(20));
+    gdb.0.write_all(&[0x03]).unwrap();
+    assert_eq!(gdb.recv(), "S02");
+    assert_eq!(gdb.send("?"), "S02");
+
+    assert_eq!(gdb.send("D"), "OK");
+    server.join().unwrap();
+}
+
+/// A pair of pipes, like the stdin and stdout of a child process.
+struct Pipe {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn pipe() {
+    // 0x200: ADD V0, 1; 0x202: JP 0x200
+    let mut chip = Chip8::new(Null, Null, Null, Null, Null);
+    chip.state_mut()
+        .ram
+        .load(0x200, &[0x7001u16, 0x1200])
+        .unwrap();
+    chip.init().unwrap();
+
+    let mut debugger = Debugger::new(chip);
+    debugger.set_limit(Some(1000));
+
+    let pipe = Pipe {
+        input: Cursor::new(b"$c#63+$p0#a0+$k#6b".to_vec()),
+        output: Vec::new(),
+    };
+    let mut server = Server::new(pipe);
+    server.serve_blocking(&mut debugger).unwrap();
+
+    let output = server.into_inner().output;
+    assert_eq!(String::from_utf8(output).unwrap(), "+$S05#b8+$f4#9a+");
+}